@@ -1,52 +1,59 @@
 use koopa::ir::BinaryOp;
 
-#[derive(Debug)]
+/* Byte range in the source, (0, 0) for nodes the compiler made up */
+pub type Span = (usize, usize);
+
+#[derive(Debug, Clone)]
 pub struct Program {
     pub list: Vec<Result<FuncDef, Decl>>
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FuncDef {
     pub func_type: FuncType,
     pub id: String,
     pub func_param_list: Vec<FuncParam>,
     pub block: Block,
+    // Function name
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FuncType {
     Int,
     Void,
 }
 
-#[derive(Debug)]
-pub struct FuncParam(pub String, pub Vec<Exp>);
+#[derive(Debug, Clone)]
+pub struct FuncParam(pub String, pub Vec<Exp>, pub Span);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Block {
     pub block_item_list: Vec<BlockItem>,
+    // Braces included
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BlockItem {
-    Decl(Decl),
-    Stmt(Stmt),
+    Decl(Decl, Span),
+    Stmt(Stmt, Span),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Stmt {
     Assign(LVal, Exp),
     Exp(Exp),
     Block(Block),
-    Ret(Option<Exp>),
+    Ret(Option<Exp>, Span),
     If(If),
     While(Exp, Box<Stmt>),
-    Continue,
-    Break,
+    Continue(Span),
+    Break(Span),
     Blank,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct If {
     pub exp: Exp,
     pub then_stmt: Box<Stmt>,
@@ -63,52 +70,57 @@ impl If {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Decl {
     Const(ConstDecl),
     Var(VarDecl),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VarDecl {
     pub btype: BType,
     pub var_def_list: Vec<VarDef>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VarDef {
     pub id: String,
     pub is_array: Vec<Exp>,
     pub init_val: Option<InitVal>,
+    // Variable name
+    pub span: Span,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InitVal {
     Exp(Exp),
     List(Vec<Box<InitVal>>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConstDecl {
     pub btype: BType,
     pub const_def_list: Vec<ConstDef>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BType {
     Int,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConstDef {
     pub id: String,
     pub is_array: Vec<Exp>,
     pub const_init_val: InitVal,
+    // Constant name
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub struct Exp {
     pub core: Box<ExpCore>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -120,20 +132,28 @@ pub enum ExpCore {
 }
 
 impl Exp {
+    /* Spans both operands, or just `e1` when `e0` was made up (as for unary operators) */
     pub fn binary(e0: Exp, op: BinaryOp, e1: Exp) -> Exp {
-        Exp { core: Box::new(ExpCore::Binary(e0, op, e1)), }
+        let span = if e0.span == (0, 0) { e1.span } else { (e0.span.0, e1.span.1) };
+        Exp { core: Box::new(ExpCore::Binary(e0, op, e1)), span }
     }
 
     pub fn single(num: i32) -> Exp {
-        Exp { core: Box::new(ExpCore::Single(num)), }
+        Exp { core: Box::new(ExpCore::Single(num)), span: (0, 0) }
     }
 
     pub fn ident(id: LVal) -> Exp {
-        Exp { core: Box::new(ExpCore::Ident(id)), }
+        let span = id.span;
+        Exp { core: Box::new(ExpCore::Ident(id)), span }
     }
 
     pub fn call(id: String, func_real_list: Vec<Exp>) -> Exp {
-        Exp { core: Box::new(ExpCore::Call(id, func_real_list)), }
+        Exp { core: Box::new(ExpCore::Call(id, func_real_list)), span: (0, 0) }
+    }
+
+    pub fn with_span(mut self, start: usize, end: usize) -> Exp {
+        self.span = (start, end);
+        self
     }
 }
 
@@ -141,6 +161,8 @@ impl Exp {
 pub struct LVal {
     pub id: String,
     pub is_array: Vec<Exp>,
+    // Variable name
+    pub span: Span,
 }
 
 /*
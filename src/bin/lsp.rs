@@ -0,0 +1,103 @@
+/* SysY language server over stdio
+ * Messages are json framed by a `Content-Length` header, see `compiler::lsp`
+ * for what is answered.
+ */
+
+/* Uses */
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+use compiler::json::Json;
+use compiler::lsp::Server;
+use compiler::trace;
+
+/* Largest message body accepted, far more than any source file needs */
+const MESSAGE_MAX: usize = 16 << 20;
+
+/* Main */
+fn main() {
+    if let Err(message) = trace::configure_from_env() {
+        eprintln!("error: {} in ${}", message, trace::ENV_VAR);
+        exit(2);
+    }
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+    let mut server = Server::new();
+    loop {
+        let body = match read_message(&mut input) {
+            Ok(Some(body)) => body,
+            // The client went away without `exit`
+            Ok(None) => exit(1),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                exit(1);
+            }
+        };
+        let replies = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(message) => vec![Json::object(vec![
+                ("jsonrpc", Json::str("2.0")),
+                ("id", Json::Null),
+                ("error", Json::object(vec![("code", Json::Int(-32700)), ("message", Json::Str(message))])),
+            ])],
+        };
+        for reply in replies {
+            let text = reply.to_string();
+            let written = write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text).and_then(|_| output.flush());
+            if let Err(err) = written {
+                eprintln!("error: {}", err);
+                exit(1);
+            }
+        }
+        if let Some(code) = server.exit {
+            exit(code);
+        }
+    }
+}
+
+/* Body of the next message, None at the end of input */
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "message without Content-Length"))?;
+    if length > MESSAGE_MAX {
+        let message = "message of ".to_string() + &length.to_string() + " bytes exceeds the limit of " + &MESSAGE_MAX.to_string();
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body).map(Some).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_framed_messages() {
+        let mut input = io::Cursor::new("Content-Length: 2\r\n\r\n{}Content-Length: 4\r\n\r\nnull".as_bytes());
+        assert_eq!(read_message(&mut input).unwrap(), Some("{}".to_string()));
+        assert_eq!(read_message(&mut input).unwrap(), Some("null".to_string()));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        let header = "Content-Length: ".to_string() + &(MESSAGE_MAX + 1).to_string() + "\r\n\r\n";
+        let err = read_message(&mut io::Cursor::new(header.as_bytes())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
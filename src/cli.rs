@@ -0,0 +1,296 @@
+/* Uses */
+use compiler::{Options, OptLevel, TargetOptions};
+use compiler::diag::WARNINGS;
+use compiler::opt::PASSES;
+
+/* What the driver writes */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Emit {
+    Ast,
+    Json,
+    Koopa,
+    Dot,
+    Asm,
+    Obj,
+}
+
+impl Emit {
+    fn new(name: &str) -> Result<Emit, String> {
+        match name {
+            "ast" => Ok(Emit::Ast),
+            "json" => Ok(Emit::Json),
+            "koopa" => Ok(Emit::Koopa),
+            "dot" => Ok(Emit::Dot),
+            "asm" => Ok(Emit::Asm),
+            "obj" => Ok(Emit::Obj),
+            _ => Err("unknown emit kind `".to_string() + name + "`, expected ast, json, koopa, dot, asm or obj"),
+        }
+    }
+
+    /* Extension of the default output file */
+    pub fn extension(self) -> &'static str {
+        match self {
+            Emit::Ast => "ast",
+            Emit::Json => "json",
+            Emit::Koopa => "koopa",
+            Emit::Dot => "dot",
+            Emit::Asm => "s",
+            Emit::Obj => "o",
+        }
+    }
+}
+
+/* A compile request from the command line */
+pub struct Args {
+    pub emit: Emit,
+    // None reads stdin
+    pub input: Option<String>,
+    // None derives a name from the input, "-" is stdout
+    pub output: Option<String>,
+    pub options: Options,
+    // --trace spec, applied after $SYSY_TRACE
+    pub trace: Option<String>,
+}
+
+pub enum Command {
+    Compile(Box<Args>),
+    Help,
+    Version,
+}
+
+pub const USAGE: &str = "\
+Usage: compiler [options] [input]
+
+Compiles a SysY source file, or stdin when input is missing or `-`.
+
+Options:
+  --emit=<kind>      output ast, json (the ast as json), koopa, dot (control
+                     flow graph of each function), asm (default) or obj
+  --dot-dom          draw the dominator tree over --emit=dot graphs
+  --dot-loops        highlight loop headers and back edges in --emit=dot
+  -o <file>          write output to <file>, `-` for stdout
+  -O<level>          optimization level 0-3, -O alone is -O2
+  --target=<triple>  target triple, only riscv32 targets are supported
+  -W<name>           enable a warning, -Wno-<name> disables it
+  -Wall              enable all warnings
+  -Werror[=<name>]   treat all warnings, or one kind, as errors
+  -w                 disable all warnings
+  --dump-after=<stage>
+                     write the program after <stage> to a numbered file
+                     next to the input, like foo.02.koopa; stages are parse,
+                     lower, codegen and the optimization passes, separated
+                     by commas
+  --dump-all         write the program after every stage
+  --trace=<spec>     trace compiler internals to stderr, spec is a comma
+                     separated list of <category>[:<level>], categories are
+                     parse, lower, init, opt, frame, isel, lsp or all,
+                     levels are info, debug, trace (default) or off;
+                     $SYSY_TRACE takes the same spec
+  -h, --help         print this help
+  -V, --version      print the version
+
+Compatible forms:
+  compiler -koopa <input> -o <output>   same as --emit=koopa -O0
+  compiler -riscv <input> -o <output>   same as --emit=asm -O0
+  compiler -perf <input> -o <output>    same as --emit=asm -O3
+";
+
+/* Text for --help, with the warnings that -W accepts */
+pub fn usage() -> String {
+    let mut text = USAGE.to_string();
+    if !WARNINGS.is_empty() {
+        text += "\nWarnings:\n";
+        for (name, on, description) in WARNINGS {
+            text += &("  -W".to_string() + name + &" ".repeat(24usize.saturating_sub(name.len() + 3)) + description);
+            text += if *on { " (on by default)\n" } else { "\n" };
+        }
+    }
+    text
+}
+
+/* Value of `--name=value` or `--name value`, None when `arg` is another option */
+fn option_value(arg: &str, name: &str, args: &mut dyn Iterator<Item = String>) -> Result<Option<String>, String> {
+    if arg == name {
+        match args.next() {
+            Some(v) => Ok(Some(v)),
+            None => Err("missing value for `".to_string() + name + "`"),
+        }
+    }else {
+        Ok(arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')).map(|v| v.to_string()))
+    }
+}
+
+/* Parse the arguments after the program name */
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let mut emit = Emit::Asm;
+    let mut input = None;
+    let mut output = None;
+    let mut options = Options::default();
+    let mut trace = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            // Course test scripts
+            "-koopa" => {
+                emit = Emit::Koopa;
+                options.opt.level = OptLevel::O0;
+            }
+            "-riscv" => {
+                emit = Emit::Asm;
+                options.opt.level = OptLevel::O0;
+            }
+            "-perf" => {
+                emit = Emit::Asm;
+                options.opt.level = OptLevel::O3;
+            }
+            "-o" => match args.next() {
+                Some(path) => output = Some(path),
+                None => return Err("missing file name after `-o`".to_string()),
+            },
+            "-O" => options.opt.level = OptLevel::O2,
+            "-O0" => options.opt.level = OptLevel::O0,
+            "-O1" => options.opt.level = OptLevel::O1,
+            "-O2" => options.opt.level = OptLevel::O2,
+            "-O3" => options.opt.level = OptLevel::O3,
+            "-w" => options.warnings.silent = true,
+            "--dump-all" => options.dump.all = true,
+            "--dot-dom" => options.dot.dom = true,
+            "--dot-loops" => options.dot.loops = true,
+            "-" => {
+                if input.is_some() {
+                    return Err("more than one input file".to_string());
+                }
+                input = Some("-".to_string());
+            }
+            _ => {
+                if let Some(kind) = option_value(&arg, "--emit", &mut args)? {
+                    emit = Emit::new(&kind)?;
+                }else if let Some(triple) = option_value(&arg, "--target", &mut args)? {
+                    options.target = match TargetOptions::new(&triple) {
+                        Some(target) => target,
+                        None => return Err("unsupported target `".to_string() + &triple + "`, only riscv32 targets are supported"),
+                    };
+                }else if let Some(stages) = option_value(&arg, "--dump-after", &mut args)? {
+                    for stage in stages.split(',') {
+                        if !compiler::is_stage(stage) {
+                            return Err("unknown stage `".to_string() + stage + "`, expected parse, lower, codegen or one of " + &PASSES.join(", "));
+                        }
+                        options.dump.after.insert(stage.to_string());
+                    }
+                }else if let Some(spec) = option_value(&arg, "--trace", &mut args)? {
+                    trace = Some(spec);
+                }else if let Some(flag) = arg.strip_prefix("-W") {
+                    options.warnings.set(flag)?;
+                }else if arg.starts_with("-O") {
+                    return Err("unknown optimization level `".to_string() + &arg + "`, expected -O0 to -O3");
+                }else if arg.starts_with('-') {
+                    return Err("unknown option `".to_string() + &arg + "`");
+                }else if input.is_some() {
+                    return Err("more than one input file".to_string());
+                }else {
+                    input = Some(arg);
+                }
+            }
+        }
+    }
+
+    let input = input.filter(|path| path != "-");
+    Ok(Command::Compile(Box::new(Args { emit, input, output, options, trace })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(list: &[&str]) -> Result<Args, String> {
+        match parse_args(list.iter().map(|arg| arg.to_string()))? {
+            Command::Compile(args) => Ok(*args),
+            Command::Help => Err("help".to_string()),
+            Command::Version => Err("version".to_string()),
+        }
+    }
+
+    fn error(list: &[&str]) -> String {
+        match parse(list) {
+            Ok(_) => panic!("{:?} parsed", list),
+            Err(message) => message,
+        }
+    }
+
+    #[test]
+    fn emit_kinds_and_output() {
+        let args = parse(&["a.sy"]).unwrap();
+        assert_eq!((args.emit, args.input.as_deref(), args.output), (Emit::Asm, Some("a.sy"), None));
+        assert_eq!(parse(&["--emit=koopa", "a.sy"]).unwrap().emit, Emit::Koopa);
+        assert_eq!(parse(&["--emit", "dot", "a.sy"]).unwrap().emit, Emit::Dot);
+        assert_eq!(parse(&["a.sy", "-o", "-"]).unwrap().output.as_deref(), Some("-"));
+        assert_eq!(error(&["--emit=exe"]), "unknown emit kind `exe`, expected ast, json, koopa, dot, asm or obj");
+        assert_eq!(error(&["--emit"]), "missing value for `--emit`");
+        assert_eq!(error(&["a.sy", "-o"]), "missing file name after `-o`");
+        // -o takes its file as the next argument only
+        assert_eq!(error(&["a.sy", "-oa.s"]), "unknown option `-oa.s`");
+        assert_eq!(error(&["-opt", "a.sy"]), "unknown option `-opt`");
+        assert_eq!(error(&["--bogus"]), "unknown option `--bogus`");
+    }
+
+    #[test]
+    fn optimization_levels() {
+        assert_eq!(parse(&["a.sy"]).unwrap().options.opt.level, OptLevel::O0);
+        assert_eq!(parse(&["-O", "a.sy"]).unwrap().options.opt.level, OptLevel::O2);
+        assert_eq!(parse(&["-O3", "-O1", "a.sy"]).unwrap().options.opt.level, OptLevel::O1);
+        assert_eq!(error(&["-O4"]), "unknown optimization level `-O4`, expected -O0 to -O3");
+    }
+
+    #[test]
+    fn warning_flags() {
+        let warnings = parse(&["-Wall", "-Wno-dead-store", "-Werror=unused-variable", "a.sy"]).unwrap().options.warnings;
+        assert!(warnings.all && !warnings.errors);
+        assert!(warnings.disabled.contains("dead-store") && !warnings.is_enabled("dead-store"));
+        assert!(warnings.as_errors.contains("unused-variable") && warnings.is_enabled("unused-variable"));
+        assert!(parse(&["-Werror", "a.sy"]).unwrap().options.warnings.errors);
+        assert!(parse(&["-w", "a.sy"]).unwrap().options.warnings.silent);
+        assert_eq!(error(&["-Wfoo"]), "unknown warning `foo`");
+        assert_eq!(error(&["-Werror=foo"]), "unknown warning `foo`");
+    }
+
+    #[test]
+    fn dump_stages() {
+        let dump = parse(&["--dump-after=lower,gvn", "--dump-after", "codegen", "a.sy"]).unwrap().options.dump;
+        let mut stages: Vec<&str> = dump.after.iter().map(String::as_str).collect();
+        stages.sort();
+        assert_eq!(stages, vec!["codegen", "gvn", "lower"]);
+        assert!(!dump.all);
+        assert!(parse(&["--dump-all", "a.sy"]).unwrap().options.dump.all);
+        assert!(error(&["--dump-after=link"]).starts_with("unknown stage `link`"));
+    }
+
+    #[test]
+    fn inputs() {
+        assert_eq!(parse(&[]).unwrap().input, None);
+        assert_eq!(parse(&["-"]).unwrap().input, None);
+        assert_eq!(error(&["a.sy", "b.sy"]), "more than one input file");
+        assert_eq!(error(&["-", "a.sy"]), "more than one input file");
+        assert_eq!(error(&["-h", "a.sy"]), "help");
+        assert_eq!(error(&["--version"]), "version");
+    }
+
+    #[test]
+    fn course_script_forms() {
+        for (flag, emit, level) in [("-koopa", Emit::Koopa, OptLevel::O0), ("-riscv", Emit::Asm, OptLevel::O0), ("-perf", Emit::Asm, OptLevel::O3)] {
+            let args = parse(&[flag, "in.sy", "-o", "out"]).unwrap();
+            assert_eq!((args.emit, args.options.opt.level), (emit, level));
+            assert_eq!((args.input.as_deref(), args.output.as_deref()), (Some("in.sy"), Some("out")));
+        }
+    }
+
+    #[test]
+    fn targets_and_trace() {
+        let args = parse(&["--target=riscv32-unknown-linux-gnu", "--trace=opt:debug", "a.sy"]).unwrap();
+        assert_eq!(args.options.target.triple, "riscv32-unknown-linux-gnu");
+        assert_eq!(args.trace.as_deref(), Some("opt:debug"));
+        assert_eq!(error(&["--target=x86_64"]), "unsupported target `x86_64`, only riscv32 targets are supported");
+    }
+}
@@ -0,0 +1,196 @@
+/* Uses */
+use std::collections::HashSet;
+use std::fmt;
+
+/* How serious a diagnostic is */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/* Message about the source, with the byte range it refers to when known */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<(usize, usize)>,
+    // Name of the warning kind, used by -W flags
+    pub code: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Error, message, span: None, code: None }
+    }
+
+    pub fn warning(code: &'static str, message: String) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, message, span: None, code: Some(code) }
+    }
+
+    pub fn with_span(mut self, start: usize, end: usize) -> Diagnostic {
+        self.span = Some((start, end));
+        self
+    }
+
+    /* Human readable form, pointing at line and column in `source` */
+    pub fn render(&self, source: &str) -> String {
+        let mut text = self.to_string();
+        if let Some((start, _)) = self.span {
+            let (line, column) = line_column(source, start);
+            text += &("\n  --> ".to_string() + &line.to_string() + ":" + &column.to_string());
+            if let Some(code) = source.lines().nth(line - 1) {
+                text += &("\n   | ".to_string() + code);
+                text += &("\n   | ".to_string() + &" ".repeat(column - 1) + "^");
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }?;
+        match self.code {
+            Some(code) => write!(f, " [-W{}]", code),
+            None => Ok(()),
+        }
+    }
+}
+
+/* 1-based line and column of a byte offset */
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, column)
+}
+
+/* Diagnostics collected while compiling */
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub list: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics { list: vec![] }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.list.push(diagnostic);
+    }
+
+    pub fn extend(&mut self, other: Diagnostics) {
+        self.list.extend(other.list);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.list.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn render(&self, source: &str) -> String {
+        self.list.iter().map(|d| d.render(source) + "\n").collect()
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Diagnostics {
+        Diagnostics { list: vec![diagnostic] }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for d in &self.list {
+            writeln!(f, "{}", d)?;
+        }
+        Ok(())
+    }
+}
+
+/* Known warnings: name, enabled without -Wall, description */
+pub const WARNINGS: &[(&str, bool, &str)] = &[
+    ("return-type", true, "non-void function may end without returning a value"),
+    ("infinite-loop", true, "loop with a constant true condition and no break or return"),
+    ("unreachable-code", false, "statements that can never be executed"),
+    ("constant-condition", false, "if or while condition that is always true or false"),
+    ("uninitialized", true, "local variable that may be read before it is assigned"),
+    ("unused-variable", false, "local variable or constant that is never used"),
+    ("unused-but-set", false, "local variable that is assigned but never read"),
+    ("dead-store", false, "value assigned to a variable that is never read afterwards"),
+    ("unused-parameter", false, "function parameter that is never read"),
+];
+
+/* Which warnings are reported, and which of them are errors */
+#[derive(Clone, Debug, Default)]
+pub struct WarningOptions {
+    // -w: report no warnings at all
+    pub silent: bool,
+    // -Wall: enable every warning
+    pub all: bool,
+    // -Werror: every reported warning is an error
+    pub errors: bool,
+    // -W<name> / -Wno-<name> / -Werror=<name>
+    pub enabled: HashSet<String>,
+    pub disabled: HashSet<String>,
+    pub as_errors: HashSet<String>,
+}
+
+impl WarningOptions {
+    /* Apply one -W flag (without the leading -W), an error for unknown warnings */
+    pub fn set(&mut self, flag: &str) -> Result<(), String> {
+        let known = |name: &str| {
+            if WARNINGS.iter().any(|w| w.0 == name) {
+                Ok(name.to_string())
+            }else {
+                Err("unknown warning `".to_string() + name + "`")
+            }
+        };
+        if flag == "all" {
+            self.all = true;
+        }else if flag == "error" {
+            self.errors = true;
+        }else if let Some(name) = flag.strip_prefix("error=") {
+            let name = known(name)?;
+            self.enabled.insert(name.clone());
+            self.as_errors.insert(name);
+        }else if let Some(name) = flag.strip_prefix("no-") {
+            let name = known(name)?;
+            self.enabled.remove(&name);
+            self.disabled.insert(name);
+        }else {
+            let name = known(flag)?;
+            self.disabled.remove(&name);
+            self.enabled.insert(name);
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, code: &str) -> bool {
+        if self.silent || self.disabled.contains(code) {
+            return false;
+        }
+        self.all || self.enabled.contains(code) || WARNINGS.iter().any(|w| w.0 == code && w.1)
+    }
+
+    /* Drop a disabled warning, or promote it to an error */
+    pub fn apply(&self, mut diagnostic: Diagnostic) -> Option<Diagnostic> {
+        if let (Severity::Warning, Some(code)) = (diagnostic.severity, diagnostic.code) {
+            if !self.is_enabled(code) {
+                return None;
+            }
+            if self.errors || self.as_errors.contains(code) {
+                diagnostic.severity = Severity::Error;
+            }
+        }
+        Some(diagnostic)
+    }
+}
@@ -0,0 +1,275 @@
+/* Uses */
+use std::collections::HashMap;
+use koopa::back::NameManager;
+use koopa::ir::*;
+use crate::opt::dom::DomTree;
+use crate::opt::loops::LoopInfo;
+
+/* What is drawn on top of the control-flow graph */
+#[derive(Clone, Debug, Default)]
+pub struct DotOptions {
+    // Dashed edges from each block to its immediate dominator's block
+    pub dom: bool,
+    // Filled loop headers and bold back edges
+    pub loops: bool,
+}
+
+/* One DOT digraph per function with a body, blocks labelled with their Koopa text */
+pub fn gen_dot(program: &Program, options: &DotOptions) -> String {
+    // Names are handed out in the order the Koopa printer asks for them,
+    // so labels read the same as --emit=koopa
+    let mut names = NameManager::new();
+    for &global in program.inst_layout() {
+        names.value_name(&program.borrow_value(global));
+    }
+    let mut text = String::new();
+    for &func in program.func_layout() {
+        let func_data = program.func(func);
+        names.func_name(func_data);
+        if func_data.layout().entry_bb().is_none() {
+            continue;
+        }
+        names.enter_func_scope();
+        let blocks = block_texts(program, func_data, &mut names);
+        names.exit_func_scope();
+        text += &gen_func(func_data, &blocks, options);
+    }
+    text
+}
+
+fn gen_func(func_data: &FunctionData, blocks: &[Vec<String>], options: &DotOptions) -> String {
+    let bbs: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+    let id: HashMap<BasicBlock, String> = bbs.iter().enumerate().map(|(i, &bb)| (bb, "bb".to_string() + &i.to_string())).collect();
+    let dom = DomTree::new(func_data);
+    let loops = LoopInfo::new(func_data, &dom);
+
+    let mut text = "digraph ".to_string() + &quote(&func_data.name()[1..]) + " {\n";
+    text += "  node [shape=box, fontname=\"monospace\"];\n";
+    text += &("  label=".to_string() + &quote(func_data.name()) + ";\n");
+
+    // Blocks
+    for (i, &bb) in bbs.iter().enumerate() {
+        let lines = blocks.get(i).cloned().unwrap_or_default();
+        let label: String = lines.iter().map(|line| escape(line) + "\\l").collect();
+        text += &("  ".to_string() + &id[&bb] + " [label=\"" + &label + "\"");
+        if options.loops && loops.loops.iter().any(|l| l.header == bb) {
+            text += ", style=filled, fillcolor=\"lightyellow\"";
+        }
+        if !dom.is_reachable(bb) {
+            text += ", color=\"gray\"";
+        }
+        text += "];\n";
+    }
+
+    // Control flow
+    for &bb in &bbs {
+        let term = match func_data.layout().bbs().node(&bb).and_then(|node| node.insts().back_key()) {
+            Some(&term) => term,
+            None => continue,
+        };
+        let edges = match func_data.dfg().value(term).kind() {
+            ValueKind::Branch(br) => vec![(br.true_bb(), "T"), (br.false_bb(), "F")],
+            ValueKind::Jump(jump) => vec![(jump.target(), "")],
+            _ => vec![],
+        };
+        for (target, label) in edges {
+            let back = options.loops && loops.loops.iter().any(|l| l.header == target && l.latches.contains(&bb));
+            let mut attrs = vec![];
+            if !label.is_empty() {
+                attrs.push("label=\"".to_string() + label + "\"");
+            }
+            if back {
+                attrs.push("style=bold, color=\"red\"".to_string());
+            }
+            text += &("  ".to_string() + &id[&bb] + " -> " + &id[&target]);
+            if !attrs.is_empty() {
+                text += &(" [".to_string() + &attrs.join(", ") + "]");
+            }
+            text += ";\n";
+        }
+    }
+
+    // Dominator tree, kept out of the layout
+    if options.dom {
+        for &bb in &dom.order {
+            if let Some(idom) = dom.idom(bb) {
+                text += &("  ".to_string() + &id[&idom] + " -> " + &id[&bb] + " [style=dashed, color=\"blue\", constraint=false];\n");
+            }
+        }
+    }
+    text + "}\n"
+}
+
+/* Lines of each block in Koopa text, in layout order */
+fn block_texts(program: &Program, func_data: &FunctionData, names: &mut NameManager) -> Vec<Vec<String>> {
+    for &param in func_data.params() {
+        names.value_name(func_data.dfg().value(param));
+    }
+    let mut blocks = vec![];
+    for (&bb, node) in func_data.layout().bbs() {
+        let bb_data = func_data.dfg().bb(bb);
+        let mut head = names.bb_name(bb_data).to_string();
+        if !bb_data.params().is_empty() {
+            let params: Vec<String> = bb_data.params().iter().map(|&param| {
+                let data = func_data.dfg().value(param);
+                names.value_name(data).to_string() + ": " + &data.ty().to_string()
+            }).collect();
+            head += &("(".to_string() + &params.join(", ") + ")");
+        }
+        let mut lines = vec![head + ":"];
+        for &inst in node.insts().keys() {
+            lines.push("  ".to_string() + &inst_text(program, func_data, names, inst));
+        }
+        blocks.push(lines);
+    }
+    blocks
+}
+
+fn inst_text(program: &Program, func_data: &FunctionData, names: &mut NameManager, inst: Value) -> String {
+    let data = func_data.dfg().value(inst);
+    let text = if data.ty().is_unit() { String::new() } else { names.value_name(data).to_string() + " = " };
+    let mut operands = |values: &[Value]| -> String {
+        let list: Vec<String> = values.iter().map(|&value| operand_text(program, func_data, names, value)).collect();
+        list.join(", ")
+    };
+    let body = match data.kind() {
+        ValueKind::Alloc(_) => match data.ty().kind() {
+            TypeKind::Pointer(base) => "alloc ".to_string() + &base.to_string(),
+            _ => unreachable!(),
+        },
+        ValueKind::Load(load) => "load ".to_string() + &operands(&[load.src()]),
+        ValueKind::Store(store) => "store ".to_string() + &operands(&[store.value(), store.dest()]),
+        ValueKind::GetPtr(gp) => "getptr ".to_string() + &operands(&[gp.src(), gp.index()]),
+        ValueKind::GetElemPtr(gep) => "getelemptr ".to_string() + &operands(&[gep.src(), gep.index()]),
+        ValueKind::Binary(bin) => bin.op().to_string() + " " + &operands(&[bin.lhs(), bin.rhs()]),
+        ValueKind::Return(ret) => match ret.value() {
+            Some(value) => "ret ".to_string() + &operands(&[value]),
+            None => "ret".to_string(),
+        },
+        ValueKind::Branch(br) => {
+            let cond = operands(&[br.cond()]);
+            "br ".to_string() + &cond + ", " + &target_text(program, func_data, names, br.true_bb(), br.true_args()) + ", " + &target_text(program, func_data, names, br.false_bb(), br.false_args())
+        }
+        ValueKind::Jump(jump) => "jump ".to_string() + &target_text(program, func_data, names, jump.target(), jump.args()),
+        ValueKind::Call(call) => {
+            let args = operands(call.args());
+            "call ".to_string() + &names.func_name(program.func(call.callee())) + "(" + &args + ")"
+        }
+        kind => format!("{:?}", kind),
+    };
+    text + &body
+}
+
+/* `%bb` or `%bb(args)` */
+fn target_text(program: &Program, func_data: &FunctionData, names: &mut NameManager, bb: BasicBlock, args: &[Value]) -> String {
+    let mut text = names.bb_name(func_data.dfg().bb(bb)).to_string();
+    if !args.is_empty() {
+        let args: Vec<String> = args.iter().map(|&arg| operand_text(program, func_data, names, arg)).collect();
+        text += &("(".to_string() + &args.join(", ") + ")");
+    }
+    text
+}
+
+/* Constants are written out, everything else by name */
+fn operand_text(program: &Program, func_data: &FunctionData, names: &mut NameManager, value: Value) -> String {
+    if value.is_global() {
+        return names.value_name(&program.borrow_value(value)).to_string();
+    }
+    let data = func_data.dfg().value(value);
+    match data.kind() {
+        ValueKind::Integer(int) => int.value().to_string(),
+        ValueKind::ZeroInit(_) => "zeroinit".to_string(),
+        ValueKind::Undef(_) => "undef".to_string(),
+        ValueKind::Aggregate(agg) => {
+            let elems: Vec<String> = agg.elems().iter().map(|&elem| operand_text(program, func_data, names, elem)).collect();
+            "{".to_string() + &elems.join(", ") + "}"
+        }
+        _ => names.value_name(data).to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn quote(s: &str) -> String {
+    "\"".to_string() + &escape(s) + "\""
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::OptLevel;
+
+    const SOURCE: &str = "int g[4];\nint sum(int a[], int n) {\n  int s = 0, i = 0;\n  while (i < n) {\n    if (a[i] > 0 && i != 2) s = s + a[i];\n    i = i + 1;\n  }\n  return s;\n}\nint main() {\n  g[1] = getint();\n  return sum(g, 4);\n}\n";
+
+    fn program(level: OptLevel) -> Program {
+        let mut program = crate::lower(&crate::parse(SOURCE).unwrap()).ok().unwrap();
+        crate::optimize(&mut program, level);
+        program
+    }
+
+    /* Block labels of each digraph, as plain text */
+    fn labels(dot: &str) -> Vec<String> {
+        let mut funcs = vec![];
+        for line in dot.lines() {
+            if line.starts_with("digraph ") {
+                funcs.push(String::new());
+            }else if let (false, Some(start), Some(end)) = (line.contains(" -> "), line.find("[label=\""), line.find("\\l\"")) {
+                let label = &line[start + 8..end + 2];
+                let text = label.replace("\\l", "\n").replace("\\\"", "\"").replace("\\\\", "\\");
+                let func = funcs.last_mut().unwrap();
+                if !func.is_empty() {
+                    func.push('\n');
+                }
+                *func += &text;
+            }
+        }
+        funcs
+    }
+
+    #[test]
+    fn labels_match_the_koopa_text() {
+        for level in [OptLevel::O0, OptLevel::O3] {
+            let program = program(level);
+            let koopa = crate::emit_koopa(&program);
+            let bodies: Vec<String> = koopa.split("fun ").skip(1).map(|func| {
+                let body = &func[func.find("{\n").unwrap() + 2..func.rfind("}").unwrap()];
+                body.to_string()
+            }).collect();
+            assert_eq!(labels(&gen_dot(&program, &DotOptions::default())), bodies, "at {:?}", level);
+        }
+    }
+
+    #[test]
+    fn dominator_and_loop_edges() {
+        let program = program(OptLevel::O0);
+        let plain = gen_dot(&program, &DotOptions::default());
+        assert!(!plain.contains("style=dashed") && !plain.contains("lightyellow") && !plain.contains("bold"));
+
+        let dot = gen_dot(&program, &DotOptions { dom: true, loops: true });
+        let sum = &dot[..dot.find("digraph \"main\"").unwrap()];
+        let func_data = program.func(*program.func_layout().iter().find(|&&f| program.func(f).name() == "@sum").unwrap());
+        let dom = DomTree::new(func_data);
+        let loops = LoopInfo::new(func_data, &dom);
+        let bbs: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().collect();
+        let id = |bb: BasicBlock| "bb".to_string() + &bbs.iter().position(|&b| b == bb).unwrap().to_string();
+
+        // One dashed edge into every reachable block but the entry
+        let dashed: Vec<&str> = sum.lines().filter(|line| line.contains("style=dashed")).collect();
+        assert_eq!(dashed.len(), dom.order.len() - 1);
+        for &bb in &dom.order[1..] {
+            let edge = "  ".to_string() + &id(dom.idom(bb).unwrap()) + " -> " + &id(bb) + " [style=dashed";
+            assert!(dashed.iter().any(|line| line.starts_with(&edge)), "{}", edge);
+        }
+
+        // The while loop: its header is filled, its latch edge is bold
+        assert_eq!(loops.loops.len(), 1);
+        let lp = &loops.loops[0];
+        assert_eq!(sum.matches("lightyellow").count(), 1);
+        assert!(sum.lines().any(|line| line.starts_with(&("  ".to_string() + &id(lp.header) + " [label=")) && line.contains("lightyellow")));
+        let back: Vec<&str> = sum.lines().filter(|line| line.contains("style=bold")).collect();
+        assert_eq!(back.len(), lp.latches.len());
+        assert!(back[0].starts_with(&("  ".to_string() + &id(lp.latches[0]) + " -> " + &id(lp.header))));
+    }
+}
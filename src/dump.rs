@@ -1,53 +1,89 @@
 /* Uses */
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::iter::zip;
 use std::rc::Rc;
 use koopa::ir::*;
 use koopa::ir::builder::*;
 use koopa::back::KoopaGenerator;
 use crate::ast;
-
-struct SymbolTable {
-    table: HashMap<String, (Value, usize, bool)>,
-    old: Option<Rc<SymbolTable>>,
-    const_table: HashMap<String, i32>
+use crate::diag::Diagnostic;
+use crate::init::Init;
+use crate::symbol::{Symbol, SymbolKind, SymbolTable};
+use crate::ty::Ty;
+
+/* Lowering stops at the first error */
+type DumpResult<T> = Result<T, Diagnostic>;
+
+/* Symbol `lval` names, an error when it isn't declared */
+fn lookup(symbols: &mut SymbolTable, lval: &ast::LVal) -> DumpResult<Symbol> {
+    match symbols.resolve(&lval.id, lval.span) {
+        Some(id) if symbols.get(id).is_func() => Err(lval_error(lval, "`".to_string() + &lval.id + "` is a function")),
+        Some(id) => Ok(symbols.get(id).clone()),
+        None => Err(lval_error(lval, "undefined variable `".to_string() + &lval.id + "`")),
+    }
 }
 
-impl SymbolTable {
-    fn new() -> SymbolTable{
-        SymbolTable {table: HashMap::new(), old: None, const_table: HashMap::new()}
+/* Add a symbol to the current scope, names can't be declared twice in one scope */
+fn define(symbols: &mut SymbolTable, symbol: Symbol) -> DumpResult<()> {
+    crate::trace!(Lower, Debug, "`{}`: {}", symbol.name, symbol.ty);
+    let (span, name) = (symbol.span, symbol.name.clone());
+    match symbols.define(symbol) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Diagnostic::error("redefinition of `".to_string() + &name + "`").with_span(span.0, span.1)),
     }
+}
 
-    fn find(&self, s: &String) -> Option<(Value, usize, bool)> {
-        match self.table.get(s) {
-            Some(res) => Some(*res),
-            None => match &self.old {
-                Some(old_table) => old_table.find(s),
-                None => None,
-            }
-        }
+fn mismatch(expected: &Ty, found: &Ty, span: ast::Span) -> Diagnostic {
+    Diagnostic::error("mismatched types: expected `".to_string() + &expected.to_string() + "`, found `" + &found.to_string() + "`").with_span(span.0, span.1)
+}
+
+/* `n` followed by `noun`, plural unless n is 1 */
+fn count(n: usize, noun: &str) -> String {
+    n.to_string() + " " + noun + if n == 1 { "" } else { "s" }
+}
+
+/* Type of `lval` after its subscripts */
+fn lval_ty(lval: &ast::LVal, symbol: &Symbol) -> DumpResult<Ty> {
+    let mut ty = symbol.ty.clone();
+    for _ in &lval.is_array {
+        ty = match ty.index() {
+            Some(elem) => elem,
+            None if symbol.shape.is_empty() => return Err(lval_error(lval, "`".to_string() + &lval.id + "` is not an array")),
+            None => return Err(lval_error(lval, "too many subscripts for `".to_string() + &lval.id + "` of type `" + &symbol.ty.to_string() + "`")),
+        };
     }
+    Ok(ty)
+}
 
-    fn find_const(&self, s: &String) -> Option<i32> {
-        match self.const_table.get(s) {
-            Some(i) => Some(*i),
-            None => match &self.old {
-                Some(old_table) => old_table.find_const(s),
-                None => None,
-            }
-        }
+/* Symbol of a `const`, scalars get no storage */
+fn const_symbol(id: &str, init: Init<i32>, value: Option<Value>, span: ast::Span) -> Symbol {
+    let ty = Ty::array(Ty::Int, &init.dims).constant();
+    let symbol = Symbol::new(id, SymbolKind::Const(Rc::new(init)), ty, span);
+    match value {
+        Some(value) => symbol.with_value(value),
+        None => symbol,
     }
 }
 
 impl ast::Exp {
-    fn dump(self, mut bb: BasicBlock, func_data: &mut FunctionData, symbol_table: Rc<SymbolTable>, func_table: &HashMap<String, Function>) -> (Value, BasicBlock) {
+    /* Value of an expression that must be an `int` */
+    fn dump(self, bb: BasicBlock, func_data: &mut FunctionData, symbols: &mut SymbolTable) -> DumpResult<(Value, BasicBlock)> {
+        let span = self.span;
+        match self.dump_typed(bb, func_data, symbols)? {
+            (value, ty, bb) if ty.is_int() => Ok((value, bb)),
+            (_, ty, _) => Err(mismatch(&Ty::Int, &ty, span)),
+        }
+    }
+
+    /* Value of an expression of any type, arrays are pointers to their first element */
+    fn dump_typed(self, mut bb: BasicBlock, func_data: &mut FunctionData, symbols: &mut SymbolTable) -> DumpResult<(Value, Ty, BasicBlock)> {
         match *self.core {
             ast::ExpCore::Binary(e0, op, e1) => {
                 match op {
                     op @ (BinaryOp::And | BinaryOp::Or) => {
                         // parse e0
                         let zero = func_data.dfg_mut().new_value().integer(0);
-                        let (v0, new_bb) = e0.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
+                        let (v0, new_bb) = e0.dump(bb, func_data, symbols)?;
                         bb = new_bb;
 
                         // assign value
@@ -73,18 +109,20 @@ impl ast::Exp {
                         
 
                         // New then bb
-                        let then_bb = func_data.dfg_mut().new_bb().basic_block(None);
+                        let rhs_name = if op == BinaryOp::And { "%and_rhs" } else { "%or_rhs" };
+                        let then_bb = func_data.dfg_mut().new_bb().basic_block(Some(rhs_name.to_string()));
                         func_data.layout_mut().bbs_mut().push_key_back(then_bb).unwrap();
 
                         // parse e1
-                        let (v1, then_last_bb) = e1.dump(then_bb, func_data, Rc::clone(&symbol_table), func_table);
+                        let (v1, then_last_bb) = e1.dump(then_bb, func_data, symbols)?;
 
                         // assign value
                         let assign2 = func_data.dfg_mut().new_value().store(v1, value);
                         func_data.layout_mut().bb_mut(then_last_bb).insts_mut().push_key_back(assign2).unwrap();
                         
                         // New end bb
-                        let end_bb = func_data.dfg_mut().new_bb().basic_block(None);
+                        let end_name = if op == BinaryOp::And { "%and_end" } else { "%or_end" };
+                        let end_bb = func_data.dfg_mut().new_bb().basic_block(Some(end_name.to_string()));
                         func_data.layout_mut().bbs_mut().push_key_back(end_bb).unwrap();
                         
                         // br & jump
@@ -95,29 +133,35 @@ impl ast::Exp {
                         
                         let load = func_data.dfg_mut().new_value().load(value);
                         func_data.layout_mut().bb_mut(end_bb).insts_mut().push_key_back(load).unwrap();
-                        (load, end_bb)
+                        Ok((load, Ty::Int, end_bb))
                     }
                     op => {
-                        let (v0, new_bb) = e0.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
-                        let (v1, new_bb) = e1.dump(new_bb, func_data, Rc::clone(&symbol_table), func_table);
+                        let (v0, new_bb) = e0.dump(bb, func_data, symbols)?;
+                        let (v1, new_bb) = e1.dump(new_bb, func_data, symbols)?;
                         let v = func_data.dfg_mut().new_value().binary(op, v0, v1);
                         func_data.layout_mut().bb_mut(new_bb).insts_mut().push_key_back(v).unwrap();
-                        (v, new_bb)
+                        Ok((v, Ty::Int, new_bb))
                     }
                 }
             },
             ast::ExpCore::Single(i) => {
-                (func_data.dfg_mut().new_value().integer(i), bb)
+                Ok((func_data.dfg_mut().new_value().integer(i), Ty::Int, bb))
             },
             ast::ExpCore::Ident(lval) => {
-                let (v, dim, is_ptr) = symbol_table.find(&lval.id).unwrap();
+                let symbol = lookup(symbols, &lval)?;
+                let ty = lval_ty(&lval, &symbol)?;
+                let v = match symbol.value {
+                    Some(value) => value,
+                    None => return Ok((func_data.dfg_mut().new_value().integer(lval_const(&lval, &symbol)?), ty, bb)),
+                };
+                let is_ptr = symbol.is_pointer();
 
-                let is_partial = lval.is_array.len() < dim;
-                let to_get = is_ptr && lval.is_array.len() == 0;
+                let is_partial = lval.is_array.len() < symbol.shape.len();
+                let to_get = is_ptr && lval.is_array.is_empty();
 
-                println!("{}", is_ptr);
+                crate::trace!(Lower, Trace, "load `{}`, pointer: {}", lval.id, is_ptr);
                 // func_data.dfg_mut().values().get(&v).unwrap();
-                let (ptr, new_bb) = get_array_ptr(v, is_ptr, lval.is_array, bb, func_data, Rc::clone(&symbol_table), func_table);
+                let (ptr, new_bb) = get_array_ptr(v, is_ptr, lval.is_array, bb, func_data, symbols)?;
                 bb = new_bb;
 
                 // func_data.dfg_mut().values().get(&ptr).unwrap();
@@ -135,56 +179,73 @@ impl ast::Exp {
                         func_data.dfg_mut().new_value().get_elem_ptr(ptr, zero)
                     };
                     func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(value).unwrap();
-                    return (value, bb);
+                    return Ok((value, ty, bb));
                 }
                 
 
                 let load = func_data.dfg_mut().new_value().load(ptr);
                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(load).unwrap();
 
-                (load, bb)
+                Ok((load, ty, bb))
             },
             ast::ExpCore::Call(id, param_list) => {
-                let mut bb = bb;
+                // The callee is named at the start of the call
+                let span = (self.span.0, self.span.0 + id.len());
+                let (func, param_tys, ret_ty) = match symbols.resolve(&id, span).map(|symbol| symbols.get(symbol)) {
+                    Some(Symbol { kind: SymbolKind::Func(func), ty: Ty::Func(params, ret), .. }) => (*func, params.clone(), (**ret).clone()),
+                    Some(_) => return Err(Diagnostic::error("`".to_string() + &id + "` is not a function").with_span(span.0, span.1)),
+                    None => return Err(Diagnostic::error("undefined function `".to_string() + &id + "`").with_span(span.0, span.1)),
+                };
+                if param_list.len() != param_tys.len() {
+                    let message = "`".to_string() + &id + "` takes " + &count(param_tys.len(), "argument") + " but " + &param_list.len().to_string() + if param_list.len() == 1 { " was given" } else { " were given" };
+                    return Err(Diagnostic::error(message).with_span(self.span.0, self.span.1));
+                }
+
                 let mut params = vec![];
-                for exp in param_list.into_iter() {
-                    let (value, new_bb) = exp.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
+                for (exp, param_ty) in zip(param_list, &param_tys) {
+                    let arg_span = exp.span;
+                    let (value, ty, new_bb) = exp.dump_typed(bb, func_data, symbols)?;
+                    if !param_ty.accepts(&ty) {
+                        return Err(mismatch(param_ty, &ty, arg_span));
+                    }
                     bb = new_bb;
-
-                    // func_data.dfg_mut().values().get(&value).unwrap();
-                    // if let TypeKind::Pointer(_) = func_data.dfg_mut().value(value).ty().kind() {
-                    //     let zero = func_data.dfg_mut().new_value().integer(0);
-                    //     value = func_data.dfg_mut().new_value().get_elem_ptr(value, zero);
-                    //     func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(value).unwrap();
-                    // }
-                    
                     params.push(value);
                 }
 
-                let func = func_table[&id];
                 let call = func_data.dfg_mut().new_value().call(func, params);
                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(call).unwrap();
 
-                (call, bb)
+                Ok((call, ret_ty, bb))
             }
         }
     }
 
-    fn dump_const(self, symbol_table: Rc<SymbolTable>) -> i32 {
+    fn dump_const(self, symbols: &mut SymbolTable) -> DumpResult<i32> {
         match *self.core {
-            ast::ExpCore::Single(i) => i,
-            ast::ExpCore::Ident(id) => {
-                symbol_table.find_const(&id.id).unwrap()
+            ast::ExpCore::Single(i) => Ok(i),
+            ast::ExpCore::Ident(lval) => {
+                let symbol = lookup(symbols, &lval)?;
+                let mut index = vec![];
+                for exp in lval.is_array.clone() {
+                    index.push(exp.dump_const(symbols)?);
+                }
+                match &symbol.kind {
+                    SymbolKind::Const(init) if !init.dims.is_empty() => const_element(&lval.id, init, &index).map_err(|err| lval_error(&lval, err.message)),
+                    _ => lval_const(&lval, &symbol),
+                }
             },
             ast::ExpCore::Binary(e0, op, e1) => {
-                let x = e0.dump_const(Rc::clone(&symbol_table));
-                let y = e1.dump_const(Rc::clone(&symbol_table));
-                match op {
-                    BinaryOp::Add => x + y,
-                    BinaryOp::Sub => x - y,
-                    BinaryOp::Mul => x * y,
-                    BinaryOp::Div => x / y,
-                    BinaryOp::Mod => x % y,
+                let x = e0.dump_const(symbols)?;
+                let y = e1.dump_const(symbols)?;
+                if matches!(op, BinaryOp::Div | BinaryOp::Mod) && y == 0 {
+                    return Err(Diagnostic::error("division by zero in constant expression".to_string()).with_span(self.span.0, self.span.1));
+                }
+                let i = match op {
+                    BinaryOp::Add => x.wrapping_add(y),
+                    BinaryOp::Sub => x.wrapping_sub(y),
+                    BinaryOp::Mul => x.wrapping_mul(y),
+                    BinaryOp::Div => x.wrapping_div(y),
+                    BinaryOp::Mod => x.wrapping_rem(y),
                     BinaryOp::And => {
                         if x & y == 0 {
                             0
@@ -242,11 +303,40 @@ impl ast::Exp {
                         }
                     }
                     _ => unreachable!()
-                }
+                };
+                Ok(i)
             }
-            _ => unreachable!()
+            ast::ExpCore::Call(id, _) => Err(Diagnostic::error("call to `".to_string() + &id + "` in constant expression")),
+        }
+    }
+}
+
+/* Value of a scalar const, an error for anything that can change */
+fn lval_const(lval: &ast::LVal, symbol: &Symbol) -> DumpResult<i32> {
+    match symbol.const_value() {
+        Some(i) if lval.is_array.is_empty() => Ok(i),
+        Some(_) => Err(lval_error(lval, "`".to_string() + &lval.id + "` is not an array")),
+        None => Err(lval_error(lval, "`".to_string() + &lval.id + "` is not a constant")),
+    }
+}
+
+fn lval_error(lval: &ast::LVal, message: String) -> Diagnostic {
+    Diagnostic::error(message).with_span(lval.span.0, lval.span.1)
+}
+
+/* Element of a const array at constant indices */
+fn const_element(id: &str, init: &Init<i32>, index: &[i32]) -> DumpResult<i32> {
+    if index.len() != init.dims.len() {
+        return Err(Diagnostic::error("`".to_string() + id + "` is not a constant"));
+    }
+    let mut flat = 0;
+    for (&i, &dim) in index.iter().zip(&init.dims) {
+        if i < 0 || i as usize >= dim {
+            return Err(Diagnostic::error("index ".to_string() + &i.to_string() + " is out of bounds for `" + id + "`"));
         }
+        flat = flat * dim + i as usize;
     }
+    Ok(init.elems.get(&flat).copied().unwrap_or(0))
 }
 
 #[derive(Clone, Copy)]
@@ -255,13 +345,13 @@ struct WhileInfo {
     end_bb: BasicBlock,
 }
 
-fn get_array_ptr(mut value: Value, mut is_ptr: bool, list: Vec<ast::Exp>, mut bb: BasicBlock, func_data: &mut FunctionData, symbol_table: Rc<SymbolTable>, func_table: &HashMap<String, Function>) -> (Value, BasicBlock) {
+fn get_array_ptr(mut value: Value, mut is_ptr: bool, list: Vec<ast::Exp>, mut bb: BasicBlock, func_data: &mut FunctionData, symbols: &mut SymbolTable) -> DumpResult<(Value, BasicBlock)> {
     if is_ptr {
         value = func_data.dfg_mut().new_value().load(value);
         func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(value).unwrap();
     }
     for exp in list {
-        let (index, new_bb) = exp.dump(bb, func_data, Rc::clone(&symbol_table), &func_table);
+        let (index, new_bb) = exp.dump(bb, func_data, symbols)?;
         bb = new_bb;
 
         if is_ptr {
@@ -272,36 +362,49 @@ fn get_array_ptr(mut value: Value, mut is_ptr: bool, list: Vec<ast::Exp>, mut bb
         }
         func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(value).unwrap();
     }
-    (value, bb)
+    Ok((value, bb))
 }
 
 impl ast::Stmt {
-    fn dump(self, mut bb: BasicBlock, func_data: &mut FunctionData, symbol_table: Rc<SymbolTable>, while_info: Option<WhileInfo>, func_table: &HashMap<String, Function>) -> BasicBlock {
+    fn dump(self, mut bb: BasicBlock, func_data: &mut FunctionData, symbols: &mut SymbolTable, while_info: Option<WhileInfo>) -> DumpResult<BasicBlock> {
         match self {
             ast::Stmt::Exp(exp) => {
-                let (_, new_bb) = exp.dump(bb, func_data, symbol_table, func_table);
+                let (_, _, new_bb) = exp.dump_typed(bb, func_data, symbols)?;
                 bb = new_bb;
             }
             ast::Stmt::Assign(lval, exp) => {
-                let (dest, _, is_ptr) = symbol_table.find(&lval.id).unwrap();
-                let (exp_val, new_bb) = exp.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
+                let symbol = lookup(symbols, &lval)?;
+                let (dest, is_ptr) = match symbol.value {
+                    Some(value) if !symbol.is_const() => (value, symbol.is_pointer()),
+                    _ => return Err(lval_error(&lval, "cannot assign to constant `".to_string() + &lval.id + "`")),
+                };
+                let ty = lval_ty(&lval, &symbol)?;
+                if !ty.is_int() {
+                    return Err(lval_error(&lval, "cannot assign to `".to_string() + &lval.id + "` of type `" + &ty.to_string() + "`"));
+                }
+                let (exp_val, new_bb) = exp.dump(bb, func_data, symbols)?;
                 bb = new_bb;
                 // func_data.dfg_mut().values().get(&dest).unwrap();
-                let (ptr, new_bb) = get_array_ptr(dest, is_ptr, lval.is_array, bb, func_data, Rc::clone(&symbol_table), func_table);
+                let (ptr, new_bb) = get_array_ptr(dest, is_ptr, lval.is_array, bb, func_data, symbols)?;
                 bb = new_bb;
 
                 let store = func_data.dfg_mut().new_value().store(exp_val, ptr);
                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(store).unwrap();
             }
             ast::Stmt::Block(block) => {
-                let mut new_table = Rc::new(SymbolTable::new());
-                Rc::get_mut(&mut new_table).unwrap().old = Some(Rc::clone(&symbol_table));
-                bb = block.dump(bb, func_data, new_table, while_info, func_table);
+                symbols.push(block.span);
+                bb = block.dump(bb, func_data, symbols, while_info)?;
+                symbols.pop();
             }
-            ast::Stmt::Ret(ret) => {
+            ast::Stmt::Ret(ret, span) => {
+                let is_void = matches!(func_data.ty().kind(), TypeKind::Function(_, ret_type) if ret_type.is_unit());
                 let ret = match ret {
+                    Some(exp) if is_void => {
+                        return Err(Diagnostic::error("void function returns a value".to_string()).with_span(exp.span.0, exp.span.1));
+                    }
+                    None if !is_void => return Err(Diagnostic::error("non-void function returns no value".to_string()).with_span(span.0, span.1)),
                     Some(exp) => {
-                        let (ret_value, new_bb) = exp.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
+                        let (ret_value, new_bb) = exp.dump(bb, func_data, symbols)?;
                         bb = new_bb;
                         func_data.dfg_mut().new_value().ret(Some(ret_value))
                     }
@@ -309,26 +412,26 @@ impl ast::Stmt {
                 };
                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(ret).unwrap();
                 // New a bb
-                bb = func_data.dfg_mut().new_bb().basic_block(None);
+                bb = func_data.dfg_mut().new_bb().basic_block(Some("%after_ret".to_string()));
                 func_data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
             }
             ast::Stmt::If(if_stmt) => {
-                let (cond, new_bb) = if_stmt.exp.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
+                let (cond, new_bb) = if_stmt.exp.dump(bb, func_data, symbols)?;
                 bb = new_bb;
                 // New then bb
-                let then_bb = func_data.dfg_mut().new_bb().basic_block(None);
+                let then_bb = func_data.dfg_mut().new_bb().basic_block(Some("%if_then".to_string()));
                 func_data.layout_mut().bbs_mut().push_key_back(then_bb).unwrap();
-                let then_last_bb = if_stmt.then_stmt.dump(then_bb, func_data, Rc::clone(&symbol_table), while_info, func_table);
+                let then_last_bb = if_stmt.then_stmt.dump(then_bb, func_data, symbols, while_info)?;
                 // New end bb
-                let end_bb = func_data.dfg_mut().new_bb().basic_block(None);
+                let end_bb = func_data.dfg_mut().new_bb().basic_block(Some("%if_end".to_string()));
                 func_data.layout_mut().bbs_mut().push_key_back(end_bb).unwrap();
                 
                 match if_stmt.else_stmt {
                     Some(else_stmt) => {
                         // New else bb
-                        let else_bb = func_data.dfg_mut().new_bb().basic_block(None);
+                        let else_bb = func_data.dfg_mut().new_bb().basic_block(Some("%if_else".to_string()));
                         func_data.layout_mut().bbs_mut().push_key_back(else_bb).unwrap();
-                        let else_last_bb = else_stmt.dump(else_bb, func_data, Rc::clone(&symbol_table), while_info, func_table);
+                        let else_last_bb = else_stmt.dump(else_bb, func_data, symbols, while_info)?;
                         
                         // bb -> then_bb | else_bb
                         let br_then = func_data.dfg_mut().new_value().branch(cond, then_bb, else_bb);
@@ -352,9 +455,9 @@ impl ast::Stmt {
             }
             ast::Stmt::While(exp, stmt) => {
                 // new exp_bb & body_bb & end_bb
-                let exp_bb = func_data.dfg_mut().new_bb().basic_block(None);
-                let body_bb = func_data.dfg_mut().new_bb().basic_block(None);
-                let end_bb = func_data.dfg_mut().new_bb().basic_block(None);
+                let exp_bb = func_data.dfg_mut().new_bb().basic_block(Some("%while_cond".to_string()));
+                let body_bb = func_data.dfg_mut().new_bb().basic_block(Some("%while_body".to_string()));
+                let end_bb = func_data.dfg_mut().new_bb().basic_block(Some("%while_end".to_string()));
                 func_data.layout_mut().bbs_mut().push_key_back(exp_bb).unwrap();
                 func_data.layout_mut().bbs_mut().push_key_back(body_bb).unwrap();
                 func_data.layout_mut().bbs_mut().push_key_back(end_bb).unwrap();
@@ -362,188 +465,283 @@ impl ast::Stmt {
                 let jump0 = func_data.dfg_mut().new_value().jump(exp_bb);
                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump0).unwrap();
                 
-                let (exp_value, exp_last_bb) = exp.dump(exp_bb, func_data, Rc::clone(&symbol_table), func_table);
+                let (exp_value, exp_last_bb) = exp.dump(exp_bb, func_data, symbols)?;
                 let br = func_data.dfg_mut().new_value().branch(exp_value, body_bb, end_bb);
                 func_data.layout_mut().bb_mut(exp_last_bb).insts_mut().push_key_back(br).unwrap();
 
-                let body_last_bb = stmt.dump(body_bb, func_data, Rc::clone(&symbol_table), Some(WhileInfo { exp_bb, end_bb }), func_table);
+                let body_last_bb = stmt.dump(body_bb, func_data, symbols, Some(WhileInfo { exp_bb, end_bb }))?;
                 let jump = func_data.dfg_mut().new_value().jump(exp_bb);
                 func_data.layout_mut().bb_mut(body_last_bb).insts_mut().push_key_back(jump).unwrap();
 
                 bb = end_bb;
             }
-            ast::Stmt::Continue => {
+            ast::Stmt::Continue(span) => {
                 match while_info {
                     Some(while_info) => {
                         let jump = func_data.dfg_mut().new_value().jump(while_info.exp_bb);
                         func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
 
-                        bb = func_data.dfg_mut().new_bb().basic_block(None);
+                        bb = func_data.dfg_mut().new_bb().basic_block(Some("%after_continue".to_string()));
                         func_data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
                     }
-                    None => panic!()
+                    None => return Err(Diagnostic::error("`continue` outside of a loop".to_string()).with_span(span.0, span.1)),
                 }
             }
-            ast::Stmt::Break => {
+            ast::Stmt::Break(span) => {
                 match while_info {
                     Some(while_info) => {
                         let jump = func_data.dfg_mut().new_value().jump(while_info.end_bb);
                         func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
 
-                        bb = func_data.dfg_mut().new_bb().basic_block(None);
+                        bb = func_data.dfg_mut().new_bb().basic_block(Some("%after_break".to_string()));
                         func_data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
                     }
-                    None => panic!()
+                    None => return Err(Diagnostic::error("`break` outside of a loop".to_string()).with_span(span.0, span.1)),
                 }
             }
             _ => {
                 //do nothing
             }
         }
-        bb
+        Ok(bb)
     }
 }
 
-fn build_value_local(vree: &Vree, ptr: Value, func_data: &mut FunctionData, bb: BasicBlock) {
-    match vree {
-        Vree::Leaf(Some(value)) => {
-            let store = func_data.dfg_mut().new_value().store(*value, ptr);
+/* Implicit zeros above this count are cleared by a loop instead of one store each */
+const ZERO_LOOP_MIN: usize = 16;
+
+fn is_zero(value: Value, func_data: &FunctionData) -> bool {
+    matches!(func_data.dfg().value(value).kind(), ValueKind::Integer(int) if int.value() == 0)
+}
+
+/* Store the elements of the sub-array at `base` into `ptr`, leaving out zeros when memory is already cleared */
+#[allow(clippy::too_many_arguments)]
+fn store_local(init: &Init<Value>, dims: &[usize], base: usize, ptr: Value, cleared: bool, func_data: &mut FunctionData, bb: BasicBlock) {
+    if dims.is_empty() {
+        let value = match init.elems.get(&base) {
+            Some(&value) => value,
+            None => func_data.dfg_mut().new_value().integer(0),
+        };
+        if !(cleared && is_zero(value, func_data)) {
+            let store = func_data.dfg_mut().new_value().store(value, ptr);
             func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(store).unwrap();
         }
-        Vree::Sub(list) => {
-            for i in 0..list.len() {
-                let index = func_data.dfg_mut().new_value().integer(i as i32);
-                let get = func_data.dfg_mut().new_value().get_elem_ptr(ptr, index);
-                func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(get).unwrap();
-                build_value_local(&list[i], get, func_data, bb);
-            }
-        }
-        _ => {
-            // donothing
+        return;
+    }
+    let stride: usize = dims[1..].iter().product();
+    for i in 0..dims[0] {
+        let start = base + i * stride;
+        if cleared && init.elems.range(start..start + stride).all(|(_, &value)| is_zero(value, func_data)) {
+            continue;
         }
+        let index = func_data.dfg_mut().new_value().integer(i as i32);
+        let get = func_data.dfg_mut().new_value().get_elem_ptr(ptr, index);
+        func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(get).unwrap();
+        store_local(init, &dims[1..], start, get, cleared, func_data, bb);
     }
 }
 
-fn adj_tree(arr: &Tree, func_data: &mut FunctionData) -> Vree {
-    match arr {
-        Tree::Leaf(i) => {
-            let v = func_data.dfg_mut().new_value().integer(*i);
-            Vree::Leaf(Some(v))
-        },
-        Tree::Sub(list) => {
-            let mut vlist = vec![];
-            for t in list {
-                vlist.push(adj_tree(t, func_data));
-            }
-            Vree::Sub(vlist)
+/* Initialize a local, clearing it with a loop first when it is mostly zero */
+fn init_local(init: &Init<Value>, alloc: Value, func_data: &mut FunctionData, mut bb: BasicBlock) -> BasicBlock {
+    let nonzero = init.elems.values().filter(|&&value| !is_zero(value, func_data)).count();
+    let cleared = init.len() - nonzero > ZERO_LOOP_MIN;
+    if cleared {
+        bb = zero_loop(alloc, &init.dims, func_data, bb);
+    }
+    store_local(init, &init.dims, 0, alloc, cleared, func_data, bb);
+    bb
+}
+
+/* Store 0 to every element of a local array, like memset */
+fn zero_loop(alloc: Value, size: &[usize], func_data: &mut FunctionData, bb: BasicBlock) -> BasicBlock {
+    // Walk down to the first element, then index it as a flat array
+    let zero = func_data.dfg_mut().new_value().integer(0);
+    let mut base = alloc;
+    for _ in size {
+        base = func_data.dfg_mut().new_value().get_elem_ptr(base, zero);
+        func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(base).unwrap();
+    }
+    let len = size.iter().product::<usize>() as i32;
+
+    let counter = func_data.dfg_mut().new_value().alloc(Type::get_i32());
+    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(counter).unwrap();
+    let init = func_data.dfg_mut().new_value().store(zero, counter);
+    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(init).unwrap();
+
+    let cond_bb = func_data.dfg_mut().new_bb().basic_block(Some("%zero_cond".to_string()));
+    let body_bb = func_data.dfg_mut().new_bb().basic_block(Some("%zero_body".to_string()));
+    let end_bb = func_data.dfg_mut().new_bb().basic_block(Some("%zero_end".to_string()));
+    func_data.layout_mut().bbs_mut().push_key_back(cond_bb).unwrap();
+    func_data.layout_mut().bbs_mut().push_key_back(body_bb).unwrap();
+    func_data.layout_mut().bbs_mut().push_key_back(end_bb).unwrap();
+    let jump = func_data.dfg_mut().new_value().jump(cond_bb);
+    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
+
+    // cond: i < len
+    let i = func_data.dfg_mut().new_value().load(counter);
+    let bound = func_data.dfg_mut().new_value().integer(len);
+    let cond = func_data.dfg_mut().new_value().binary(BinaryOp::Lt, i, bound);
+    let br = func_data.dfg_mut().new_value().branch(cond, body_bb, end_bb);
+    for inst in [i, cond, br] {
+        func_data.layout_mut().bb_mut(cond_bb).insts_mut().push_key_back(inst).unwrap();
+    }
+
+    // body: base[i] = 0; i = i + 1
+    let i = func_data.dfg_mut().new_value().load(counter);
+    let ptr = func_data.dfg_mut().new_value().get_ptr(base, i);
+    let store = func_data.dfg_mut().new_value().store(zero, ptr);
+    let one = func_data.dfg_mut().new_value().integer(1);
+    let next = func_data.dfg_mut().new_value().binary(BinaryOp::Add, i, one);
+    let step = func_data.dfg_mut().new_value().store(next, counter);
+    let jump = func_data.dfg_mut().new_value().jump(cond_bb);
+    for inst in [i, ptr, store, next, step, jump] {
+        func_data.layout_mut().bb_mut(body_bb).insts_mut().push_key_back(inst).unwrap();
+    }
+    end_bb
+}
+
+/* Type of a parameter, an array decays to a pointer to its first row */
+fn param_ty(param: &ast::FuncParam, symbols: &mut SymbolTable) -> DumpResult<Ty> {
+    if param.1.is_empty() {
+        return Ok(Ty::Int);
+    }
+    // The first dimension is omitted
+    let dims = dims(param.1[1..].to_vec(), symbols)?;
+    Ok(Ty::Pointer(Box::new(Ty::array(Ty::Int, &dims))))
+}
+
+/* Evaluate array dimensions, each must be a positive constant
+ * The size in bytes has to fit an i32, the backend addresses frames and
+ * globals with 32-bit offsets.
+ */
+fn dims(exps: Vec<ast::Exp>, symbols: &mut SymbolTable) -> DumpResult<Vec<usize>> {
+    let mut dims = vec![];
+    let mut bytes: usize = 4;
+    for exp in exps {
+        let span = exp.span;
+        let len = exp.dump_const(symbols)?;
+        if len <= 0 {
+            return Err(Diagnostic::error("array size must be positive".to_string()).with_span(span.0, span.1));
         }
+        bytes = match bytes.checked_mul(len as usize) {
+            Some(bytes) if bytes <= i32::MAX as usize => bytes,
+            _ => return Err(Diagnostic::error("array is too large".to_string()).with_span(span.0, span.1)),
+        };
+        dims.push(len as usize);
+    }
+    Ok(dims)
+}
+
+/* Place the elements of an initializer, it must fit the variable */
+fn normalize(init_val: ast::InitVal, dims: &[usize], id: &str) -> DumpResult<Init<ast::Exp>> {
+    let init = Init::new(init_val, dims).map_err(|err| Diagnostic::error(err.to_string() + " of `" + id + "`"))?;
+    crate::trace!(Init, Debug, "`{}` {:?}: {} of {} elements given", id, dims, init.elems.len(), init.len());
+    Ok(init)
+}
+
+/* Initializer whose elements are all constant expressions */
+fn const_init(init_val: ast::InitVal, dims: &[usize], id: &str, symbols: &mut SymbolTable) -> DumpResult<Init<i32>> {
+    normalize(init_val, dims, id)?.try_map(|exp| exp.dump_const(symbols))
+}
+
+/* Koopa initializer of the sub-array at `base`, zero sub-arrays become zeroinit */
+fn build_global(init: &Init<i32>, dims: &[usize], base: usize, program: &mut Program) -> Value {
+    if dims.is_empty() {
+        let i = init.elems.get(&base).copied().unwrap_or(0);
+        return program.new_value().integer(i);
+    }
+    let len: usize = dims.iter().product();
+    if init.elems.range(base..base + len).all(|(_, &i)| i == 0) {
+        return program.new_value().zero_init(Ty::array(Ty::Int, dims).to_koopa());
+    }
+    let stride = len / dims[0];
+    let mut value_list = vec![];
+    for i in 0..dims[0] {
+        value_list.push(build_global(init, &dims[1..], base + i * stride, program));
     }
+    program.new_value().aggregate(value_list)
 }
 
 impl ast::Block {
-    fn dump(self, mut bb: BasicBlock, func_data: &mut FunctionData, mut symbol_table: Rc<SymbolTable>, while_info: Option<WhileInfo>, func_table: &HashMap<String, Function>) -> BasicBlock {
+    fn dump(self, mut bb: BasicBlock, func_data: &mut FunctionData, symbols: &mut SymbolTable, while_info: Option<WhileInfo>) -> DumpResult<BasicBlock> {
 
         for item in self.block_item_list {
             match item {
-                ast::BlockItem::Decl(decl) => {
+                ast::BlockItem::Decl(decl, _) => {
                     match decl {
                         ast::Decl::Const(const_decl) => {
                             for const_def in const_decl.const_def_list {
-                                let mut index = vec![];
-                                for exp in const_def.is_array {
-                                    let i = exp.dump_const(Rc::clone(&symbol_table)) as usize;
-                                    index.push(i);
-                                }
-
-                                let len = index.len();
+                                let index = dims(const_def.is_array, symbols)?;
 
-                                let mut ty = Type::get_i32();
-                                for i in index.iter().rev() {
-                                    ty = Type::get_array(ty, *i);
+                                let init = const_init(const_def.const_init_val, &index, &const_def.id, symbols)?;
+                                if index.is_empty() {
+                                    define(symbols, const_symbol(&const_def.id, init, None, const_def.span))?;
+                                    continue;
                                 }
 
-                                let arr = const_def.const_init_val.dump_global(index, Rc::clone(&symbol_table));
-
-                                let alloc = func_data.dfg_mut().new_value().alloc(ty);
+                                let alloc = func_data.dfg_mut().new_value().alloc(Ty::array(Ty::Int, &index).to_koopa());
+                                func_data.dfg_mut().set_value_name(alloc, Some(symbols.local_name(&const_def.id)));
                                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(alloc).unwrap();
 
-                                let vree = adj_tree(&arr, func_data);
-                                build_value_local(&vree, alloc, func_data, bb);
-                                Rc::get_mut(&mut symbol_table).unwrap().table.insert(const_def.id.clone(), (alloc, len, false));
-
-                                if let Tree::Leaf(i) = arr {
-                                    Rc::get_mut(&mut symbol_table).unwrap().const_table.insert(const_def.id.clone(), i);
-                                }
+                                let values = init.clone().map(|i| func_data.dfg_mut().new_value().integer(i));
+                                bb = init_local(&values, alloc, func_data, bb);
+                                define(symbols, const_symbol(&const_def.id, init, Some(alloc), const_def.span))?;
                             }
                         }
                         ast::Decl::Var(var_decl) => {
                             for var_def in var_decl.var_def_list {
-                                let mut index = vec![];
-                                for exp in var_def.is_array {
-                                    let i = exp.dump_const(Rc::clone(&symbol_table)) as usize;
-                                    index.push(i);
-                                }
-
-                                let len = index.len();
-
-                                let mut ty = Type::get_i32();
-                                for i in index.iter().rev() {
-                                    ty = Type::get_array(ty, *i);
-                                }
+                                let index = dims(var_def.is_array, symbols)?;
 
-                                let alloc = func_data.dfg_mut().new_value().alloc(ty);
+                                let ty = Ty::array(Ty::Int, &index);
+                                let alloc = func_data.dfg_mut().new_value().alloc(ty.to_koopa());
+                                func_data.dfg_mut().set_value_name(alloc, Some(symbols.local_name(&var_def.id)));
                                 func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(alloc).unwrap();
 
                                 if let Some(init_val) = var_def.init_val {
-                                    let (vree, new_bb) = init_val.dump_local(index, bb, Rc::clone(&symbol_table), func_data, func_table);
-                                    bb = new_bb;
-
-                                    build_value_local(&vree, alloc, func_data, bb);
+                                    // Expressions are evaluated in source order
+                                    let init = normalize(init_val, &index, &var_def.id)?.try_map(|exp| {
+                                        let (value, new_bb) = exp.dump(bb, func_data, symbols)?;
+                                        bb = new_bb;
+                                        Ok(value)
+                                    })?;
+                                    bb = init_local(&init, alloc, func_data, bb);
                                 }
-                                Rc::get_mut(&mut symbol_table).unwrap().table.insert(var_def.id.clone(), (alloc, len, false));
+                                define(symbols, Symbol::new(&var_def.id, SymbolKind::Var, ty, var_def.span).with_value(alloc))?;
                             }
                         }
                     }
                 }
-                ast::BlockItem::Stmt(stmt) => {
-                    bb = stmt.dump(bb, func_data, Rc::clone(&symbol_table), while_info, func_table);
+                ast::BlockItem::Stmt(stmt, _) => {
+                    bb = stmt.dump(bb, func_data, symbols, while_info)?;
                 }
             }
         }
-        bb
+        Ok(bb)
     }
 }
 
 impl ast::FuncDef {
-    fn dump(self, func_data: &mut FunctionData, func_table: &HashMap<String, Function>, old_symbol_table: Rc<SymbolTable>) {
-        let mut symbol_table = Rc::new(SymbolTable::new());
-        Rc::get_mut(&mut symbol_table).unwrap().old = Some(old_symbol_table);
+    fn dump(self, func_data: &mut FunctionData, param_tys: Vec<Ty>, symbols: &mut SymbolTable) -> DumpResult<()> {
+        // Parameters share the scope of the outermost block
+        symbols.push((self.span.0, self.block.span.1));
+        let param_names: Vec<String> = self.func_param_list.iter().map(|param| param.0.clone()).collect();
+        symbols.begin_function(&param_names);
         let entry = func_data.dfg_mut().new_bb().basic_block(Some("%entry".to_string()));
         func_data.layout_mut().bbs_mut().push_key_back(entry).unwrap();
 
         let params = func_data.params().to_vec();
-        let param_kind = if let TypeKind::Function(p, _) = func_data.ty().kind() {
-            p.clone()
-        }else {
-            panic!()
-        };
-        for (func_param, (value, ty)) in zip(self.func_param_list, zip(params, param_kind)) {
-            let alloc = func_data.dfg_mut().new_value().alloc(ty);
+        for (func_param, (value, ty)) in zip(self.func_param_list, zip(params, param_tys)) {
+            let alloc = func_data.dfg_mut().new_value().alloc(ty.to_koopa());
+            func_data.dfg_mut().set_value_name(alloc, Some(symbols.local_name(&func_param.0)));
             func_data.layout_mut().bb_mut(entry).insts_mut().push_key_back(alloc).unwrap();
             let assign = func_data.dfg_mut().new_value().store(value, alloc);
             func_data.layout_mut().bb_mut(entry).insts_mut().push_key_back(assign).unwrap();
 
-            let len = func_param.1.len();
-            if len == 0 {
-                println!("{}", 1);
-                Rc::get_mut(&mut symbol_table).unwrap().table.insert(func_param.0, (alloc, 0, false));
-            }else {
-                println!("{}", 2);
-                Rc::get_mut(&mut symbol_table).unwrap().table.insert(func_param.0, (alloc, len, true));
-            }
+            define(symbols, Symbol::new(&func_param.0, SymbolKind::Param, ty, func_param.2).with_value(alloc))?;
         }
 
-        let last_bb = self.block.dump(entry, func_data, symbol_table, None, func_table);
+        let last_bb = self.block.dump(entry, func_data, symbols, None)?;
+        symbols.pop();
         
         let ret = match func_data.ty().kind() {
             TypeKind::Function(_, ret_type) => {
@@ -557,340 +755,99 @@ impl ast::FuncDef {
             _ => unreachable!()
         };
         func_data.layout_mut().bb_mut(last_bb).insts_mut().push_key_back(ret).unwrap();
+        Ok(())
     }
 }
 
-fn push_runtime_func(program: &mut Program, func_table: &mut HashMap<String, Function>) {
-    let getint = program.new_func(FunctionData::new(
-        "@getint".to_string(),
-        vec![],
-        Type::get_i32()
-    ));
-    let getch = program.new_func(FunctionData::new(
-        "@getch".to_string(),
-        vec![],
-        Type::get_i32()
-    ));
-    let getarray = program.new_func(FunctionData::new(
-        "@getarray".to_string(),
-        vec![Type::get_pointer(Type::get_i32())],
-        Type::get_i32()
-    ));
-    let putint = program.new_func(FunctionData::new(
-        "@putint".to_string(),
-        vec![Type::get_i32()],
-        Type::get_unit()
-    ));
-    let putch = program.new_func(FunctionData::new(
-        "@putch".to_string(),
-        vec![Type::get_i32()],
-        Type::get_unit()
-    ));
-    let putarray = program.new_func(FunctionData::new(
-        "@putarray".to_string(),
-        vec![Type::get_i32(), Type::get_pointer(Type::get_i32())],
-        Type::get_unit()
-    ));
-    let starttime = program.new_func(FunctionData::new(
-        "@starttime".to_string(),
-        vec![],
-        Type::get_unit()
-    ));
-    let stoptime = program.new_func(FunctionData::new(
-        "@stoptime".to_string(),
-        vec![],
-        Type::get_unit()
-    ));
-    
-    func_table.insert("getint".to_string(), getint);
-    func_table.insert("getch".to_string(), getch);
-    func_table.insert("getarray".to_string(), getarray);
-    func_table.insert("putint".to_string(), putint);
-    func_table.insert("putch".to_string(), putch);
-    func_table.insert("putarray".to_string(), putarray);
-    func_table.insert("starttime".to_string(), starttime);
-    func_table.insert("stoptime".to_string(), stoptime);
-}
-
-fn union_set(size: &Vec<usize>, arr_set: &mut Vec<Vec<Tree>>) {
-    for i in 0..size.len() {
-        let len = size[size.len() - i - 1];
-        while arr_set[i].len() >= len {
-            // println!("!{} {} {}", size.len(), i, len);
-            let mut set = vec![];
-            for _ in 0..len {
-                set.push(arr_set[i].remove(0));
-            }
-            let arr = Tree::Sub(set);
-            arr_set[i + 1].push(arr);
-        }
-    }
-}
-
-fn union_set_local(size: &Vec<usize>, arr_set: &mut Vec<Vec<Vree>>) {
-    for i in 0..size.len() {
-        let len = size[size.len() - i - 1];
-        while arr_set[i].len() >= len {
-            let mut set = vec![];
-            for _ in 0..len {
-                set.push(arr_set[i].remove(0));
-            }
-            let arr = Vree::Sub(set);
-            arr_set[i + 1].push(arr);
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-enum Tree {
-    Sub(Vec<Tree>),
-    Leaf(i32)
-}
-
-#[derive(Clone)]
-enum Vree {
-    Sub(Vec<Vree>),
-    Leaf(Option<Value>)
-}
-
-impl ast::InitVal {
-    fn dump_local(self, size: Vec<usize>, mut bb: BasicBlock, symbol_table: Rc<SymbolTable>, func_data: &mut FunctionData, func_table: &HashMap<String, Function>) -> (Vree, BasicBlock) {
-        match self {
-            ast::InitVal::Exp(exp) => {
-                // ArrayD::from_elem(vec![1], exp.dump_const(Rc::clone(&symbol_table)))
-                let (value, new_bb) = exp.dump(bb, func_data, Rc::clone(&symbol_table), func_table);
-                (Vree::Leaf(Some(value)), new_bb)
-            },
-            ast::InitVal::List(list) => {
-                let mut arr_set = vec![vec![]; size.len() + 1];
-                for init_val in list {
-                    // Union arr set
-                    union_set_local(&size, &mut arr_set);
-
-                    // Divide
-                    match &*init_val {
-                        ast::InitVal::Exp(_) => {
-                            let (vree, new_bb) = init_val.dump_local(vec![], bb, Rc::clone(&symbol_table), func_data, func_table);
-                            arr_set[0].push(vree);
-                            bb = new_bb;
-                        },
-                        ast::InitVal::List(_) => {
-                            let mut dim = size.len() - 1;
-                            for i in 0..arr_set.len() {
-                                if !arr_set[i].is_empty() {
-                                    dim = i;
-                                    break;
-                                }
-                            }
-                            let start = size.len() - dim;
-                            let back = size[start..].to_vec();
-                            let (vree, new_bb) = init_val.dump_local(back, bb, Rc::clone(&symbol_table), func_data, func_table);
-                            arr_set[dim].push(vree);
-                            bb = new_bb;
-                        }
-                    }
-                }
-                // Count & Push
-                let mut s = 1 as usize;
-                let mut count = 0 as usize;
-                for i in 0..arr_set.len() {
-                    if i > 0 {
-                        s *= size[size.len() - i];
-                    }
-                    count += s * arr_set[i].len();
-                }
-                if count < s {
-                    for _ in 0..s-count {
-                        let zero = func_data.dfg_mut().new_value().integer(0);
-                        arr_set[0].push(Vree::Leaf(Some(zero)));
-                    }
-                }
-                // Union
-                union_set_local(&size, &mut arr_set);
-
-                (arr_set[size.len()].remove(0), bb)
-            },
-        }
-    }
-
-    fn dump_global(self, size: Vec<usize>, symbol_table: Rc<SymbolTable>) -> Tree {
-        // println!("{}", size.len());
-        match self {
-            ast::InitVal::Exp(exp) => {
-                // ArrayD::from_elem(vec![1], exp.dump_const(Rc::clone(&symbol_table)))
-                let i = exp.dump_const(Rc::clone(&symbol_table));
-                println!("= {}", i);
-                Tree::Leaf(i)
-            },
-            ast::InitVal::List(list) => {
-                let mut arr_set = vec![vec![]; size.len() + 1];
-                for init_val in list {
-                    // Union arr set
-                    union_set(&size, &mut arr_set);
-
-                    // Divide
-                    match &*init_val {
-                        ast::InitVal::Exp(_) => {
-                            arr_set[0].push(init_val.dump_global(vec![], Rc::clone(&symbol_table)));
-                        },
-                        ast::InitVal::List(_) => {
-                            let mut dim = size.len() - 1;
-                            println!("??{}", dim);
-                            for i in 0..arr_set.len() {
-                                if !arr_set[i].is_empty() {
-                                    dim = i;
-                                    break;
-                                }
-                            }
-                            println!("?{}", dim);
-                            let start = size.len() - dim;
-                            let back = size[start..].to_vec();
-                            let arr = init_val.dump_global(back, Rc::clone(&symbol_table));
-                            arr_set[dim].push(arr);
-                        }
-                    }
-                }
-                // Count & Push
-                let mut s = 1 as usize;
-                let mut count = 0 as usize;
-                for i in 0..arr_set.len() {
-                    if i > 0 {
-                        s *= size[size.len() - i];
-                    }
-                    count += s * arr_set[i].len();
-                }
-                println!("size: {}, count: {}, s: {}", size.len(), count, s);
-                if count < s {
-                    for _ in 0..s-count {
-                        arr_set[0].push(Tree::Leaf(0));
-                    }
-                }
-                // Union
-                union_set(&size, &mut arr_set);
-
-                arr_set[size.len()].remove(0)
-            },
-        }
-    }
-}
-
-fn build_value(arr: Tree, program: &mut Program) -> Value {
-    match arr {
-        Tree::Leaf(i) => {
-            program.new_value().integer(i)
-            // program.new_value().global_alloc(int)
-        },
-        Tree::Sub(list) => {
-            let mut value_list = vec![];
-            for t in list {
-                let v = build_value(t, program);
-                value_list.push(v);
-            }
-            program.new_value().aggregate(value_list)
-            // program.new_value().global_alloc(agg)
-        }
+fn push_runtime_func(program: &mut Program, symbols: &mut SymbolTable) {
+    let int_ptr = || Ty::Pointer(Box::new(Ty::Int));
+    let funcs = [
+        ("getint", vec![], Ty::Int),
+        ("getch", vec![], Ty::Int),
+        ("getarray", vec![int_ptr()], Ty::Int),
+        ("putint", vec![Ty::Int], Ty::Void),
+        ("putch", vec![Ty::Int], Ty::Void),
+        ("putarray", vec![Ty::Int, int_ptr()], Ty::Void),
+        ("starttime", vec![], Ty::Void),
+        ("stoptime", vec![], Ty::Void),
+    ];
+    // Runtime functions are defined nowhere in the source
+    for (name, params, ret) in funcs {
+        let func = program.new_func(FunctionData::new(
+            "@".to_string() + name,
+            params.iter().map(Ty::to_koopa).collect(),
+            ret.to_koopa()
+        ));
+        let ty = Ty::Func(params, Box::new(ret));
+        symbols.define(Symbol::new(name, SymbolKind::Func(func), ty, (0, 0))).unwrap();
     }
 }
 
 impl ast::Program {
     /* Dump prog into koopa */
-    pub fn dump(self) -> Program {
+    pub fn dump(self) -> DumpResult<Program> {
+        self.dump_with(&mut SymbolTable::new())
+    }
+
+    /* Dump prog into koopa, leaving every symbol defined before any error in `symbols` */
+    pub fn dump_with(self, symbols: &mut SymbolTable) -> DumpResult<Program> {
         let mut program = Program::new();
-        let mut func_table = HashMap::new();
-        let mut symbol_table = Rc::new(SymbolTable::new());
 
-        push_runtime_func(&mut program, &mut func_table);
-        
-        let mut func_list = vec![];
+        push_runtime_func(&mut program, symbols);
 
+        // Globals and functions are visible from their definition on
         for def in self.list {
             match def {
-                Ok(mut func_def) => {
-                    let mut param_ty = Vec::new();
-                    let param_list = func_def.func_param_list;
-                    func_def.func_param_list = vec![];
-                    for param in param_list {
-                        let fake_exp_list = vec![ast::Exp::single(0); param.1.len()];
-                        func_def.func_param_list.push(ast::FuncParam(param.0, fake_exp_list));
-                        // param_ty.push((Some("@".to_owned() + &param.0), Type::get_i32()));
-                        let mut ty = Type::get_i32();
-                        let mut list = param.1;
-                        if list.len() > 0 {
-                            list.reverse();
-                            list.pop().unwrap();
-                            for exp in list {
-                                let i = exp.dump_const(Rc::clone(&symbol_table));
-                                ty = Type::get_array(ty, i as usize);
-                            }
-                            ty = Type::get_pointer(ty);
-                        }
-                        param_ty.push((None, ty));
+                Ok(func_def) => {
+                    let mut params = vec![];
+                    for param in &func_def.func_param_list {
+                        params.push(param_ty(param, symbols)?);
                     }
-                    let ret_ty = match func_def.func_type {
-                        ast::FuncType::Int => Type::get_i32(),
-                        ast::FuncType::Void => Type::get_unit(),
+                    let ret = match func_def.func_type {
+                        ast::FuncType::Int => Ty::Int,
+                        ast::FuncType::Void => Ty::Void,
                     };
+                    let param_names = zip(&func_def.func_param_list, &params).map(|(param, ty)| (Some("@".to_string() + &param.0), ty.to_koopa()));
                     let func = program.new_func(
-                        FunctionData::with_param_names(("@".to_owned() + &func_def.id).into(), param_ty, ret_ty),
+                        FunctionData::with_param_names("@".to_owned() + &func_def.id, param_names.collect(), ret.to_koopa()),
                     );
-                    func_table.insert(func_def.id.clone(), func);
-                    func_list.push(func_def);
+                    // Defined before its body, so it can call itself
+                    let ty = Ty::Func(params.clone(), Box::new(ret));
+                    define(symbols, Symbol::new(&func_def.id, SymbolKind::Func(func), ty, func_def.span))?;
+                    crate::trace!(Lower, Info, "lowering function `{}`", func_def.id);
+                    func_def.dump(program.func_mut(func), params, symbols)?;
                 },
                 Err(decl) => {
                     match decl {
                         ast::Decl::Const(const_decl) => {
                             for const_def in const_decl.const_def_list {
-                                let mut index = vec![];
-                                for exp in const_def.is_array {
-                                    let i = exp.dump_const(Rc::clone(&symbol_table)) as usize;
-                                    index.push(i);
-                                }
-
-                                let len = index.len();
+                                let index = dims(const_def.is_array, symbols)?;
 
-                                let arr = const_def.const_init_val.dump_global(index, Rc::clone(&symbol_table));
-
-                                if let Tree::Leaf(i) = arr {
-                                    Rc::get_mut(&mut symbol_table).unwrap().const_table.insert(const_def.id.clone(), i);
+                                let init = const_init(const_def.const_init_val, &index, &const_def.id, symbols)?;
+                                if index.is_empty() {
+                                    define(symbols, const_symbol(&const_def.id, init, None, const_def.span))?;
+                                    continue;
                                 }
 
-                                let value = build_value(arr, &mut program);
+                                let value = build_global(&init, &index, 0, &mut program);
                                 let alloc = program.new_value().global_alloc(value);
-                                Rc::get_mut(&mut symbol_table).unwrap().table.insert(const_def.id.clone(), (alloc, len, false));
+                                program.set_value_name(alloc, Some("@".to_string() + &const_def.id));
+                                define(symbols, const_symbol(&const_def.id, init, Some(alloc), const_def.span))?;
                             }
                         }
                         ast::Decl::Var(var_decl) => {
                             for var_def in var_decl.var_def_list {
-                                let mut index = vec![];
-                                for exp in var_def.is_array {
-                                    let i = exp.dump_const(Rc::clone(&symbol_table)) as usize;
-                                    index.push(i);
-                                }
-
-                                let len = index.len();
+                                let index = dims(var_def.is_array, symbols)?;
 
-                                println!("len: {}", len);
-
-                                let arr = match var_def.init_val {
-                                    Some(init_val) => init_val.dump_global(index, Rc::clone(&symbol_table)),
-                                    None => {
-                                        if index.len() == 0 {
-                                            Tree::Leaf(0)
-                                        }else {
-                                            ast::InitVal::List(vec![]).dump_global(index, Rc::clone(&symbol_table))
-                                        }
-                                    }
+                                // Globals without initializer are zero
+                                let init = match var_def.init_val {
+                                    Some(init_val) => const_init(init_val, &index, &var_def.id, symbols)?,
+                                    None => Init { dims: index.clone(), elems: BTreeMap::new() },
                                 };
-
-                                
-                                
-                                if let Tree::Leaf(i) = arr {
-                                    Rc::get_mut(&mut symbol_table).unwrap().const_table.insert(var_def.id.clone(), i);
-                                }
-
-                                let value = build_value(arr, &mut program);
+                                let value = build_global(&init, &index, 0, &mut program);
                                 let alloc = program.new_value().global_alloc(value);
-                                Rc::get_mut(&mut symbol_table).unwrap().table.insert(var_def.id.clone(), (alloc, len, false));
+                                program.set_value_name(alloc, Some("@".to_string() + &var_def.id));
+                                define(symbols, Symbol::new(&var_def.id, SymbolKind::Var, Ty::array(Ty::Int, &index), var_def.span).with_value(alloc))?;
                             }
                         }
                     }
@@ -898,22 +855,129 @@ impl ast::Program {
             }
         }
 
-        for func_def in func_list {
-            let name = func_def.id.clone();
-            // println!("{} dumping", name);
-            func_def.dump(program.func_mut(func_table[&name]), &func_table, Rc::clone(&symbol_table));
-        }
-
-        program
+        Ok(program)
     }
 }
 
 /* Generate koopa text */
-pub fn gen_text_koopa(ast: ast::Program) -> String {
-    // Dump, then call koopa lib
-    let program = ast.dump();
+pub fn gen_text_koopa(program: &Program) -> String {
     let mut gen = KoopaGenerator::new(Vec::new());
-    gen.generate_on(&program).unwrap();
+    gen.generate_on(program).unwrap();
 
     std::str::from_utf8(&gen.writer()).unwrap().to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use koopa::ir::*;
+    use crate::opt::sccp::fold_binary;
+
+    /* What a load of memory nothing stored to gives, like a stale stack */
+    const GARBAGE: i32 = 0x5a5a5a5a;
+
+    fn lower(source: &str) -> Program {
+        crate::lower(&crate::parse(source).unwrap()).ok().unwrap()
+    }
+
+    /* Run a function without calls, memory addressed by byte */
+    fn run(program: &Program, name: &str, args: &[i32]) -> i32 {
+        Type::set_ptr_size(4);
+        let func_data = program.funcs().values().find(|func_data| func_data.name() == name).unwrap();
+        let mut values: HashMap<Value, i32> = func_data.params().iter().copied().zip(args.iter().copied()).collect();
+        let mut memory: HashMap<i32, i32> = HashMap::new();
+        let mut top = 0x1000;
+        let get = |values: &HashMap<Value, i32>, value: Value| match func_data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => int.value(),
+            _ => values[&value],
+        };
+        // Size of what a pointer typed value points to
+        let pointee = |value: Value| match func_data.dfg().value(value).ty().kind() {
+            TypeKind::Pointer(base) => base.size() as i32,
+            _ => unreachable!(),
+        };
+        let mut bb = func_data.layout().entry_bb().unwrap();
+        for _ in 0..100000 {
+            let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                let result = match func_data.dfg().value(inst).kind() {
+                    ValueKind::Alloc(_) => {
+                        top += pointee(inst);
+                        top - pointee(inst)
+                    }
+                    ValueKind::Load(load) => memory.get(&get(&values, load.src())).copied().unwrap_or(GARBAGE),
+                    ValueKind::Store(store) => {
+                        memory.insert(get(&values, store.dest()), get(&values, store.value()));
+                        continue;
+                    }
+                    ValueKind::GetElemPtr(gep) => get(&values, gep.src()) + get(&values, gep.index()) * pointee(inst),
+                    ValueKind::GetPtr(gp) => get(&values, gp.src()) + get(&values, gp.index()) * pointee(inst),
+                    ValueKind::Binary(bin) => fold_binary(bin.op(), get(&values, bin.lhs()), get(&values, bin.rhs())).unwrap(),
+                    ValueKind::Branch(br) => {
+                        bb = if get(&values, br.cond()) != 0 { br.true_bb() } else { br.false_bb() };
+                        break;
+                    }
+                    ValueKind::Jump(jump) => {
+                        bb = jump.target();
+                        break;
+                    }
+                    ValueKind::Return(ret) => return get(&values, ret.value().unwrap()),
+                    kind => panic!("cannot run {:?}", kind),
+                };
+                values.insert(inst, result);
+            }
+        }
+        panic!("did not return");
+    }
+
+    /* Element k of `int a[4][6]` after its initializer, with `v` as a variable element */
+    fn elements(init: &str, v: i32) -> (Program, Vec<i32>) {
+        let source = "int f(int v, int k) {\n  int a[4][6] = ".to_string() + init + ";\n  return a[k / 6][k % 6];\n}\n";
+        let program = lower(&source);
+        let list = (0..24).map(|k| run(&program, "@f", &[v, k])).collect();
+        (program, list)
+    }
+
+    fn has_zero_loop(program: &Program) -> bool {
+        let func_data = program.funcs().values().find(|func_data| func_data.name() == "@f").unwrap();
+        func_data.dfg().bbs().values().any(|bb_data| bb_data.name().as_deref() == Some("%zero_cond"))
+    }
+
+    #[test]
+    fn mostly_zero_local_arrays_read_back() {
+        let mut expected = vec![0; 24];
+        expected[0] = 1;
+        expected[1] = 2;
+        expected[14] = -9;
+        expected[15] = 3;
+        expected[18] = 4;
+        let (program, list) = elements("{{1, 2}, {}, {0, 0, v, 3}, 4}", -9);
+        assert!(has_zero_loop(&program));
+        assert_eq!(list, expected);
+
+        // A zero that is only known when running is stored like any value
+        expected[14] = 0;
+        assert_eq!(elements("{{1, 2}, {}, {0, 0, v, 3}, 4}", 0).1, expected);
+
+        let (program, list) = elements("{}", 5);
+        assert!(has_zero_loop(&program));
+        assert_eq!(list, vec![0; 24]);
+    }
+
+    #[test]
+    fn few_zeros_are_stored_one_by_one() {
+        // 8 given, 16 implicit zeros: not more than ZERO_LOOP_MIN
+        let init = "{1, 2, 3, 4, 5, 6, 7, v}";
+        let (program, list) = elements(init, 8);
+        assert!(!has_zero_loop(&program));
+        let mut expected: Vec<i32> = (1..=8).collect();
+        expected.resize(24, 0);
+        assert_eq!(list, expected);
+
+        // One more zero and it is cleared by the loop
+        let (program, list) = elements("{1, 2, 3, 4, 5, 6, 7}", 8);
+        assert!(has_zero_loop(&program));
+        expected[7] = 0;
+        assert_eq!(list, expected);
+    }
+}
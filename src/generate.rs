@@ -1,6 +1,8 @@
 /* Uses */
-use std::collections::HashMap;
-use crate::ast;
+use std::collections::{HashMap, HashSet};
+use crate::opt::dom::DomTree;
+use crate::opt::loops::LoopInfo;
+use crate::strength;
 use koopa::ir::*;
 
 /* Parse Binary into risc32 text (instruction text, final register) */
@@ -75,14 +77,53 @@ fn parse_binary(op: BinaryOp) -> String {
     final_str
 }
 
+/* Get the value of an integer operand */
+fn get_integer(value: Value, func_data: &FunctionData) -> Option<i32> {
+    match func_data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
 /* Get offset */
 fn get_offset(offset: usize, text: &mut String) -> String {
-
+    // Fits in the 12-bit immediate of lw/sw
+    if offset < 2048 {
+        return offset.to_string() + "(t3)";
+    }
     *text += &("li t4, ".to_string() + &offset.to_string() + "\n");
     *text += "add t4, t3, t4\n";
     "0(t4)".to_string()
 }
 
+/* Weight of the stack slot of every value: its definition and uses, weighted by loop depth
+ * There is no register allocator, every value lives in a slot. The weights
+ * only order the slots, so the hottest get offsets below 2048 that fit in the
+ * immediate of lw/sw.
+ */
+fn slot_weights(func_data: &FunctionData) -> HashMap<Value, u64> {
+    let dom = DomTree::new(func_data);
+    let loop_info = LoopInfo::new(func_data, &dom);
+    let freq = |inst: Value| {
+        let depth = func_data.layout().parent_bb(inst).map_or(0, |bb| loop_info.depth(bb));
+        10_u64.pow(depth.min(8) as u32)
+    };
+    let mut weights = HashMap::new();
+    // Arguments are stored once in the entry
+    for &param in func_data.params() {
+        let uses: u64 = func_data.dfg().value(param).used_by().iter().map(|&user| freq(user)).sum();
+        weights.insert(param, 1 + uses);
+    }
+    for (_, node) in func_data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            let data = func_data.dfg().value(inst);
+            let uses: u64 = data.used_by().iter().map(|&user| freq(user)).sum();
+            weights.insert(inst, freq(inst) + uses);
+        }
+    }
+    weights
+}
+
 fn load_value(reg: String, value: Value, func_data: &FunctionData, sp_delta: usize, pos: &HashMap<Value, usize>, _program: &Program) -> String {
     let in_func = func_data.dfg().values().get(&value).is_some();
     let kind = if in_func {
@@ -95,16 +136,12 @@ fn load_value(reg: String, value: Value, func_data: &FunctionData, sp_delta: usi
         ValueKind::Integer(int) => {
             "li ".to_string() + &reg + ", " + &int.value().to_string() + "\n"
         },
-        ValueKind::FuncArgRef(arg) => {
-            let i = arg.index();
-            if i < 8 {
-                "mv ".to_string() + &reg + ", a" + &i.to_string() + "\n"
-            }else {
-                let offset = sp_delta + (i - 8) * 4;
-                "li t4, ".to_string() + &offset.to_string() + "\n" + 
-                "add t4, sp, t4\n" +
-                &"lw ".to_string() + &reg + ", 0(t4)\n"
-            }
+        // Arguments past a7 stay in the caller's frame, the others have a slot
+        ValueKind::FuncArgRef(arg) if arg.index() >= 8 => {
+            let offset = sp_delta + (arg.index() - 8) * 4;
+            "li t4, ".to_string() + &offset.to_string() + "\n" + 
+            "add t4, sp, t4\n" +
+            "lw " + &reg + ", 0(t4)\n"
         },
         _ => {
             let mut text = String::new();
@@ -114,12 +151,23 @@ fn load_value(reg: String, value: Value, func_data: &FunctionData, sp_delta: usi
     }
 }
 
-fn check_for_bb(bb: BasicBlock, program: &Program, func_data: &FunctionData, sp_delta: usize, pos: &HashMap<Value, usize>, global_var: &HashMap<Value, i32>, bb_count: &mut usize, check: &mut HashMap<BasicBlock, usize>) -> (usize, String) {
+/* Local label of a basic block, with its source name when it has one */
+fn bb_label(func_data: &FunctionData, bb: BasicBlock, id: usize) -> String {
+    let mut label = ".L".to_string() + &func_data.name()[1..] + "_" + &id.to_string();
+    if let Some(name) = func_data.dfg().bb(bb).name() {
+        label += "_";
+        label += &name[1..].replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    }
+    label
+}
+
+#[allow(clippy::too_many_arguments)]
+fn check_for_bb(bb: BasicBlock, program: &Program, func_data: &FunctionData, sp_delta: usize, pos: &HashMap<Value, usize>, global_var: &HashMap<Value, String>, bb_count: &mut usize, check: &mut HashMap<BasicBlock, usize>) -> (usize, String) {
     match check.get(&bb) {
         None => {
             *bb_count += 1;
             check.insert(bb, *bb_count);
-            (*bb_count, bb_gen_riscv32(bb, &program, func_data, sp_delta, pos, global_var, bb_count, check))
+            (*bb_count, bb_gen_riscv32(bb, program, func_data, sp_delta, pos, global_var, bb_count, check))
         },
         Some(&id) => {
             (id, String::new())
@@ -127,9 +175,9 @@ fn check_for_bb(bb: BasicBlock, program: &Program, func_data: &FunctionData, sp_
     }
 }
 
-fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, sp_delta: usize, pos: &HashMap<Value, usize>, global_var: &HashMap<Value, i32>, bb_count: &mut usize, check: &mut HashMap<BasicBlock, usize>) -> String {
-    let prefix = &(".L".to_string() + &func_data.name()[1..]);
-    let mut text = prefix.to_string() + &bb_count.to_string() + ":\n";
+#[allow(clippy::too_many_arguments)]
+fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, sp_delta: usize, pos: &HashMap<Value, usize>, global_var: &HashMap<Value, String>, bb_count: &mut usize, check: &mut HashMap<BasicBlock, usize>) -> String {
+    let mut text = bb_label(func_data, bb, *bb_count) + ":\n";
     let node = func_data.layout().bbs().node(&bb).unwrap();
 
     for &inst in node.insts().keys() {
@@ -155,12 +203,14 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                     let get_ptr = matches!(func_data.dfg().value(dest).kind(), ValueKind::GetElemPtr(_)) || matches!(func_data.dfg().value(dest).kind(), ValueKind::GetPtr(_));
                     let offset = get_offset(offset, &mut text);
                     if get_ptr {
-                        text += "lw t4, 0(t4)\n";
+                        text += &("lw t4, ".to_string() + &offset + "\n");
+                        text += "sw t0, 0(t4)\n";
+                    }else {
+                        text += &("sw t0, ".to_string() + &offset + "\n");
                     }
-                    text += &("sw t0, ".to_string() + &offset + "\n");
                 }else {
                     // Global
-                    text += &("la t5, gvar".to_string() + &global_var[&dest].to_string() + "\n");
+                    text += &("la t5, ".to_string() + &global_var[&dest] + "\n");
                     text += "sw t0, 0(t5)\n";
                 }
             },
@@ -176,23 +226,37 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 }else {
                     // panic!("Load not found");
                     // Global
-                    text += &("la t5, gvar".to_string() + &global_var[&src].to_string() + "\n");
+                    text += &("la t5, ".to_string() + &global_var[&src] + "\n");
                     text += "lw t0, 0(t5)\n";
                 }
                 let offset = get_offset(pos[&inst], &mut text);
                 text += &("sw t0, ".to_string() + &offset + "\n");
             },
             ValueKind::Binary(bin) => {
-                text += &load_value("t0".to_string(), bin.lhs(), func_data, sp_delta, pos, program);
-                text += &load_value("t1".to_string(), bin.rhs(), func_data, sp_delta, pos, program);
-                text += &parse_binary(bin.op());
+                // Constant operand: try strength reduction first
+                let reduced = match (get_integer(bin.lhs(), func_data), get_integer(bin.rhs(), func_data)) {
+                    (_, Some(c)) => strength::reduce_binary(bin.op(), c).map(|s| (bin.lhs(), s)),
+                    (Some(c), None) if bin.op() == BinaryOp::Mul => strength::reduce_binary(bin.op(), c).map(|s| (bin.rhs(), s)),
+                    _ => None,
+                };
+                match reduced {
+                    Some((operand, reduced_text)) => {
+                        text += &load_value("t0".to_string(), operand, func_data, sp_delta, pos, program);
+                        text += &reduced_text;
+                    }
+                    None => {
+                        text += &load_value("t0".to_string(), bin.lhs(), func_data, sp_delta, pos, program);
+                        text += &load_value("t1".to_string(), bin.rhs(), func_data, sp_delta, pos, program);
+                        text += &parse_binary(bin.op());
+                    }
+                }
                 let offset = get_offset(pos[&inst], &mut text);
                 text += &("sw t2, ".to_string() + &offset + "\n");
             },
             ValueKind::Jump(jump) => {
                 let target = jump.target();
                 let (target_id, new_text) = check_for_bb(target, program, func_data, sp_delta, pos, global_var, bb_count, check);
-                text += &("j ".to_string() + prefix + &target_id.to_string() + "\n");
+                text += &("j ".to_string() + &bb_label(func_data, target, target_id) + "\n");
                 text += &new_text;
             },
             ValueKind::Branch(branch) => {
@@ -203,8 +267,8 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 let (true_id, true_text) = check_for_bb(true_bb, program, func_data, sp_delta, pos, global_var, bb_count, check);
                 let (false_id, false_text) = check_for_bb(false_bb, program, func_data, sp_delta, pos, global_var, bb_count, check);
 
-                text += &("bnez t0, ".to_string() + prefix + &true_id.to_string() + "\n");
-                text += &("j ".to_string() + prefix + &false_id.to_string() + "\n");
+                text += &("bnez t0, ".to_string() + &bb_label(func_data, true_bb, true_id) + "\n");
+                text += &("j ".to_string() + &bb_label(func_data, false_bb, false_id) + "\n");
                 text += &true_text;
                 text += &false_text;
             },
@@ -265,7 +329,7 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 let in_func = func_data.dfg().values().get(&src).is_some();
                 let mut alloc = false;
                 let ty = if in_func {
-                    // println!("src_kind: {:?}", func_data.dfg().value(src).kind());
+                    crate::trace!(Isel, Trace, "getelemptr src kind: {:?}", func_data.dfg().value(src).kind());
                     if let ValueKind::Alloc(_) = func_data.dfg().value(src).kind() {
                         alloc = true;
                     }
@@ -283,7 +347,7 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                     panic!()
                 };
                 // Todo: bad calling dfg().value(...) in load_value
-                // println!("src_size: {}", src_size);
+                crate::trace!(Isel, Trace, "getelemptr elem size: {}", src_size);
                 text += &load_value("t0".to_string(), gep.index(), func_data, sp_delta, pos, program);
                 text += "li t1, ";
                 text += &src_size.to_string();
@@ -291,14 +355,18 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 text += "mul t0, t0, t1\n";
                 // 2. Position array
                 // text += &load_value("t1".to_string(), gep.src(), func_data, sp_delta, pos, program);
-                if in_func {
-                    text += &("li t1, ".to_string() + &pos[&src].to_string() + "\n");
-                    text += "add t1, t3, t1\n";
-                    if !alloc {
-                        text += "lw t1, 0(t1)\n";
+                if in_func && !alloc {
+                    // Pointer value: spilled result or argument
+                    text += &load_value("t1".to_string(), src, func_data, sp_delta, pos, program);
+                }else if in_func {
+                    if pos[&src] < 2048 {
+                        text += &("addi t1, t3, ".to_string() + &pos[&src].to_string() + "\n");
+                    }else {
+                        text += &("li t1, ".to_string() + &pos[&src].to_string() + "\n");
+                        text += "add t1, t3, t1\n";
                     }
                 }else {
-                    text += &("la t1, gvar".to_string() + &global_var[&src].to_string() + "\n");
+                    text += &("la t1, ".to_string() + &global_var[&src] + "\n");
                 }
                 // 3. Calc absolute addr
                 text += "add t1, t1, t0\n";
@@ -314,7 +382,7 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 let in_func = func_data.dfg().values().get(&src).is_some();
                 let mut alloc = false;
                 let ty = if in_func {
-                    println!("src_kind: {:?}", func_data.dfg().value(src).kind());
+                    crate::trace!(Isel, Trace, "getptr src kind: {:?}", func_data.dfg().value(src).kind());
                     if let ValueKind::Alloc(_) = func_data.dfg().value(src).kind() {
                         alloc = true;
                     }
@@ -322,14 +390,14 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 }else {
                     program.borrow_value(src).ty().clone()
                 };
-                println!("gp ty: {:#?}", ty);
+                crate::trace!(Isel, Trace, "getptr src type: {}", ty);
                 let src_size = if let TypeKind::Pointer(ptr) = ty.kind() {
                     ptr.size()
                 }else {
                     panic!()
                 };
                 // Todo: bad calling dfg().value(...) in load_value
-                println!("src_size: {}", src_size);
+                crate::trace!(Isel, Trace, "getptr stride: {}", src_size);
                 text += &load_value("t0".to_string(), gp.index(), func_data, sp_delta, pos, program);
                 text += "li t1, ";
                 text += &src_size.to_string();
@@ -337,14 +405,18 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
                 text += "mul t0, t0, t1\n";
                 // 2. Position array
                 // text += &load_value("t1".to_string(), gep.src(), func_data, sp_delta, pos, program);
-                if in_func {
-                    text += &("li t1, ".to_string() + &pos[&src].to_string() + "\n");
-                    text += "add t1, t3, t1\n";
-                    if !alloc {
-                        text += "lw t1, 0(t1)\n";
+                if in_func && !alloc {
+                    // Pointer value: spilled result or argument
+                    text += &load_value("t1".to_string(), src, func_data, sp_delta, pos, program);
+                }else if in_func {
+                    if pos[&src] < 2048 {
+                        text += &("addi t1, t3, ".to_string() + &pos[&src].to_string() + "\n");
+                    }else {
+                        text += &("li t1, ".to_string() + &pos[&src].to_string() + "\n");
+                        text += "add t1, t3, t1\n";
                     }
                 }else {
-                    text += &("la t1, gvar".to_string() + &global_var[&src].to_string() + "\n");
+                    text += &("la t1, ".to_string() + &global_var[&src] + "\n");
                 }
                 // 3. Calc absolute addr
                 text += "add t1, t1, t0\n";
@@ -362,50 +434,118 @@ fn bb_gen_riscv32(bb: BasicBlock, program: &Program, func_data: &FunctionData, s
     text
 }
 
-fn gen_global_alloc(value: Value, program: &Program, text: &mut String) {
+fn gen_global_alloc(value: Value, program: &Program, text: &mut String, zeros: &mut usize) {
     match program.borrow_value(value).kind() {
         ValueKind::GlobalAlloc(alloc) => {
-            gen_global_alloc(alloc.init(), program, text);
+            gen_global_alloc(alloc.init(), program, text, zeros);
+            flush_zeros(text, zeros);
+        }
+        ValueKind::Integer(int) if int.value() == 0 => {
+            *zeros += 4;
         }
         ValueKind::Integer(int) => {
+            flush_zeros(text, zeros);
             *text += ".word ";
             *text += &int.value().to_string();
             *text += "\n";
         }
+        ValueKind::ZeroInit(_) => {
+            *zeros += program.borrow_value(value).ty().size();
+        }
         ValueKind::Aggregate(agg) => {
             for v in agg.elems() {
-                gen_global_alloc(*v, program, text);
+                gen_global_alloc(*v, program, text, zeros);
             }
         }
         _ => unreachable!()
     }
 }
 
+/* Emit a run of zero bytes as one directive */
+fn flush_zeros(text: &mut String, zeros: &mut usize) {
+    if *zeros > 0 {
+        *text += ".zero ";
+        *text += &zeros.to_string();
+        *text += "\n";
+        *zeros = 0;
+    }
+}
+
+/* Initializer has no non-zero element */
+fn is_zero_init(value: Value, program: &Program) -> bool {
+    match program.borrow_value(value).kind() {
+        ValueKind::GlobalAlloc(alloc) => is_zero_init(alloc.init(), program),
+        ValueKind::Integer(int) => int.value() == 0,
+        ValueKind::ZeroInit(_) => true,
+        ValueKind::Aggregate(agg) => agg.elems().iter().all(|&v| is_zero_init(v, program)),
+        _ => false
+    }
+}
+
+/* Functions of the runtime library, linked even when not declared */
+const RUNTIME_FUNCS: [&str; 8] = ["getint", "getch", "getarray", "putint", "putch", "putarray", "starttime", "stoptime"];
+
+fn is_register(name: &str) -> bool {
+    let numbered = |prefix: &str, max: u32| {
+        match name.strip_prefix(prefix) {
+            Some(n) => n.parse::<u32>().is_ok_and(|i| i <= max && i.to_string() == n),
+            None => false,
+        }
+    };
+    matches!(name, "zero" | "ra" | "sp" | "gp" | "tp" | "fp") || numbered("x", 31) || numbered("t", 6) || numbered("s", 11) || numbered("a", 7)
+}
+
+/* Assembly label of a global: its source name, renamed if it would clash */
+fn global_label(name: &Option<String>, index: usize, used: &mut HashSet<String>) -> String {
+    let base = match name {
+        Some(name) => name[1..].to_string(),
+        None => "gvar".to_string() + &index.to_string(),
+    };
+    let mut label = base.clone();
+    let mut suffix = 0;
+    while used.contains(&label) || is_register(&label) {
+        label = base.clone() + "_g" + &suffix.to_string();
+        suffix += 1;
+    }
+    used.insert(label.clone());
+    label
+}
+
 /* Generate riscv32 code */
-pub fn gen_riscv32(ast: ast::Program) -> String {
+pub fn gen_riscv32(program: &Program) -> String {
     let mut text = String::new();
-    let program = ast.dump();
 
     Type::set_ptr_size(4);
-    let mut global_count = 0;
     let mut global_var = HashMap::new();
     // Global alloc
-    for &inst in program.inst_layout() {
-        global_var.insert(inst, global_count);
-        text += ".data\n";
-        text += ".globl gvar";
-        text += &global_count.to_string();
-        text += "\n";
-        text += "gvar";
-        text += &global_count.to_string();
+    let mut used: HashSet<String> = program.func_layout().iter().map(|&f| program.func(f).name()[1..].to_string()).collect();
+    used.extend(RUNTIME_FUNCS.iter().map(|f| f.to_string()));
+    for (global_count, &inst) in program.inst_layout().iter().enumerate() {
+        let name = global_label(program.borrow_value(inst).name(), global_count, &mut used);
+        global_var.insert(inst, name.clone());
+        let size = match program.borrow_value(inst).ty().kind() {
+            TypeKind::Pointer(base) => base.size(),
+            _ => unreachable!()
+        };
+        // All-zero globals go to .bss and take no space in the object file
+        let zero = is_zero_init(inst, program);
+        text += if zero { ".bss\n" } else { ".data\n" };
+        text += &(".globl ".to_string() + &name + "\n");
+        text += ".align 2\n";
+        text += &(".type ".to_string() + &name + ", @object\n");
+        text += &(".size ".to_string() + &name + ", " + &size.to_string() + "\n");
+        text += &name;
         text += ":\n";
-        gen_global_alloc(inst, &program, &mut text);
-        global_count += 1;
+        if zero {
+            text += &(".zero ".to_string() + &size.to_string() + "\n");
+        }else {
+            gen_global_alloc(inst, program, &mut text, &mut 0);
+        }
     }
     // Function
     for &func in program.func_layout() {
         let func_data = program.func(func);
-        if let None = func_data.layout().entry_bb() {
+        if func_data.layout().entry_bb().is_none() {
             continue;
         }
 
@@ -415,23 +555,32 @@ pub fn gen_riscv32(ast: ast::Program) -> String {
         text += ":\n";
 
         // Calc stack placement
-        let mut sp_delta = 0 as usize;
-        let mut call_delta = 0 as usize;
+        // Heavier scalar slots get the low offsets, which fit in an immediate
+        let weights = slot_weights(func_data);
+        let mut sp_delta = 0_usize;
+        let mut call_delta = 0_usize;
         let mut pos = HashMap::new();
+        // a0 - a7 are clobbered by calls, so register arguments get a slot too
+        let mut scalars: Vec<Value> = func_data.params().iter().take(8).copied().collect();
+        let mut arrays = vec![];
         for (&_bb, node) in func_data.layout().bbs() {
             for &inst in node.insts().keys() {
                 let value_data = func_data.dfg().value(inst);
                 // Only visit alloc, load, binary
                 if !value_data.ty().is_unit() {
-                    pos.insert(inst, sp_delta);
-                    if let ValueKind::Alloc(_) = value_data.kind() {
+                    let size = if let ValueKind::Alloc(_) = value_data.kind() {
                         if let TypeKind::Pointer(ptr) = value_data.ty().kind() {
-                            sp_delta += ptr.size();
+                            ptr.size()
                         }else {
                             panic!();
                         }
                     }else {
-                        sp_delta += 4;
+                        4
+                    };
+                    if size == 4 {
+                        scalars.push(inst);
+                    }else {
+                        arrays.push((inst, size));
                     }
                 }else if let ValueKind::Call(call) = value_data.kind() {
                     let val = call.args().len() * 4;
@@ -441,24 +590,84 @@ pub fn gen_riscv32(ast: ast::Program) -> String {
                 }
             }
         }
-        println!("sp_delta: {}", sp_delta);
-        sp_delta = (sp_delta + 4 + 15) / 16 * 16;
-        call_delta = (call_delta + 15) / 16 * 16;
+        // Stable sort keeps layout order for equal weights
+        scalars.sort_by_key(|inst| std::cmp::Reverse(weights[inst]));
+        for inst in scalars {
+            pos.insert(inst, sp_delta);
+            sp_delta += 4;
+        }
+        for (inst, size) in arrays {
+            pos.insert(inst, sp_delta);
+            sp_delta += size;
+        }
+        crate::trace!(Frame, Debug, "frame of {} bytes before alignment", sp_delta);
+        sp_delta = (sp_delta + 4).div_ceil(16) * 16;
+        call_delta = call_delta.div_ceil(16) * 16;
         // sp_delta = 1536;
         // call_delta = 512;
         let delta = sp_delta + call_delta + 128;
+        crate::trace!(Frame, Info, "{}: locals {} bytes, call area {} bytes, frame {} bytes", &func_data.name()[1..], sp_delta, call_delta, delta);
         text += &("li t3, -".to_string() + &sp_delta.to_string() + "\n");
         text += "add t3, sp, t3\n";
         text += &("li t0, -".to_string() + &delta.to_string() + "\n");
         text += "add sp, sp, t0\n";
         // store ra
         text += "sw ra, -4(t3)\n";
+        for (i, param) in func_data.params().iter().take(8).enumerate() {
+            let offset = get_offset(pos[param], &mut text);
+            text += &("sw a".to_string() + &i.to_string() + ", " + &offset + "\n");
+        }
         
 
         // Start from entry
         let mut check = HashMap::new();
-        let mut bb_count = 0 as usize;
-        text += &bb_gen_riscv32(func_data.layout().entry_bb().unwrap(), &program, func_data, delta, &pos, &global_var, &mut bb_count, &mut check);
+        let mut bb_count = 0_usize;
+        text += &bb_gen_riscv32(func_data.layout().entry_bb().unwrap(), program, func_data, delta, &pos, &global_var, &mut bb_count, &mut check);
     }
     text
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Section and directives of each global, in order */
+    fn globals(source: &str) -> Vec<(String, String, Vec<String>)> {
+        let program = crate::lower(&crate::parse(source).unwrap()).ok().unwrap();
+        let text = gen_riscv32(&program);
+        let mut list: Vec<(String, String, Vec<String>)> = vec![];
+        let mut section = String::new();
+        for line in text[..text.find(".text").unwrap()].lines() {
+            if line == ".data" || line == ".bss" {
+                section = line.to_string();
+            }else if let Some(name) = line.strip_prefix(".globl ") {
+                list.push((section.clone(), name.to_string(), vec![]));
+            }else {
+                list.last_mut().unwrap().2.push(line.to_string());
+            }
+        }
+        list
+    }
+
+    #[test]
+    fn zero_globals_go_to_bss() {
+        let source = "int z[4][8];\nint s;\nint e[2][2] = {{0, 0}, {}};\nint big[1000][1000];\nint d[3] = {1};\nint m[6] = {1, 0, 0, 0, 0, 2};\nint t = 7;\nint main() { return z[1][2] + s + e[1][1] + big[9][9] + d[0] + m[5] + t; }\n";
+        let list = globals(source);
+        let expect = |name: &str, section: &str, size: usize, data: &[&str]| {
+            let mut lines = vec![".align 2".to_string(), ".type ".to_string() + name + ", @object", ".size ".to_string() + name + ", " + &size.to_string(), name.to_string() + ":"];
+            lines.extend(data.iter().map(|line| line.to_string()));
+            (section.to_string(), name.to_string(), lines)
+        };
+        assert_eq!(list, vec![
+            expect("z", ".bss", 128, &[".zero 128"]),
+            expect("s", ".bss", 4, &[".zero 4"]),
+            expect("e", ".bss", 16, &[".zero 16"]),
+            // One line however large
+            expect("big", ".bss", 4000000, &[".zero 4000000"]),
+            // Runs of zeros inside data are one directive each
+            expect("d", ".data", 12, &[".word 1", ".zero 8"]),
+            expect("m", ".data", 24, &[".word 1", ".zero 16", ".word 2"]),
+            expect("t", ".data", 4, &[".word 7"]),
+        ]);
+    }
+}
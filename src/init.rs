@@ -0,0 +1,150 @@
+/* Uses */
+use std::collections::BTreeMap;
+use std::fmt;
+use crate::ast;
+
+/* Initializer normalized to the shape of its variable
+ * Elements are keyed by row-major index into the flattened array, those
+ * not present are zero.
+ */
+#[derive(Debug, Clone)]
+pub struct Init<T> {
+    pub dims: Vec<usize>,
+    pub elems: BTreeMap<usize, T>,
+}
+
+/* Initializer that doesn't fit its variable */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InitError {
+    // More elements than the (sub)array holds
+    Excess,
+    // Array initialized by a single expression
+    NotList,
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitError::Excess => write!(f, "excess elements in initializer"),
+            InitError::NotList => write!(f, "array initializer must be a list"),
+        }
+    }
+}
+
+impl Init<ast::Exp> {
+    /* Place each expression of `init_val` following the brace rules of SysY */
+    pub fn new(init_val: ast::InitVal, dims: &[usize]) -> Result<Init<ast::Exp>, InitError> {
+        let mut elems = BTreeMap::new();
+        match init_val {
+            ast::InitVal::Exp(exp) => {
+                if !dims.is_empty() {
+                    return Err(InitError::NotList);
+                }
+                elems.insert(0, exp);
+            }
+            ast::InitVal::List(list) => fill(list, dims, 0, &mut elems)?,
+        }
+        Ok(Init { dims: dims.to_vec(), elems })
+    }
+}
+
+impl<T> Init<T> {
+    /* Number of scalars in the variable */
+    pub fn len(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /* Same layout with every element mapped */
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> Init<U> {
+        Init { dims: self.dims, elems: self.elems.into_iter().map(|(i, elem)| (i, f(elem))).collect() }
+    }
+
+    /* Same layout with every element mapped, stopping at the first error */
+    pub fn try_map<U, E, F: FnMut(T) -> Result<U, E>>(self, mut f: F) -> Result<Init<U>, E> {
+        let elems = self.elems.into_iter().map(|(i, elem)| Ok((i, f(elem)?))).collect::<Result<_, E>>()?;
+        Ok(Init { dims: self.dims, elems })
+    }
+}
+
+/* Fill the (sub)array of shape `dims` starting at flat index `base` */
+#[allow(clippy::vec_box)]
+fn fill(list: Vec<Box<ast::InitVal>>, dims: &[usize], base: usize, elems: &mut BTreeMap<usize, ast::Exp>) -> Result<(), InitError> {
+    let len: usize = dims.iter().product();
+    let mut pos = 0;
+    for init_val in list {
+        if pos >= len {
+            return Err(InitError::Excess);
+        }
+        match *init_val {
+            ast::InitVal::Exp(exp) => {
+                elems.insert(base + pos, exp);
+                pos += 1;
+            }
+            ast::InitVal::List(sub) => {
+                // A nested list fills the largest sub-array aligned at the current position,
+                // or a single scalar when it sits in the middle of a row
+                let sub_dims = (1..=dims.len()).map(|i| &dims[i..]).find(|d| pos % d.iter().product::<usize>() == 0).unwrap_or(&[]);
+                fill(sub, sub_dims, base + pos, elems)?;
+                pos += sub_dims.iter().product::<usize>();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Initializer written as `text` */
+    fn init_val(text: &str) -> ast::InitVal {
+        let program = crate::parse(&("int a = ".to_string() + text + ";")).unwrap();
+        match program.list.into_iter().next() {
+            Some(Err(ast::Decl::Var(decl))) => decl.var_def_list.into_iter().next().unwrap().init_val.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    /* Placed elements as (flat index, value) */
+    fn place(text: &str, dims: &[usize]) -> Result<Vec<(usize, i32)>, InitError> {
+        let init = Init::new(init_val(text), dims)?.map(|exp| match *exp.core {
+            ast::ExpCore::Single(value) => value,
+            _ => unreachable!(),
+        });
+        Ok(init.elems.into_iter().collect())
+    }
+
+    #[test]
+    fn nested_list_fills_a_row() {
+        // In the middle of a row {2} is a single scalar, as in C
+        assert_eq!(place("{1, {2}, 3}", &[2, 3]), Ok(vec![(0, 1), (1, 2), (2, 3)]));
+        // At a row boundary it fills the whole row
+        assert_eq!(place("{1, 2, 3, {4}}", &[2, 3]), Ok(vec![(0, 1), (1, 2), (2, 3), (3, 4)]));
+        assert_eq!(place("{{1, 2}, {3}}", &[2, 3]), Ok(vec![(0, 1), (1, 2), (3, 3)]));
+        assert_eq!(place("{1, 2, 3, 4}", &[2, 3]), Ok(vec![(0, 1), (1, 2), (2, 3), (3, 4)]));
+    }
+
+    #[test]
+    fn empty_list_is_all_zero() {
+        assert_eq!(place("{}", &[2, 3, 4]), Ok(vec![]));
+        assert_eq!(place("{{}, {}}", &[2, 3]), Ok(vec![]));
+    }
+
+    #[test]
+    fn excess_elements() {
+        assert_eq!(place("{1, 2, 3, 4, 5, 6, 7}", &[2, 3]), Err(InitError::Excess));
+        assert_eq!(place("{{1, 2, 3, 4}}", &[2, 3]), Err(InitError::Excess));
+        assert_eq!(place("{{1}, {2}, {3}}", &[2, 3]), Err(InitError::Excess));
+        assert_eq!(place("{1, 2, 3, {4}, 5}", &[2, 3]), Err(InitError::Excess));
+    }
+
+    #[test]
+    fn array_needs_a_list() {
+        assert_eq!(place("1", &[2]), Err(InitError::NotList));
+        assert_eq!(place("1", &[]), Ok(vec![(0, 1)]));
+    }
+}
@@ -0,0 +1,516 @@
+/* Uses */
+use std::fmt;
+use crate::ast;
+
+/* Json value, objects keep their field order */
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    /* Object tagged with the name of the variant it came from */
+    fn node(kind: &str, mut fields: Vec<(&str, Json)>) -> Json {
+        fields.insert(0, ("kind", Json::Str(kind.to_string())));
+        Json::object(fields)
+    }
+
+    pub fn str(s: &str) -> Json {
+        Json::Str(s.to_string())
+    }
+
+    fn list<T: ToJson>(items: &[T]) -> Json {
+        Json::Array(items.iter().map(ToJson::to_json).collect())
+    }
+
+    fn option<T: ToJson>(item: &Option<T>) -> Json {
+        item.as_ref().map_or(Json::Null, ToJson::to_json)
+    }
+
+    /* Field `key` of an object */
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /* Value at a path of object keys */
+    pub fn at(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, key| json.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Json::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /* Read json text, numbers with a fraction or exponent are truncated */
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut reader = Reader { chars: text.chars().collect(), pos: 0 };
+        let json = reader.value()?;
+        reader.space();
+        if reader.pos < reader.chars.len() {
+            return Err(reader.error("trailing characters"));
+        }
+        Ok(json)
+    }
+
+    /* Indented text, two spaces per level */
+    pub fn pretty(&self) -> String {
+        let mut text = String::new();
+        self.write(&mut text, 0);
+        text
+    }
+
+    fn write(&self, text: &mut String, indent: usize) {
+        let pad = |n: usize| "  ".repeat(n);
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                *text += "[\n";
+                for (i, item) in items.iter().enumerate() {
+                    *text += &pad(indent + 1);
+                    item.write(text, indent + 1);
+                    *text += if i + 1 < items.len() { ",\n" } else { "\n" };
+                }
+                *text += &(pad(indent) + "]");
+            }
+            Json::Object(fields) if !fields.is_empty() => {
+                *text += "{\n";
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    *text += &(pad(indent + 1) + &escape(key) + ": ");
+                    value.write(text, indent + 1);
+                    *text += if i + 1 < fields.len() { ",\n" } else { "\n" };
+                }
+                *text += &(pad(indent) + "}");
+            }
+            _ => *text += &self.to_string(),
+        }
+    }
+}
+
+/* Compact text */
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(i) => write!(f, "{}", i),
+            Json::Str(s) => write!(f, "{}", escape(s)),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", escape(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/* Recursive descent over json text */
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn error(&self, message: &str) -> String {
+        message.to_string() + " at character " + &self.pos.to_string()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or_else(|| self.error("unexpected end"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for c in word.chars() {
+            if self.next()? != c {
+                return Err(self.error(&("expected `".to_string() + word + "`")));
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.space();
+        match self.peek() {
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::Str),
+            Some('[') => {
+                self.pos += 1;
+                let mut items = vec![];
+                self.space();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.space();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Ok(Json::Array(items)),
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = vec![];
+                self.space();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.space();
+                    let key = self.string()?;
+                    self.space();
+                    self.expect(":")?;
+                    fields.push((key, self.value()?));
+                    self.space();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Ok(Json::Object(fields)),
+                        _ => return Err(self.error("expected `,` or `}`")),
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' || c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse::<i64>() {
+            Ok(i) => Ok(Json::Int(i)),
+            Err(_) => text.parse::<f64>().map(|f| Json::Int(f as i64)).map_err(|_| self.error("invalid number")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        if self.next()? != '"' {
+            return Err(self.error("expected a string"));
+        }
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(text),
+                '\\' => match self.next()? {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex()?;
+                        // A surrogate pair spells one character outside the basic plane
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.hex()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + low.wrapping_sub(0xdc00);
+                        }
+                        text.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    c => text.push(c),
+                },
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next()?.to_digit(16).ok_or_else(|| self.error("invalid escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
+
+/* Quoted json string */
+fn escape(s: &str) -> String {
+    let mut text = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '"' => text += "\\\"",
+            '\\' => text += "\\\\",
+            '\n' => text += "\\n",
+            '\r' => text += "\\r",
+            '\t' => text += "\\t",
+            c if (c as u32) < 0x20 => text += &format!("\\u{:04x}", c as u32),
+            c => text.push(c),
+        }
+    }
+    text + "\""
+}
+
+/* Conversion of ast nodes */
+pub trait ToJson {
+    fn to_json(&self) -> Json;
+}
+
+impl<T: ToJson> ToJson for Box<T> {
+    fn to_json(&self) -> Json {
+        (**self).to_json()
+    }
+}
+
+impl ToJson for ast::Program {
+    fn to_json(&self) -> Json {
+        let items = self.list.iter().map(|item| match item {
+            Ok(func_def) => func_def.to_json(),
+            Err(decl) => decl.to_json(),
+        }).collect();
+        Json::node("Program", vec![("items", Json::Array(items))])
+    }
+}
+
+impl ToJson for ast::FuncDef {
+    fn to_json(&self) -> Json {
+        let func_type = match self.func_type {
+            ast::FuncType::Int => "int",
+            ast::FuncType::Void => "void",
+        };
+        Json::node("FuncDef", vec![
+            ("type", Json::str(func_type)),
+            ("id", Json::str(&self.id)),
+            ("params", Json::list(&self.func_param_list)),
+            ("block", self.block.to_json()),
+        ])
+    }
+}
+
+impl ToJson for ast::FuncParam {
+    fn to_json(&self) -> Json {
+        // An array parameter has an omitted first dimension, the rest are listed
+        let dims = if self.1.is_empty() { Json::Null } else { Json::list(&self.1[1..]) };
+        Json::node("FuncParam", vec![("id", Json::str(&self.0)), ("dims", dims)])
+    }
+}
+
+impl ToJson for ast::Block {
+    fn to_json(&self) -> Json {
+        Json::node("Block", vec![("items", Json::list(&self.block_item_list))])
+    }
+}
+
+impl ToJson for ast::BlockItem {
+    fn to_json(&self) -> Json {
+        match self {
+            ast::BlockItem::Decl(decl, _) => decl.to_json(),
+            ast::BlockItem::Stmt(stmt, _) => stmt.to_json(),
+        }
+    }
+}
+
+impl ToJson for ast::Stmt {
+    fn to_json(&self) -> Json {
+        match self {
+            ast::Stmt::Assign(lval, exp) => Json::node("Assign", vec![("lval", lval.to_json()), ("exp", exp.to_json())]),
+            ast::Stmt::Exp(exp) => Json::node("ExpStmt", vec![("exp", exp.to_json())]),
+            ast::Stmt::Block(block) => block.to_json(),
+            ast::Stmt::Ret(exp, _) => Json::node("Return", vec![("exp", Json::option(exp))]),
+            ast::Stmt::If(stmt) => Json::node("If", vec![
+                ("cond", stmt.exp.to_json()),
+                ("then", stmt.then_stmt.to_json()),
+                ("else", Json::option(&stmt.else_stmt)),
+            ]),
+            ast::Stmt::While(exp, body) => Json::node("While", vec![("cond", exp.to_json()), ("body", body.to_json())]),
+            ast::Stmt::Continue(_) => Json::node("Continue", vec![]),
+            ast::Stmt::Break(_) => Json::node("Break", vec![]),
+            ast::Stmt::Blank => Json::node("Blank", vec![]),
+        }
+    }
+}
+
+impl ToJson for ast::Decl {
+    fn to_json(&self) -> Json {
+        match self {
+            ast::Decl::Const(decl) => Json::node("ConstDecl", vec![("defs", Json::list(&decl.const_def_list))]),
+            ast::Decl::Var(decl) => Json::node("VarDecl", vec![("defs", Json::list(&decl.var_def_list))]),
+        }
+    }
+}
+
+impl ToJson for ast::ConstDef {
+    fn to_json(&self) -> Json {
+        Json::node("ConstDef", vec![
+            ("id", Json::str(&self.id)),
+            ("dims", Json::list(&self.is_array)),
+            ("init", self.const_init_val.to_json()),
+        ])
+    }
+}
+
+impl ToJson for ast::VarDef {
+    fn to_json(&self) -> Json {
+        Json::node("VarDef", vec![
+            ("id", Json::str(&self.id)),
+            ("dims", Json::list(&self.is_array)),
+            ("init", Json::option(&self.init_val)),
+        ])
+    }
+}
+
+impl ToJson for ast::InitVal {
+    fn to_json(&self) -> Json {
+        match self {
+            ast::InitVal::Exp(exp) => exp.to_json(),
+            ast::InitVal::List(list) => Json::node("InitList", vec![("elems", Json::list(list))]),
+        }
+    }
+}
+
+impl ToJson for ast::Exp {
+    fn to_json(&self) -> Json {
+        match &*self.core {
+            ast::ExpCore::Binary(lhs, op, rhs) => Json::node("Binary", vec![
+                ("op", Json::Str(format!("{:?}", op).to_lowercase())),
+                ("lhs", lhs.to_json()),
+                ("rhs", rhs.to_json()),
+            ]),
+            ast::ExpCore::Single(num) => Json::node("Number", vec![("value", Json::Int(*num as i64))]),
+            ast::ExpCore::Ident(lval) => lval.to_json(),
+            ast::ExpCore::Call(id, args) => Json::node("Call", vec![("id", Json::str(id)), ("args", Json::list(args))]),
+        }
+    }
+}
+
+impl ToJson for ast::LVal {
+    fn to_json(&self) -> Json {
+        Json::node("LVal", vec![("id", Json::str(&self.id)), ("indices", Json::list(&self.is_array))])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(json: &Json) {
+        assert_eq!(Json::parse(&json.to_string()).as_ref(), Ok(json), "{}", json);
+        assert_eq!(Json::parse(&json.pretty()).as_ref(), Ok(json), "{}", json.pretty());
+    }
+
+    #[test]
+    fn strings_keep_every_character() {
+        for s in ["", "plain", "quote \" and \\ backslash", "lines\nand\ttabs\r", "\u{1}\u{1f}", "ünïcödé ✓ 𝄞"] {
+            round_trip(&Json::str(s));
+        }
+        assert_eq!(Json::str("a\"b\\c\n\u{1}").to_string(), r#""a\"b\\c\n\u0001""#);
+        // Escapes only other encoders produce
+        assert_eq!(Json::parse(r#""\/\b\fé𝄞""#), Ok(Json::str("/\u{8}\u{c}é𝄞")));
+        assert!(Json::parse(r#""\u12""#).is_err());
+        assert!(Json::parse(r#""open"#).is_err());
+    }
+
+    #[test]
+    fn nested_values() {
+        let json = Json::object(vec![
+            ("empty", Json::Array(vec![])),
+            ("none", Json::Object(vec![])),
+            ("list", Json::Array(vec![Json::Null, Json::Bool(true), Json::Bool(false), Json::Array(vec![Json::Int(1)])])),
+            ("deep", Json::object(vec![("a", Json::object(vec![("b", Json::str("c"))]))])),
+        ]);
+        round_trip(&json);
+        assert_eq!(json.at(&["deep", "a", "b"]).and_then(Json::as_str), Some("c"));
+        assert_eq!(json.get("list").and_then(Json::as_array).map(|items| items.len()), Some(4));
+        assert_eq!(Json::object(vec![("a", Json::Array(vec![])), ("b", Json::Int(1))]).pretty(), "{\n  \"a\": [],\n  \"b\": 1\n}");
+        assert_eq!(Json::parse(" { \"a\" : [ 1 , { } ] } "), Ok(Json::object(vec![("a", Json::Array(vec![Json::Int(1), Json::Object(vec![])]))])));
+        assert!(Json::parse("[1,]").is_err());
+        assert!(Json::parse("{\"a\" 1}").is_err());
+        assert!(Json::parse("[1] x").is_err());
+    }
+
+    #[test]
+    fn numbers() {
+        for i in [0, -1, 42, i32::MIN as i64, i64::MAX, i64::MIN] {
+            round_trip(&Json::Int(i));
+        }
+        assert_eq!(Json::parse("2.75"), Ok(Json::Int(2)));
+        assert_eq!(Json::parse("-1e3"), Ok(Json::Int(-1000)));
+        assert!(Json::parse("-").is_err());
+        assert!(Json::parse("1-2").is_err());
+    }
+
+    #[test]
+    fn ast_snapshot() {
+        let ast = crate::parse("int f(int p[][3]) {\n  int x = 1;\n  if (x) return -p[0][x]; else ;\n}\n").unwrap();
+        assert_eq!(ast.to_json().to_string(), concat!(
+            r#"{"kind":"Program","items":[{"kind":"FuncDef","type":"int","id":"f","#,
+            r#""params":[{"kind":"FuncParam","id":"p","dims":[{"kind":"Number","value":3}]}],"#,
+            r#""block":{"kind":"Block","items":["#,
+            r#"{"kind":"VarDecl","defs":[{"kind":"VarDef","id":"x","dims":[],"init":{"kind":"Number","value":1}}]},"#,
+            r#"{"kind":"If","cond":{"kind":"LVal","id":"x","indices":[]},"#,
+            r#""then":{"kind":"Return","exp":{"kind":"Binary","op":"sub","lhs":{"kind":"Number","value":0},"#,
+            r#""rhs":{"kind":"LVal","id":"p","indices":[{"kind":"Number","value":0},{"kind":"LVal","id":"x","indices":[]}]}}},"#,
+            r#""else":{"kind":"Blank"}}]}}]}"#,
+        ));
+    }
+}
@@ -0,0 +1,386 @@
+/* SysY compiler library
+ * Source goes through `parse`, `lower` to Koopa IR, `optimize`, then
+ * `emit_riscv`, `emit_koopa` or `emit_dot`. `Session` runs the whole pipeline with
+ * the settings in `Options` and keeps the diagnostics, along with
+ * snapshots of the program after the stages `DumpOptions` asks for.
+ */
+
+/* Uses */
+use std::collections::HashSet;
+use lalrpop_util::lalrpop_mod;
+use lalrpop_util::ParseError;
+use koopa::ir::Program;
+
+/* Lalrpop Generate */
+lalrpop_mod!(#[allow(clippy::all)] sysy);
+
+/* Module (Extern) */
+pub mod ast;
+pub mod diag;
+pub mod dot;
+pub mod dump;
+pub mod generate;
+pub mod init;
+pub mod json;
+pub mod lint;
+pub mod lsp;
+pub mod opt;
+pub mod strength;
+pub mod symbol;
+pub mod trace;
+pub mod ty;
+
+pub use dot::DotOptions;
+pub use diag::{Diagnostic, Diagnostics, Severity, WarningOptions};
+pub use opt::OptLevel;
+pub use symbol::SymbolTable;
+
+/* Parse SysY source into an ast */
+pub fn parse(source: &str) -> Result<ast::Program, Diagnostics> {
+    trace!(Parse, Info, "parsing {} bytes", source.len());
+    let result = sysy::ProgramParser::new().parse(source);
+    if let Ok(program) = &result {
+        trace!(Parse, Debug, "{} global items", program.list.len());
+    }
+    result.map_err(|err| {
+        let (start, end) = match &err {
+            ParseError::InvalidToken { location } => (*location, *location),
+            ParseError::UnrecognizedEOF { location, .. } => (*location, *location),
+            ParseError::UnrecognizedToken { token: (start, _, end), .. } => (*start, *end),
+            ParseError::ExtraToken { token: (start, _, end) } => (*start, *end),
+            ParseError::User { .. } => (0, 0),
+        };
+        let message = match &err {
+            ParseError::InvalidToken { .. } => "invalid token".to_string(),
+            ParseError::UnrecognizedEOF { expected, .. } => "unexpected end of file, expected one of ".to_string() + &expected.join(", "),
+            ParseError::UnrecognizedToken { token: (_, token, _), expected } => "unexpected `".to_string() + token.1 + "`, expected one of " + &expected.join(", "),
+            ParseError::ExtraToken { token: (_, token, _) } => "extra token `".to_string() + token.1 + "`",
+            ParseError::User { error } => error.to_string(),
+        };
+        Diagnostics::from(Diagnostic::error(message).with_span(start, end))
+    })
+}
+
+/* Warnings about an ast */
+pub fn check(program: &ast::Program) -> Diagnostics {
+    lint::check(program)
+}
+
+/* Lower an ast into Koopa IR */
+pub fn lower(program: &ast::Program) -> Result<Program, Diagnostics> {
+    let program = program.clone().dump().map_err(Diagnostics::from)?;
+    if cfg!(debug_assertions) {
+        opt::verify::assert_valid(&program, "lowering");
+    }
+    Ok(program)
+}
+
+/* What an editor shows for a source */
+pub struct Analysis {
+    pub diagnostics: Diagnostics,
+    // Symbols defined and resolved before the first error, None when parsing failed
+    pub symbols: Option<SymbolTable>,
+}
+
+/* Diagnostics and symbols of a source, without generating code */
+pub fn analyze(source: &str, warnings: &WarningOptions) -> Analysis {
+    let (found, symbols) = match parse(source) {
+        Ok(ast) => {
+            let mut found = check(&ast);
+            let mut symbols = SymbolTable::new();
+            if let Err(err) = ast.dump_with(&mut symbols) {
+                found.push(err);
+            }
+            (found, Some(symbols))
+        }
+        Err(found) => (found, None),
+    };
+    let mut diagnostics = Diagnostics::new();
+    for diagnostic in found.list {
+        if let Some(diagnostic) = warnings.apply(diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+    Analysis { diagnostics, symbols }
+}
+
+/* Optimize Koopa IR in place */
+pub fn optimize(program: &mut Program, level: OptLevel) {
+    opt::optimize(program, &opt::Options { level, ..Default::default() });
+}
+
+/* Assembler settings, the generated assembly is the same for every riscv32 triple */
+#[derive(Clone, Debug)]
+pub struct TargetOptions {
+    // Target triple passed to the assembler, only 32-bit RISC-V is supported
+    pub triple: String,
+}
+
+impl TargetOptions {
+    /* Settings for `triple`, if the backend can generate code for it */
+    pub fn new(triple: &str) -> Option<TargetOptions> {
+        if triple.starts_with("riscv32") {
+            Some(TargetOptions { triple: triple.to_string() })
+        }else {
+            None
+        }
+    }
+}
+
+impl Default for TargetOptions {
+    fn default() -> TargetOptions {
+        TargetOptions { triple: "riscv32-unknown-elf".to_string() }
+    }
+}
+
+/* RISC-V assembly of a Koopa program */
+pub fn emit_riscv(program: &Program) -> String {
+    generate::gen_riscv32(program)
+}
+
+/* Koopa IR in text form */
+pub fn emit_koopa(program: &Program) -> String {
+    dump::gen_text_koopa(program)
+}
+
+/* Control-flow graphs of a Koopa program in DOT */
+pub fn emit_dot(program: &Program, options: &DotOptions) -> String {
+    dot::gen_dot(program, options)
+}
+
+/* Whether `name` is a stage --dump-after accepts: parse, lower, an optimization pass or codegen */
+pub fn is_stage(name: &str) -> bool {
+    matches!(name, "parse" | "lower" | "codegen") || opt::PASSES.contains(&name)
+}
+
+/* Which intermediate results a session keeps */
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    // --dump-all
+    pub all: bool,
+    // --dump-after=<stage>
+    pub after: HashSet<String>,
+}
+
+/* Program text after one stage of the pipeline */
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    // Position of the stage in the pipeline, from 1
+    pub index: usize,
+    pub stage: String,
+    pub text: String,
+}
+
+impl Snapshot {
+    /* `<stem>.<index>.ast`, `.koopa`, `.after-<pass>.koopa` or `.s` */
+    pub fn file_name(&self, stem: &str) -> String {
+        let suffix = match self.stage.as_str() {
+            "parse" => "ast".to_string(),
+            "lower" => "koopa".to_string(),
+            "codegen" => "s".to_string(),
+            pass => "after-".to_string() + pass + ".koopa",
+        };
+        let index = self.index.to_string();
+        stem.to_string() + "." + &"0".repeat(2usize.saturating_sub(index.len())) + &index + "." + &suffix
+    }
+}
+
+/* Snapshots taken so far, numbered by every stage that ran */
+#[derive(Clone, Debug, Default)]
+pub struct Snapshots {
+    pub options: DumpOptions,
+    pub stages: usize,
+    pub list: Vec<Snapshot>,
+}
+
+impl Snapshots {
+    pub fn new(options: DumpOptions) -> Snapshots {
+        Snapshots { options, stages: 0, list: vec![] }
+    }
+
+    /* Count a finished stage, keeping its text if it was asked for */
+    pub fn record(&mut self, stage: &str, text: impl FnOnce() -> String) {
+        self.stages += 1;
+        if self.options.all || self.options.after.contains(stage) {
+            self.list.push(Snapshot { index: self.stages, stage: stage.to_string(), text: text() });
+        }
+    }
+}
+
+/* Compiler settings */
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub opt: opt::Options,
+    pub target: TargetOptions,
+    pub warnings: WarningOptions,
+    pub dump: DumpOptions,
+    pub dot: DotOptions,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            opt: opt::Options { level: OptLevel::O0, ..Default::default() },
+            target: TargetOptions::default(),
+            warnings: WarningOptions::default(),
+            dump: DumpOptions::default(),
+            dot: DotOptions::default(),
+        }
+    }
+}
+
+/* One compilation, collecting diagnostics of every stage */
+pub struct Session {
+    pub options: Options,
+    pub diagnostics: Diagnostics,
+    pub snapshots: Snapshots,
+}
+
+impl Session {
+    pub fn new(options: Options) -> Session {
+        let snapshots = Snapshots::new(options.dump.clone());
+        Session { options, diagnostics: Diagnostics::new(), snapshots }
+    }
+
+    pub fn parse(&mut self, source: &str) -> Option<ast::Program> {
+        let ast = self.record(parse(source))?;
+        self.snapshots.record("parse", || format!("{:#?}\n", ast));
+        Some(ast)
+    }
+
+    /* Koopa IR of the source, optimized at the session's level */
+    pub fn koopa(&mut self, source: &str) -> Option<Program> {
+        let ast = self.parse(source)?;
+        self.report(check(&ast));
+        let mut program = self.record(lower(&ast))?;
+        self.snapshots.record("lower", || emit_koopa(&program));
+        let snapshots = &mut self.snapshots;
+        opt::optimize_with(&mut program, &self.options.opt, &mut |pass, program| snapshots.record(pass, || emit_koopa(program)));
+        Some(program)
+    }
+
+    pub fn riscv(&mut self, source: &str) -> Option<String> {
+        let program = self.koopa(source)?;
+        let asm = emit_riscv(&program);
+        self.snapshots.record("codegen", || asm.clone());
+        Some(asm)
+    }
+
+    fn record<T>(&mut self, result: Result<T, Diagnostics>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(diagnostics) => {
+                self.report(diagnostics);
+                None
+            }
+        }
+    }
+
+    /* Keep the diagnostics the warning options let through */
+    pub fn report(&mut self, diagnostics: Diagnostics) {
+        for diagnostic in diagnostics.list {
+            if let Some(diagnostic) = self.options.warnings.apply(diagnostic) {
+                self.diagnostics.push(diagnostic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "int g = 2;\nint main() {\n  int unused = 1;\n  return g + 3 * 4;\n}\n";
+
+    #[test]
+    fn parse_errors_point_at_the_token() {
+        assert_eq!(parse(SOURCE).unwrap().list.len(), 2);
+        let source = "int main() { return 1 }";
+        let diagnostics = parse(source).unwrap_err();
+        assert!(diagnostics.has_errors());
+        let found = &diagnostics.list[0];
+        assert!(found.message.starts_with("unexpected `}`"), "{}", found.message);
+        assert_eq!(found.span, Some((22, 23)));
+    }
+
+    #[test]
+    fn lower_reports_semantic_errors() {
+        let ast = parse("int main() { return x; }").unwrap();
+        let diagnostics = match lower(&ast) {
+            Ok(_) => panic!("lowered an undefined name"),
+            Err(diagnostics) => diagnostics,
+        };
+        assert!(diagnostics.list[0].message.contains("x"), "{}", diagnostics.list[0].message);
+    }
+
+    #[test]
+    fn optimize_folds_through_the_pipeline() {
+        let mut program = lower(&parse("int main() { const int c = 3; int x = c * 4; return x + 1; }").unwrap()).unwrap();
+        assert!(emit_koopa(&program).contains("load @x_1"));
+        optimize(&mut program, OptLevel::O1);
+        let text = emit_koopa(&program);
+        assert!(!text.contains("load"), "{}", text);
+        assert!(text.contains("store 12, @x_1"), "{}", text);
+    }
+
+    #[test]
+    fn emitters_name_every_function() {
+        let program = lower(&parse(SOURCE).unwrap()).unwrap();
+        assert!(emit_koopa(&program).contains("fun @main(): i32"));
+        let asm = emit_riscv(&program);
+        assert!(asm.contains(".globl main\nmain:"), "{}", asm);
+        assert!(asm.contains(".globl g"), "{}", asm);
+        let dot = emit_dot(&program, &DotOptions::default());
+        assert!(dot.starts_with("digraph"), "{}", dot);
+    }
+
+    #[test]
+    fn targets_other_than_riscv32_are_rejected() {
+        assert_eq!(TargetOptions::new("riscv32-unknown-linux-gnu").unwrap().triple, "riscv32-unknown-linux-gnu");
+        assert!(TargetOptions::new("riscv64-unknown-elf").is_none());
+        assert!(TargetOptions::new("x86_64-unknown-linux-gnu").is_none());
+    }
+
+    #[test]
+    fn stages_and_snapshot_names() {
+        assert!(is_stage("parse") && is_stage("lower") && is_stage("codegen") && is_stage("gvn"));
+        assert!(!is_stage("link"));
+        let snapshot = Snapshot { index: 3, stage: "sccp".to_string(), text: String::new() };
+        assert_eq!(snapshot.file_name("a"), "a.03.after-sccp.koopa");
+        let snapshot = Snapshot { index: 12, stage: "codegen".to_string(), text: String::new() };
+        assert_eq!(snapshot.file_name("a"), "a.12.s");
+    }
+
+    #[test]
+    fn session_keeps_diagnostics_and_requested_snapshots() {
+        let mut options = Options::default();
+        options.opt.level = OptLevel::O1;
+        options.warnings.set("unused-variable").unwrap();
+        options.dump.after.insert("lower".to_string());
+        options.dump.after.insert("codegen".to_string());
+        let mut session = Session::new(options);
+        let asm = session.riscv(SOURCE).unwrap();
+        assert_eq!(session.diagnostics.list.len(), 1);
+        assert_eq!(session.diagnostics.list[0].code, Some("unused-variable"));
+        let stages: Vec<&str> = session.snapshots.list.iter().map(|s| s.stage.as_str()).collect();
+        assert_eq!(stages, vec!["lower", "codegen"]);
+        assert_eq!(session.snapshots.list[0].index, 2);
+        assert_eq!(session.snapshots.list[1].index, session.snapshots.stages);
+        assert_eq!(session.snapshots.list[1].text, asm);
+    }
+
+    #[test]
+    fn session_stops_at_the_first_failing_stage() {
+        let mut options = Options::default();
+        options.warnings.set("error=unused-variable").unwrap();
+        let mut session = Session::new(options);
+        assert!(session.riscv("int main() { return 1 }").is_none());
+        assert_eq!(session.snapshots.stages, 0);
+        assert!(session.diagnostics.has_errors());
+
+        // Warnings promoted by -Werror= are errors, but do not stop lowering
+        let mut session = Session::new(session.options.clone());
+        assert!(session.koopa(SOURCE).is_some());
+        assert_eq!(session.diagnostics.list[0].severity, Severity::Error);
+    }
+}
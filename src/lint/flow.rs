@@ -0,0 +1,205 @@
+/* Uses */
+use koopa::ir::BinaryOp;
+use crate::ast;
+use crate::diag::{Diagnostic, Diagnostics};
+
+/* Control-flow warnings of every function
+ * A statement "completes" when control can leave it at its end. Conditions
+ * made of literals only are folded, so `while (1)` without `break` never
+ * completes and the code after it is unreachable.
+ */
+pub fn check(program: &ast::Program, diagnostics: &mut Diagnostics) {
+    for func_def in program.list.iter().flatten() {
+        let mut flow = Flow { diagnostics, loops: vec![], dead: false };
+        let completes = flow.block(&func_def.block);
+        // Falling off `main` returns 0, as in C
+        if completes && matches!(func_def.func_type, ast::FuncType::Int) && func_def.id != "main" {
+            let end = func_def.block.span.1;
+            let message = "non-void function `".to_string() + &func_def.id + "` may end without returning a value";
+            flow.diagnostics.push(Diagnostic::warning("return-type", message).with_span(end - 1, end));
+        }
+    }
+}
+
+struct Flow<'a> {
+    diagnostics: &'a mut Diagnostics,
+    // Whether each enclosing loop may be left by `break`
+    loops: Vec<bool>,
+    // Inside code already reported as unreachable
+    dead: bool,
+}
+
+impl Flow<'_> {
+    fn warn(&mut self, code: &'static str, message: &str, span: ast::Span) {
+        self.diagnostics.push(Diagnostic::warning(code, message.to_string()).with_span(span.0, span.1));
+    }
+
+    /* Whether control may reach the end of the block */
+    fn block(&mut self, block: &ast::Block) -> bool {
+        let mut completes = true;
+        let dead = self.dead;
+        for item in &block.block_item_list {
+            let span = match item {
+                ast::BlockItem::Decl(_, span) => *span,
+                ast::BlockItem::Stmt(ast::Stmt::Blank, _) => continue,
+                ast::BlockItem::Stmt(_, span) => *span,
+            };
+            if !completes && !self.dead {
+                self.warn("unreachable-code", "code will never be executed", span);
+                self.dead = true;
+            }
+            if let ast::BlockItem::Stmt(stmt, _) = item {
+                // Statements after a jump stay unreachable whatever they do
+                completes = self.stmt(stmt) && completes;
+            }
+        }
+        self.dead = dead;
+        completes
+    }
+
+    fn stmt(&mut self, stmt: &ast::Stmt) -> bool {
+        match stmt {
+            ast::Stmt::Ret(..) | ast::Stmt::Continue(_) => false,
+            ast::Stmt::Break(_) => {
+                if let Some(breaks) = self.loops.last_mut() {
+                    *breaks = true;
+                }
+                false
+            }
+            ast::Stmt::Block(block) => self.block(block),
+            ast::Stmt::If(stmt) => {
+                let cond = fold(&stmt.exp);
+                if let Some(value) = cond {
+                    let message = "condition is always ".to_string() + if value != 0 { "true" } else { "false" };
+                    self.warn("constant-condition", &message, stmt.exp.span);
+                }
+                let then_completes = self.stmt(&stmt.then_stmt);
+                let else_completes = match &stmt.else_stmt {
+                    Some(else_stmt) => self.stmt(else_stmt),
+                    None => true,
+                };
+                match cond {
+                    Some(0) => else_completes,
+                    Some(_) => then_completes,
+                    None => then_completes || else_completes,
+                }
+            }
+            ast::Stmt::While(exp, body) => {
+                let cond = fold(exp);
+                if cond == Some(0) {
+                    self.warn("constant-condition", "loop condition is always false, the body never runs", exp.span);
+                }
+                self.loops.push(false);
+                self.stmt(body);
+                let breaks = self.loops.pop().unwrap();
+                match cond {
+                    Some(value) if value != 0 && !breaks => {
+                        if !returns(body) {
+                            self.warn("infinite-loop", "loop never ends, it has no `break` or `return`", exp.span);
+                        }
+                        false
+                    }
+                    _ => true,
+                }
+            }
+            ast::Stmt::Assign(..) | ast::Stmt::Exp(_) | ast::Stmt::Blank => true,
+        }
+    }
+}
+
+/* Whether a `return` appears anywhere in the statement */
+fn returns(stmt: &ast::Stmt) -> bool {
+    match stmt {
+        ast::Stmt::Ret(..) => true,
+        ast::Stmt::Block(block) => block.block_item_list.iter().any(|item| matches!(item, ast::BlockItem::Stmt(stmt, _) if returns(stmt))),
+        ast::Stmt::If(stmt) => returns(&stmt.then_stmt) || stmt.else_stmt.as_ref().is_some_and(|s| returns(s)),
+        ast::Stmt::While(_, body) => returns(body),
+        _ => false,
+    }
+}
+
+/* Value of an expression made of literals only */
+pub fn fold(exp: &ast::Exp) -> Option<i32> {
+    match &*exp.core {
+        ast::ExpCore::Single(num) => Some(*num),
+        ast::ExpCore::Binary(lhs, op, rhs) => {
+            let (l, r) = (fold(lhs)?, fold(rhs)?);
+            Some(match op {
+                BinaryOp::Add => l.wrapping_add(r),
+                BinaryOp::Sub => l.wrapping_sub(r),
+                BinaryOp::Mul => l.wrapping_mul(r),
+                BinaryOp::Div if r != 0 => l.wrapping_div(r),
+                BinaryOp::Mod if r != 0 => l.wrapping_rem(r),
+                BinaryOp::Lt => (l < r) as i32,
+                BinaryOp::Gt => (l > r) as i32,
+                BinaryOp::Le => (l <= r) as i32,
+                BinaryOp::Ge => (l >= r) as i32,
+                BinaryOp::Eq => (l == r) as i32,
+                BinaryOp::NotEq => (l != r) as i32,
+                BinaryOp::And => (l != 0 && r != 0) as i32,
+                BinaryOp::Or => (l != 0 || r != 0) as i32,
+                _ => return None,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Warnings for `source` as (code, source text of the span) */
+    fn warnings(source: &str) -> Vec<(&'static str, String)> {
+        let program = crate::parse(source).unwrap();
+        let mut diagnostics = Diagnostics::new();
+        check(&program, &mut diagnostics);
+        diagnostics.list.iter().map(|d| {
+            let (start, end) = d.span.unwrap();
+            (d.code.unwrap(), source[start..end].to_string())
+        }).collect()
+    }
+
+    #[test]
+    fn falling_off_the_end() {
+        assert_eq!(warnings("int f(int x) { if (x) return 1; }"), vec![("return-type", "}".to_string())]);
+        assert_eq!(warnings("int f(int x) { if (x) return 1; else return 2; }"), vec![]);
+        // `main` returns 0
+        assert_eq!(warnings("int main() { }"), vec![]);
+        assert_eq!(warnings("void f() { }"), vec![]);
+    }
+
+    #[test]
+    fn code_after_jumps() {
+        assert_eq!(warnings("int main() { return 0; int x = 1; x = 2; }"), vec![("unreachable-code", "int x = 1;".to_string())]);
+        assert_eq!(
+            warnings("int main() { while (getint()) { break; putint(1); } while (getint()) { continue; putint(2); } return 0; }"),
+            vec![("unreachable-code", "putint(1);".to_string()), ("unreachable-code", "putint(2);".to_string())],
+        );
+    }
+
+    #[test]
+    fn endless_loops() {
+        assert_eq!(
+            warnings("int main() { while (1) { putint(1); } return 0; }"),
+            vec![("infinite-loop", "1".to_string()), ("unreachable-code", "return 0;".to_string())],
+        );
+        assert_eq!(warnings("int main() { while (1) { if (getint()) break; } return 0; }"), vec![]);
+        // A `return` inside ends the loop too, falling off the end can't happen
+        assert_eq!(warnings("int f() { while (1) { if (getint()) return 1; } }"), vec![]);
+    }
+
+    #[test]
+    fn constant_conditions() {
+        assert_eq!(
+            warnings("int main() { if (0) putint(1); if (1 - 1) putint(2); return 0; }"),
+            vec![("constant-condition", "0".to_string()), ("constant-condition", "1 - 1".to_string())],
+        );
+        assert_eq!(warnings("int main() { while (0) putint(1); return 0; }"), vec![("constant-condition", "0".to_string())]);
+        // `if (0)` never takes the return, so the end is reachable
+        assert_eq!(
+            warnings("int f() { if (0) return 1; }"),
+            vec![("constant-condition", "0".to_string()), ("return-type", "}".to_string())],
+        );
+    }
+}
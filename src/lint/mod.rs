@@ -0,0 +1,15 @@
+/* Uses */
+use crate::ast;
+use crate::diag::Diagnostics;
+
+/* Module (Extern) */
+pub mod flow;
+pub mod vars;
+
+/* Warnings about the source, before it is lowered */
+pub fn check(program: &ast::Program) -> Diagnostics {
+    let mut diagnostics = Diagnostics::new();
+    flow::check(program, &mut diagnostics);
+    vars::check(program, &mut diagnostics);
+    diagnostics
+}
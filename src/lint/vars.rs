@@ -0,0 +1,614 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use crate::ast;
+use crate::diag::{Diagnostic, Diagnostics};
+use crate::lint::flow::fold;
+
+/* Warnings about the locals and parameters of every function
+ * Globals are zero initialized and shared between functions, so they are
+ * left alone. Arrays are tracked as a whole and conservatively: storing
+ * any element, or passing the array to a function, on some path before a
+ * read (or anywhere in an enclosing loop) initializes all of it.
+ */
+pub fn check(program: &ast::Program, diagnostics: &mut Diagnostics) {
+    for func_def in program.list.iter().flatten() {
+        let vars = Resolver::resolve(func_def);
+        usage(&vars, diagnostics);
+
+        let mut init = Init { vars: &vars, loops: vec![], reported: HashSet::new(), diagnostics };
+        let mut assigned = HashSet::new();
+        for (id, var) in vars.list.iter().enumerate() {
+            if var.kind == Kind::Param {
+                assigned.insert(id);
+            }
+        }
+        init.block(&func_def.block, Some(assigned));
+
+        let mut live = Live { vars: &vars, loops: vec![], writes: HashMap::new() };
+        live.block(&func_def.block, HashSet::new());
+        let mut dead: Vec<(ast::Span, usize)> = live.writes.into_iter().filter(|(_, (_, dead))| *dead).map(|(span, (id, _))| (span, id)).collect();
+        dead.sort();
+        for (span, id) in dead {
+            // Variables never read at all get one unused-but-set warning instead
+            if vars.list[id].reads > 0 {
+                let message = "value stored to `".to_string() + &vars.list[id].name + "` is never read";
+                diagnostics.push(Diagnostic::warning("dead-store", message).with_span(span.0, span.1));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Param,
+    Var,
+    Const,
+}
+
+/* Local variable, constant or parameter */
+struct Var {
+    name: String,
+    kind: Kind,
+    span: ast::Span,
+    // Array dimensions, a parameter's omitted first one included
+    dims: usize,
+    // Uses as a value (array arguments included) and assignments
+    reads: usize,
+    writes: usize,
+}
+
+/* Locals of one function, with the definition each name use refers to */
+struct Vars {
+    list: Vec<Var>,
+    // Span of an LVal or definition to the variable
+    refs: HashMap<ast::Span, usize>,
+}
+
+impl Vars {
+    fn lookup(&self, lval: &ast::LVal) -> Option<usize> {
+        self.refs.get(&lval.span).copied()
+    }
+
+    /* Scalar that can be assigned, the ones dataflow tracks value by value */
+    fn is_scalar_var(&self, id: usize) -> bool {
+        self.list[id].kind != Kind::Const && self.list[id].dims == 0
+    }
+}
+
+struct Resolver {
+    vars: Vars,
+    scopes: Vec<HashMap<String, usize>>,
+}
+
+impl Resolver {
+    fn resolve(func_def: &ast::FuncDef) -> Vars {
+        let mut resolver = Resolver { vars: Vars { list: vec![], refs: HashMap::new() }, scopes: vec![HashMap::new()] };
+        for param in &func_def.func_param_list {
+            resolver.define(&param.0, Kind::Param, param.2, param.1.len());
+        }
+        resolver.block(&func_def.block);
+        resolver.vars
+    }
+
+    fn define(&mut self, name: &str, kind: Kind, span: ast::Span, dims: usize) -> usize {
+        let id = self.vars.list.len();
+        self.vars.list.push(Var { name: name.to_string(), kind, span, dims, reads: 0, writes: 0 });
+        self.vars.refs.insert(span, id);
+        self.scopes.last_mut().unwrap().insert(name.to_string(), id);
+        id
+    }
+
+    fn lookup(&mut self, lval: &ast::LVal) -> Option<usize> {
+        let id = self.scopes.iter().rev().find_map(|scope| scope.get(&lval.id)).copied()?;
+        self.vars.refs.insert(lval.span, id);
+        Some(id)
+    }
+
+    fn block(&mut self, block: &ast::Block) {
+        self.scopes.push(HashMap::new());
+        for item in &block.block_item_list {
+            match item {
+                ast::BlockItem::Decl(decl, _) => self.decl(decl),
+                ast::BlockItem::Stmt(stmt, _) => self.stmt(stmt),
+            }
+        }
+        self.scopes.pop();
+    }
+
+    fn decl(&mut self, decl: &ast::Decl) {
+        match decl {
+            ast::Decl::Const(decl) => {
+                for def in &decl.const_def_list {
+                    def.is_array.iter().for_each(|exp| self.exp(exp));
+                    self.init_val(&def.const_init_val);
+                    self.define(&def.id, Kind::Const, def.span, def.is_array.len());
+                }
+            }
+            ast::Decl::Var(decl) => {
+                for def in &decl.var_def_list {
+                    def.is_array.iter().for_each(|exp| self.exp(exp));
+                    if let Some(init_val) = &def.init_val {
+                        self.init_val(init_val);
+                    }
+                    self.define(&def.id, Kind::Var, def.span, def.is_array.len());
+                }
+            }
+        }
+    }
+
+    fn init_val(&mut self, init_val: &ast::InitVal) {
+        match init_val {
+            ast::InitVal::Exp(exp) => self.exp(exp),
+            ast::InitVal::List(list) => list.iter().for_each(|init_val| self.init_val(init_val)),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &ast::Stmt) {
+        match stmt {
+            ast::Stmt::Assign(lval, exp) => {
+                self.exp(exp);
+                lval.is_array.iter().for_each(|exp| self.exp(exp));
+                if let Some(id) = self.lookup(lval) {
+                    let var = &mut self.vars.list[id];
+                    // Stores through an array parameter are seen by the caller
+                    if var.kind == Kind::Param && var.dims > 0 {
+                        var.reads += 1;
+                    }else {
+                        var.writes += 1;
+                    }
+                }
+            }
+            ast::Stmt::Exp(exp) => self.exp(exp),
+            ast::Stmt::Block(block) => self.block(block),
+            ast::Stmt::Ret(exp, _) => exp.iter().for_each(|exp| self.exp(exp)),
+            ast::Stmt::If(stmt) => {
+                self.exp(&stmt.exp);
+                self.stmt(&stmt.then_stmt);
+                if let Some(else_stmt) = &stmt.else_stmt {
+                    self.stmt(else_stmt);
+                }
+            }
+            ast::Stmt::While(exp, body) => {
+                self.exp(exp);
+                self.stmt(body);
+            }
+            ast::Stmt::Continue(_) | ast::Stmt::Break(_) | ast::Stmt::Blank => {}
+        }
+    }
+
+    fn exp(&mut self, exp: &ast::Exp) {
+        match &*exp.core {
+            ast::ExpCore::Binary(lhs, _, rhs) => {
+                self.exp(lhs);
+                self.exp(rhs);
+            }
+            ast::ExpCore::Single(_) => {}
+            ast::ExpCore::Ident(lval) => {
+                lval.is_array.iter().for_each(|exp| self.exp(exp));
+                if let Some(id) = self.lookup(lval) {
+                    self.vars.list[id].reads += 1;
+                }
+            }
+            ast::ExpCore::Call(_, args) => args.iter().for_each(|exp| self.exp(exp)),
+        }
+    }
+}
+
+/* Declared but never used, or only ever assigned */
+fn usage(vars: &Vars, diagnostics: &mut Diagnostics) {
+    for var in &vars.list {
+        if var.reads > 0 {
+            continue;
+        }
+        let (code, message) = match (var.kind, var.writes) {
+            (Kind::Param, _) => ("unused-parameter", "unused parameter `".to_string() + &var.name + "`"),
+            (_, 0) => ("unused-variable", "unused variable `".to_string() + &var.name + "`"),
+            (_, _) => ("unused-but-set", "variable `".to_string() + &var.name + "` is set but never read"),
+        };
+        diagnostics.push(Diagnostic::warning(code, message).with_span(var.span.0, var.span.1));
+    }
+}
+
+/* Forward pass: which scalars are assigned on every path, and which arrays on some path
+ * A state of None means the point is unreachable.
+ */
+struct Init<'a> {
+    vars: &'a Vars,
+    // States at the `break`s of each enclosing loop
+    loops: Vec<Vec<Option<HashSet<usize>>>>,
+    reported: HashSet<usize>,
+    diagnostics: &'a mut Diagnostics,
+}
+
+type State = Option<HashSet<usize>>;
+
+impl Init<'_> {
+    /* State where two paths join */
+    fn meet(&self, a: State, b: State) -> State {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(a.union(&b).copied().filter(|id| self.vars.list[*id].dims > 0 || (a.contains(id) && b.contains(id))).collect()),
+            (a, None) => a,
+            (None, b) => b,
+        }
+    }
+
+    fn block(&mut self, block: &ast::Block, mut state: State) -> State {
+        for item in &block.block_item_list {
+            state = match item {
+                ast::BlockItem::Decl(ast::Decl::Var(decl), _) => {
+                    for def in &decl.var_def_list {
+                        if let Some(init_val) = &def.init_val {
+                            self.init_val(init_val, &mut state);
+                        }
+                        // Declared again on each trip around a loop
+                        let id = self.vars.refs[&def.span];
+                        if let Some(assigned) = state.as_mut() {
+                            if def.init_val.is_some() {
+                                assigned.insert(id);
+                            }else {
+                                assigned.remove(&id);
+                            }
+                        }
+                    }
+                    state
+                }
+                ast::BlockItem::Decl(ast::Decl::Const(_), _) => state,
+                ast::BlockItem::Stmt(stmt, _) => self.stmt(stmt, state),
+            };
+        }
+        state
+    }
+
+    fn init_val(&mut self, init_val: &ast::InitVal, state: &mut State) {
+        match init_val {
+            ast::InitVal::Exp(exp) => self.exp(exp, state),
+            ast::InitVal::List(list) => list.iter().for_each(|init_val| self.init_val(init_val, state)),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &ast::Stmt, mut state: State) -> State {
+        match stmt {
+            ast::Stmt::Assign(lval, exp) => {
+                self.exp(exp, &mut state);
+                lval.is_array.iter().for_each(|exp| self.exp(exp, &mut state));
+                if let (Some(id), Some(assigned)) = (self.vars.lookup(lval), state.as_mut()) {
+                    assigned.insert(id);
+                }
+                state
+            }
+            ast::Stmt::Exp(exp) => {
+                self.exp(exp, &mut state);
+                state
+            }
+            ast::Stmt::Block(block) => self.block(block, state),
+            ast::Stmt::Ret(exp, _) => {
+                if let Some(exp) = exp {
+                    self.exp(exp, &mut state);
+                }
+                None
+            }
+            ast::Stmt::If(stmt) => {
+                self.exp(&stmt.exp, &mut state);
+                let then_state = self.stmt(&stmt.then_stmt, state.clone());
+                let else_state = match &stmt.else_stmt {
+                    Some(else_stmt) => self.stmt(else_stmt, state),
+                    None => state,
+                };
+                self.meet(then_state, else_state)
+            }
+            ast::Stmt::While(exp, body) => {
+                // Scalar assignments only add to the state, so the loop head sees what the
+                // entry sees, plus the arrays stored in the body from a previous trip
+                if let Some(assigned) = state.as_mut() {
+                    let mut stored = HashSet::new();
+                    self.stored_arrays(body, &mut stored);
+                    assigned.extend(stored);
+                }
+                self.exp(exp, &mut state);
+                self.loops.push(vec![]);
+                self.stmt(body, state.clone());
+                let breaks = self.loops.pop().unwrap();
+                let exit = match fold(exp) {
+                    Some(value) if value != 0 => None,
+                    _ => state,
+                };
+                breaks.into_iter().fold(exit, |a, b| self.meet(a, b))
+            }
+            ast::Stmt::Break(_) => {
+                if let Some(breaks) = self.loops.last_mut() {
+                    breaks.push(state);
+                }
+                None
+            }
+            ast::Stmt::Continue(_) => None,
+            ast::Stmt::Blank => state,
+        }
+    }
+
+    fn exp(&mut self, exp: &ast::Exp, state: &mut State) {
+        match &*exp.core {
+            ast::ExpCore::Binary(lhs, _, rhs) => {
+                self.exp(lhs, state);
+                self.exp(rhs, state);
+            }
+            ast::ExpCore::Single(_) => {}
+            ast::ExpCore::Ident(lval) => {
+                lval.is_array.iter().for_each(|exp| self.exp(exp, state));
+                self.read(lval, state);
+            }
+            ast::ExpCore::Call(_, args) => {
+                args.iter().for_each(|exp| self.exp(exp, state));
+                // The callee may fill an array passed to it
+                for arg in args {
+                    if let ast::ExpCore::Ident(lval) = &*arg.core {
+                        if let (Some(id), Some(assigned)) = (self.vars.lookup(lval), state.as_mut()) {
+                            if lval.is_array.len() < self.vars.list[id].dims {
+                                assigned.insert(id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /* Arrays a statement stores to or passes to a function */
+    fn stored_arrays(&self, stmt: &ast::Stmt, stored: &mut HashSet<usize>) {
+        match stmt {
+            ast::Stmt::Assign(lval, value) => {
+                if let Some(id) = self.vars.lookup(lval).filter(|&id| self.vars.list[id].dims > 0) {
+                    stored.insert(id);
+                }
+                self.passed_arrays(value, stored);
+            }
+            ast::Stmt::Exp(value) => self.passed_arrays(value, stored),
+            ast::Stmt::Block(block) => {
+                for item in &block.block_item_list {
+                    match item {
+                        ast::BlockItem::Stmt(stmt, _) => self.stored_arrays(stmt, stored),
+                        ast::BlockItem::Decl(ast::Decl::Var(decl), _) => {
+                            for init_val in decl.var_def_list.iter().filter_map(|def| def.init_val.as_ref()) {
+                                if let ast::InitVal::Exp(value) = init_val {
+                                    self.passed_arrays(value, stored);
+                                }
+                            }
+                        }
+                        ast::BlockItem::Decl(ast::Decl::Const(_), _) => {}
+                    }
+                }
+            }
+            ast::Stmt::Ret(Some(value), _) => self.passed_arrays(value, stored),
+            ast::Stmt::If(stmt) => {
+                self.passed_arrays(&stmt.exp, stored);
+                self.stored_arrays(&stmt.then_stmt, stored);
+                if let Some(else_stmt) = &stmt.else_stmt {
+                    self.stored_arrays(else_stmt, stored);
+                }
+            }
+            ast::Stmt::While(cond, body) => {
+                self.passed_arrays(cond, stored);
+                self.stored_arrays(body, stored);
+            }
+            _ => {}
+        }
+    }
+
+    /* Arrays an expression passes to a function */
+    fn passed_arrays(&self, exp: &ast::Exp, stored: &mut HashSet<usize>) {
+        match &*exp.core {
+            ast::ExpCore::Binary(lhs, _, rhs) => {
+                self.passed_arrays(lhs, stored);
+                self.passed_arrays(rhs, stored);
+            }
+            ast::ExpCore::Ident(lval) => lval.is_array.iter().for_each(|exp| self.passed_arrays(exp, stored)),
+            ast::ExpCore::Call(_, args) => {
+                for arg in args {
+                    if let ast::ExpCore::Ident(lval) = &*arg.core {
+                        if let Some(id) = self.vars.lookup(lval).filter(|&id| lval.is_array.len() < self.vars.list[id].dims) {
+                            stored.insert(id);
+                        }
+                    }
+                    self.passed_arrays(arg, stored);
+                }
+            }
+            ast::ExpCore::Single(_) => {}
+        }
+    }
+
+    fn read(&mut self, lval: &ast::LVal, state: &State) {
+        let (id, assigned) = match (self.vars.lookup(lval), state) {
+            (Some(id), Some(assigned)) => (id, assigned),
+            _ => return,
+        };
+        let var = &self.vars.list[id];
+        // Passing a whole array is not a read of its elements
+        let element = lval.is_array.len() == var.dims;
+        if var.kind == Kind::Var && element && !assigned.contains(&id) && self.reported.insert(id) {
+            let message = "`".to_string() + &var.name + "` may be used before it is initialized";
+            self.diagnostics.push(Diagnostic::warning("uninitialized", message).with_span(lval.span.0, lval.span.1));
+        }
+    }
+}
+
+/* Backward pass: which scalars may still be read, to find stores nobody reads */
+struct Live<'a> {
+    vars: &'a Vars,
+    // Live sets at the exit and the head of each enclosing loop
+    loops: Vec<(HashSet<usize>, HashSet<usize>)>,
+    // Every store, and whether it is dead on the latest visit
+    writes: HashMap<ast::Span, (usize, bool)>,
+}
+
+impl Live<'_> {
+    fn block(&mut self, block: &ast::Block, mut live: HashSet<usize>) -> HashSet<usize> {
+        for item in block.block_item_list.iter().rev() {
+            live = match item {
+                ast::BlockItem::Decl(ast::Decl::Var(decl), _) => {
+                    for def in decl.var_def_list.iter().rev() {
+                        let id = self.vars.refs[&def.span];
+                        if let Some(init_val) = &def.init_val {
+                            if self.vars.is_scalar_var(id) {
+                                self.writes.insert(def.span, (id, !live.contains(&id)));
+                            }
+                            live.remove(&id);
+                            self.init_val(init_val, &mut live);
+                        }else {
+                            live.remove(&id);
+                        }
+                    }
+                    live
+                }
+                ast::BlockItem::Decl(ast::Decl::Const(_), _) => live,
+                ast::BlockItem::Stmt(stmt, _) => self.stmt(stmt, live),
+            };
+        }
+        live
+    }
+
+    fn init_val(&mut self, init_val: &ast::InitVal, live: &mut HashSet<usize>) {
+        match init_val {
+            ast::InitVal::Exp(exp) => self.exp(exp, live),
+            ast::InitVal::List(list) => list.iter().for_each(|init_val| self.init_val(init_val, live)),
+        }
+    }
+
+    /* Live set before `stmt` given the one after it */
+    fn stmt(&mut self, stmt: &ast::Stmt, mut live: HashSet<usize>) -> HashSet<usize> {
+        match stmt {
+            ast::Stmt::Assign(lval, exp) => {
+                if let Some(id) = self.vars.lookup(lval).filter(|&id| self.vars.is_scalar_var(id)) {
+                    self.writes.insert(lval.span, (id, !live.contains(&id)));
+                    live.remove(&id);
+                }
+                lval.is_array.iter().for_each(|exp| self.exp(exp, &mut live));
+                self.exp(exp, &mut live);
+                live
+            }
+            ast::Stmt::Exp(exp) => {
+                self.exp(exp, &mut live);
+                live
+            }
+            ast::Stmt::Block(block) => self.block(block, live),
+            ast::Stmt::Ret(exp, _) => {
+                let mut live = HashSet::new();
+                if let Some(exp) = exp {
+                    self.exp(exp, &mut live);
+                }
+                live
+            }
+            ast::Stmt::If(stmt) => {
+                let mut before = self.stmt(&stmt.then_stmt, live.clone());
+                match &stmt.else_stmt {
+                    Some(else_stmt) => before.extend(self.stmt(else_stmt, live)),
+                    None => before.extend(live),
+                }
+                self.exp(&stmt.exp, &mut before);
+                before
+            }
+            ast::Stmt::While(exp, body) => {
+                // Grow the live set of the loop head until the body stops adding to it,
+                // the last visit of the body then sees the final sets
+                let exits = !matches!(fold(exp), Some(value) if value != 0);
+                let head_of = |this: &mut Self, body_live: HashSet<usize>| {
+                    let mut head = body_live;
+                    if exits {
+                        head.extend(live.iter().copied());
+                    }
+                    this.exp(exp, &mut head);
+                    head
+                };
+                let mut head = head_of(self, HashSet::new());
+                loop {
+                    self.loops.push((live.clone(), head.clone()));
+                    let body_live = self.stmt(body, head.clone());
+                    self.loops.pop();
+                    let next = head_of(self, body_live);
+                    if next == head {
+                        break head;
+                    }
+                    head = next;
+                }
+            }
+            ast::Stmt::Break(_) => self.loops.last().map_or(live, |(exit, _)| exit.clone()),
+            ast::Stmt::Continue(_) => self.loops.last().map_or(live, |(_, head)| head.clone()),
+            ast::Stmt::Blank => live,
+        }
+    }
+
+    fn exp(&mut self, exp: &ast::Exp, live: &mut HashSet<usize>) {
+        match &*exp.core {
+            ast::ExpCore::Binary(lhs, _, rhs) => {
+                self.exp(lhs, live);
+                self.exp(rhs, live);
+            }
+            ast::ExpCore::Single(_) => {}
+            ast::ExpCore::Ident(lval) => {
+                lval.is_array.iter().for_each(|exp| self.exp(exp, live));
+                if let Some(id) = self.vars.lookup(lval) {
+                    live.insert(id);
+                }
+            }
+            ast::ExpCore::Call(_, args) => args.iter().for_each(|exp| self.exp(exp, live)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Warnings for `source` as (code, start of the span, source text of the span) */
+    fn warnings(source: &str) -> Vec<(&'static str, usize, String)> {
+        let program = crate::parse(source).unwrap();
+        let mut diagnostics = Diagnostics::new();
+        check(&program, &mut diagnostics);
+        diagnostics.list.iter().map(|d| {
+            let (start, end) = d.span.unwrap();
+            (d.code.unwrap(), start, source[start..end].to_string())
+        }).collect()
+    }
+
+    /* Offset of the first occurrence of `text` */
+    fn at(source: &str, text: &str) -> usize {
+        source.find(text).unwrap()
+    }
+
+    #[test]
+    fn assigned_on_one_branch() {
+        let source = "int main() { int x; if (getint()) x = 1; return x; }";
+        assert_eq!(warnings(source), vec![("uninitialized", at(source, "x; }"), "x".to_string())]);
+        let source = "int main() { int x; if (getint()) x = 1; else x = 2; return x; }";
+        assert_eq!(warnings(source), vec![]);
+    }
+
+    #[test]
+    fn dead_store_before_overwrite() {
+        let source = "int main() { int x = 1; x = 2; return x; }";
+        assert_eq!(warnings(source), vec![("dead-store", at(source, "x = 1"), "x".to_string())]);
+        let source = "int main() { int y; y = getint(); y = 2; return y; }";
+        assert_eq!(warnings(source), vec![("dead-store", at(source, "y = getint"), "y".to_string())]);
+        // Read in between, nothing is dead
+        let source = "int main() { int x = 1; putint(x); x = 2; return x; }";
+        assert_eq!(warnings(source), vec![]);
+    }
+
+    #[test]
+    fn unused_parameter() {
+        let source = "int f(int a, int b) { return a; } int main() { return f(1, 2); }";
+        assert_eq!(warnings(source), vec![("unused-parameter", at(source, "b)"), "b".to_string())]);
+        // Storing through an array parameter uses it
+        let source = "void f(int a[]) { a[0] = 1; } int main() { int a[1]; f(a); return a[0]; }";
+        assert_eq!(warnings(source), vec![]);
+    }
+
+    #[test]
+    fn array_passed_to_a_callee_is_initialized() {
+        let source = "void fill(int a[]) { a[0] = 1; } int main() { int a[2]; fill(a); return a[0]; }";
+        assert_eq!(warnings(source), vec![]);
+        let source = "int main() { int a[4]; getarray(a); return a[1]; }";
+        assert_eq!(warnings(source), vec![]);
+        // Without the call the read is reported
+        let source = "int main() { int a[4]; return a[1]; }";
+        assert_eq!(warnings(source), vec![("uninitialized", at(source, "a[1]"), "a".to_string())]);
+    }
+}
@@ -0,0 +1,542 @@
+/* Language server
+ * `Server` answers Language Server Protocol messages, already decoded from
+ * json; the `lsp` binary frames them over stdio. Documents are synced
+ * whole, every change is parsed and lowered again to publish diagnostics
+ * and refresh the symbols the requests are answered from.
+ */
+
+/* Uses */
+use std::collections::HashMap;
+use crate::analyze;
+use crate::ast::Span;
+use crate::diag::{Diagnostic, Severity, WarningOptions};
+use crate::init::Init;
+use crate::json::Json;
+use crate::symbol::{SymbolId, SymbolKind, SymbolTable};
+
+/* Json-rpc error codes */
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/* Const values in hover are cut after this many characters */
+const VALUE_TEXT_MAX: usize = 200;
+
+/* Open file */
+struct Document {
+    text: String,
+    // Symbols of the last version that parsed, they may be stale
+    symbols: Option<SymbolTable>,
+    // Whether `symbols` belong to `text`
+    fresh: bool,
+}
+
+pub struct Server {
+    documents: HashMap<String, Document>,
+    warnings: WarningOptions,
+    // A `shutdown` request came, `exit` then ends cleanly
+    shutdown: bool,
+    // Exit code once `exit` arrives
+    pub exit: Option<i32>,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new()
+    }
+}
+
+type RequestResult = Result<Json, (i64, String)>;
+
+/* Where a request points */
+struct Cursor<'a> {
+    uri: &'a str,
+    text: &'a str,
+    symbols: &'a SymbolTable,
+    offset: usize,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server { documents: HashMap::new(), warnings: WarningOptions::default(), shutdown: false, exit: None }
+    }
+
+    /* Messages to send for one received: the response of a request and notifications */
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        crate::trace!(Lsp, Info, "{}", method);
+        match message.get("id") {
+            // Responses to requests of ours, the server sends none
+            Some(_) if method.is_empty() => vec![],
+            Some(id) => {
+                let (key, value) = match self.request(method, params) {
+                    Ok(result) => ("result", result),
+                    Err((code, message)) => ("error", Json::object(vec![("code", Json::Int(code)), ("message", Json::Str(message))])),
+                };
+                vec![Json::object(vec![("jsonrpc", Json::str("2.0")), ("id", id.clone()), (key, value)])]
+            }
+            None => self.notification(method, params),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> RequestResult {
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                ("capabilities", Json::object(vec![
+                    // Full text on every change
+                    ("textDocumentSync", Json::Int(1)),
+                    ("hoverProvider", Json::Bool(true)),
+                    ("definitionProvider", Json::Bool(true)),
+                    ("referencesProvider", Json::Bool(true)),
+                    ("documentSymbolProvider", Json::Bool(true)),
+                    ("completionProvider", Json::object(vec![])),
+                ])),
+                ("serverInfo", Json::object(vec![("name", Json::str("sysy-lsp")), ("version", Json::str(env!("CARGO_PKG_VERSION")))])),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, "unknown method `".to_string() + method + "`")),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.at(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or("");
+                vec![self.update(&uri, text.to_string())]
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(Json::as_array).unwrap_or(&[]);
+                match changes.last().and_then(|change| change.get("text")).and_then(Json::as_str) {
+                    Some(text) => vec![self.update(&uri, text.to_string())],
+                    None => vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish(&uri, vec![])]
+            }
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /* Analyze a new version of a document, returning its diagnostics */
+    fn update(&mut self, uri: &str, text: String) -> Json {
+        let analysis = analyze(&text, &self.warnings);
+        let diagnostics = analysis.diagnostics.list.iter().map(|diagnostic| diagnostic_json(&text, diagnostic)).collect();
+        let old = self.documents.remove(uri).and_then(|document| document.symbols);
+        let fresh = analysis.symbols.is_some();
+        let symbols = analysis.symbols.or(old);
+        self.documents.insert(uri.to_string(), Document { text, symbols, fresh });
+        publish(uri, diagnostics)
+    }
+
+    /* Document and offset a request points at, if it has symbols that may be used */
+    fn locate<'a>(&'a self, params: &'a Json, stale: bool) -> Result<Option<Cursor<'a>>, (i64, String)> {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str);
+        let line = params.at(&["position", "line"]).and_then(Json::as_int);
+        let character = params.at(&["position", "character"]).and_then(Json::as_int);
+        let (uri, line, character) = match (uri, line, character) {
+            (Some(uri), Some(line), Some(character)) => (uri, line, character),
+            _ => return Err((INVALID_PARAMS, "expected a document and a position".to_string())),
+        };
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        match &document.symbols {
+            Some(symbols) if document.fresh || stale => {
+                let text = &document.text;
+                Ok(Some(Cursor { uri, text, symbols, offset: offset(text, line, character) }))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn definition(&self, params: &Json) -> RequestResult {
+        let Cursor { uri, text, symbols, offset } = match self.locate(params, false)? {
+            Some(found) => found,
+            None => return Ok(Json::Null),
+        };
+        Ok(match symbol_at(symbols, offset).map(|id| symbols.get(id)) {
+            Some(symbol) if symbol.span != (0, 0) => location(uri, text, symbol.span),
+            _ => Json::Null,
+        })
+    }
+
+    fn references(&self, params: &Json) -> RequestResult {
+        let Cursor { uri, text, symbols, offset } = match self.locate(params, false)? {
+            Some(found) => found,
+            None => return Ok(Json::Null),
+        };
+        let id = match symbol_at(symbols, offset) {
+            Some(id) => id,
+            None => return Ok(Json::Null),
+        };
+        let mut spans: Vec<_> = symbols.uses().filter(|&(_, symbol)| symbol == id).map(|(span, _)| span).collect();
+        let declaration = params.at(&["context", "includeDeclaration"]).and_then(Json::as_bool).unwrap_or(true);
+        if declaration && symbols.get(id).span != (0, 0) {
+            spans.push(symbols.get(id).span);
+        }
+        spans.sort();
+        Ok(Json::Array(spans.into_iter().map(|span| location(uri, text, span)).collect()))
+    }
+
+    fn hover(&self, params: &Json) -> RequestResult {
+        let Cursor { text, symbols, offset, .. } = match self.locate(params, false)? {
+            Some(found) => found,
+            None => return Ok(Json::Null),
+        };
+        let id = match symbol_at(symbols, offset) {
+            Some(id) => id,
+            None => return Ok(Json::Null),
+        };
+        let symbol = symbols.get(id);
+        let mut declaration = symbol.ty.declare(&symbol.name);
+        if let SymbolKind::Const(init) = &symbol.kind {
+            let mut value = init_text(init, &init.dims, 0);
+            if value.len() > VALUE_TEXT_MAX {
+                value = value.chars().take(VALUE_TEXT_MAX).collect::<String>() + "...";
+            }
+            declaration += &(" = ".to_string() + &value);
+        }
+        let note = match symbol.kind {
+            SymbolKind::Param => "\n\nparameter",
+            SymbolKind::Func(_) if symbol.span == (0, 0) => "\n\nruntime library function",
+            _ => "",
+        };
+        let span = symbols.uses().find(|&(span, use_id)| use_id == id && covers(span, offset)).map_or(symbol.span, |(span, _)| span);
+        Ok(Json::object(vec![
+            ("contents", Json::object(vec![("kind", Json::str("markdown")), ("value", Json::Str("```c\n".to_string() + &declaration + "\n```" + note))])),
+            ("range", range(text, span)),
+        ]))
+    }
+
+    fn document_symbols(&self, params: &Json) -> RequestResult {
+        let uri = params.at(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or("");
+        let (document, symbols) = match self.documents.get(uri) {
+            Some(document @ Document { symbols: Some(symbols), fresh: true, .. }) => (document, symbols),
+            _ => return Ok(Json::Null),
+        };
+        let list = symbols.symbols().filter(|(_, symbol)| symbol.span != (0, 0)).map(|(_, symbol)| {
+            // SymbolKind of the protocol: function, variable or constant
+            let kind = match symbol.kind {
+                SymbolKind::Func(_) => 12,
+                SymbolKind::Const(_) => 14,
+                SymbolKind::Var | SymbolKind::Param => 13,
+            };
+            Json::object(vec![
+                ("name", Json::Str(symbol.name.clone())),
+                ("kind", Json::Int(kind)),
+                ("detail", Json::Str(symbol.ty.to_string())),
+                ("location", location(uri, &document.text, symbol.span)),
+            ])
+        }).collect();
+        Ok(Json::Array(list))
+    }
+
+    fn completion(&self, params: &Json) -> RequestResult {
+        // Symbols of the last version that parsed, the source is rarely complete while typing
+        let Cursor { symbols, offset, .. } = match self.locate(params, true)? {
+            Some(found) => found,
+            None => return Ok(Json::Array(vec![])),
+        };
+        let items = symbols.visible_at(offset).into_iter().map(|id| symbols.get(id))
+            .map(|symbol| {
+                // CompletionItemKind of the protocol: function, variable or constant
+                let kind = match symbol.kind {
+                    SymbolKind::Func(_) => 3,
+                    SymbolKind::Const(_) => 21,
+                    SymbolKind::Var | SymbolKind::Param => 6,
+                };
+                Json::object(vec![
+                    ("label", Json::Str(symbol.name.clone())),
+                    ("kind", Json::Int(kind)),
+                    ("detail", Json::Str(symbol.ty.declare(&symbol.name))),
+                ])
+            }).collect();
+        Ok(Json::Array(items))
+    }
+}
+
+/* Whether `offset` is in `span` or right after it, where the cursor ends after typing a name */
+fn covers((start, end): Span, offset: usize) -> bool {
+    start < end && start <= offset && offset <= end
+}
+
+/* Symbol named at `offset`, by one of its uses or its definition */
+fn symbol_at(symbols: &SymbolTable, offset: usize) -> Option<SymbolId> {
+    symbols.uses().find(|&(span, _)| covers(span, offset)).map(|(_, id)| id)
+        .or_else(|| symbols.symbols().find(|(_, symbol)| covers(symbol.span, offset)).map(|(id, _)| id))
+}
+
+/* Elements of a const in braces, zero sub-arrays as `{}` */
+fn init_text(init: &Init<i32>, dims: &[usize], base: usize) -> String {
+    if dims.is_empty() {
+        return init.elems.get(&base).copied().unwrap_or(0).to_string();
+    }
+    let len: usize = dims.iter().product();
+    if init.elems.range(base..base + len).all(|(_, &i)| i == 0) {
+        return "{}".to_string();
+    }
+    let stride = len / dims[0];
+    let items: Vec<_> = (0..dims[0]).map(|i| init_text(init, &dims[1..], base + i * stride)).collect();
+    "{".to_string() + &items.join(", ") + "}"
+}
+
+/* Byte offset of a protocol position, a line and a column in UTF-16 units */
+fn offset(text: &str, line: i64, character: i64) -> usize {
+    let mut start = 0;
+    for _ in 0..line {
+        match text[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16() as i64;
+    }
+    text.len()
+}
+
+/* Protocol position of a byte offset */
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count();
+    let column: usize = before[before.rfind('\n').map_or(0, |i| i + 1)..].chars().map(char::len_utf16).sum();
+    Json::object(vec![("line", Json::Int(line as i64)), ("character", Json::Int(column as i64))])
+}
+
+fn range(text: &str, (start, end): Span) -> Json {
+    Json::object(vec![("start", position(text, start)), ("end", position(text, end))])
+}
+
+fn location(uri: &str, text: &str, span: Span) -> Json {
+    Json::object(vec![("uri", Json::str(uri)), ("range", range(text, span))])
+}
+
+fn diagnostic_json(text: &str, diagnostic: &Diagnostic) -> Json {
+    // DiagnosticSeverity of the protocol
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    let mut fields = vec![
+        ("range", range(text, diagnostic.span.unwrap_or((0, 0)))),
+        ("severity", Json::Int(severity)),
+        ("source", Json::str("sysy")),
+        ("message", Json::Str(diagnostic.message.clone())),
+    ];
+    if let Some(code) = diagnostic.code {
+        fields.push(("code", Json::str(code)));
+    }
+    Json::object(fields)
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::str("2.0")),
+        ("method", Json::str("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![("uri", Json::str(uri)), ("diagnostics", Json::Array(diagnostics))])),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///a.sy";
+    const SOURCE: &str = "const int N = 2;\nint x = N;\nint f(int a[]) {\n  int x = a[0];\n  {\n    int x[2] = {N};\n    x[0] = x[1] + 1;\n  }\n  return x;\n}\nint main() {\n  int b[2];\n  return f(b) + x;\n}\n";
+
+    fn open(server: &mut Server, text: &str) -> Json {
+        let message = Json::object(vec![
+            ("method", Json::str("textDocument/didOpen")),
+            ("params", Json::object(vec![("textDocument", Json::object(vec![("uri", Json::str(URI)), ("text", Json::str(text))]))])),
+        ]);
+        server.handle(&message).remove(0)
+    }
+
+    fn request(server: &mut Server, method: &str, line: i64, character: i64) -> Json {
+        let message = Json::object(vec![
+            ("id", Json::Int(1)),
+            ("method", Json::str(method)),
+            ("params", Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::str(URI))])),
+                ("position", Json::object(vec![("line", Json::Int(line)), ("character", Json::Int(character))])),
+            ])),
+        ]);
+        let mut response = server.handle(&message);
+        assert_eq!(response.len(), 1);
+        response.remove(0).get("result").unwrap().clone()
+    }
+
+    /* `line:character-line:character` of a range */
+    fn span(range: &Json) -> String {
+        let at = |key: &str| {
+            let line = range.at(&[key, "line"]).and_then(Json::as_int).unwrap();
+            line.to_string() + ":" + &range.at(&[key, "character"]).and_then(Json::as_int).unwrap().to_string()
+        };
+        at("start") + "-" + &at("end")
+    }
+
+    fn spans(locations: &Json) -> Vec<String> {
+        locations.as_array().unwrap().iter().map(|location| span(location.get("range").unwrap())).collect()
+    }
+
+    fn diagnostics(published: &Json) -> Vec<(String, String)> {
+        published.at(&["params", "diagnostics"]).and_then(Json::as_array).unwrap().iter().map(|diagnostic| {
+            (span(diagnostic.get("range").unwrap()), diagnostic.get("message").and_then(Json::as_str).unwrap().to_string())
+        }).collect()
+    }
+
+    #[test]
+    fn definitions_follow_shadowing() {
+        let mut server = Server::new();
+        assert!(diagnostics(&open(&mut server, SOURCE)).is_empty());
+        let definition = |server: &mut Server, line, character| span(request(server, "textDocument/definition", line, character).get("range").unwrap());
+        // The block's array, the function's local and the global
+        assert_eq!(definition(&mut server, 6, 4), "5:8-5:9");
+        assert_eq!(definition(&mut server, 8, 9), "3:6-3:7");
+        assert_eq!(definition(&mut server, 12, 16), "1:4-1:5");
+        assert_eq!(definition(&mut server, 3, 10), "2:10-2:11");
+        // Keywords and numbers name nothing
+        assert_eq!(request(&mut server, "textDocument/definition", 8, 3), Json::Null);
+    }
+
+    #[test]
+    fn references_of_each_x() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+        assert_eq!(spans(&request(&mut server, "textDocument/references", 5, 8)), vec!["5:8-5:9", "6:4-6:5", "6:11-6:12"]);
+        assert_eq!(spans(&request(&mut server, "textDocument/references", 3, 6)), vec!["3:6-3:7", "8:9-8:10"]);
+        assert_eq!(spans(&request(&mut server, "textDocument/references", 12, 16)), vec!["1:4-1:5", "12:16-12:17"]);
+        assert_eq!(spans(&request(&mut server, "textDocument/references", 0, 10)), vec!["0:10-0:11", "1:8-1:9", "5:16-5:17"]);
+    }
+
+    #[test]
+    fn hover_shows_the_declaration() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+        let hover = |server: &mut Server, line, character| {
+            let result = request(server, "textDocument/hover", line, character);
+            (result.at(&["contents", "value"]).and_then(Json::as_str).unwrap().to_string(), span(result.get("range").unwrap()))
+        };
+        assert_eq!(hover(&mut server, 1, 8), ("```c\nconst int N = 2\n```".to_string(), "1:8-1:9".to_string()));
+        assert_eq!(hover(&mut server, 6, 11), ("```c\nint x[2]\n```".to_string(), "6:11-6:12".to_string()));
+        assert_eq!(hover(&mut server, 8, 9), ("```c\nint x\n```".to_string(), "8:9-8:10".to_string()));
+        assert_eq!(hover(&mut server, 3, 10), ("```c\nint a[]\n```\n\nparameter".to_string(), "3:10-3:11".to_string()));
+        assert_eq!(hover(&mut server, 12, 9), ("```c\nint f(int[])\n```".to_string(), "12:9-12:10".to_string()));
+    }
+
+    #[test]
+    fn document_symbols_list_every_definition() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+        let message = Json::object(vec![
+            ("id", Json::Int(2)),
+            ("method", Json::str("textDocument/documentSymbol")),
+            ("params", Json::object(vec![("textDocument", Json::object(vec![("uri", Json::str(URI))]))])),
+        ]);
+        let result = server.handle(&message).remove(0);
+        let mut list: Vec<(String, i64, String, String)> = result.get("result").and_then(Json::as_array).unwrap().iter().map(|symbol| (
+            symbol.get("name").and_then(Json::as_str).unwrap().to_string(),
+            symbol.get("kind").and_then(Json::as_int).unwrap(),
+            symbol.get("detail").and_then(Json::as_str).unwrap().to_string(),
+            span(symbol.at(&["location", "range"]).unwrap()),
+        )).collect();
+        // In source order
+        list.sort_by_key(|symbol| symbol.3.split(['-', ':']).take(2).map(|n| n.parse::<i64>().unwrap()).collect::<Vec<_>>());
+        let expected = [
+            ("N", 14, "const int", "0:10-0:11"),
+            ("x", 13, "int", "1:4-1:5"),
+            ("f", 12, "int(int[])", "2:4-2:5"),
+            ("a", 13, "int[]", "2:10-2:11"),
+            ("x", 13, "int", "3:6-3:7"),
+            ("x", 13, "int[2]", "5:8-5:9"),
+            ("main", 12, "int()", "10:4-10:8"),
+            ("b", 13, "int[2]", "11:6-11:7"),
+        ];
+        let expected: Vec<_> = expected.iter().map(|&(name, kind, detail, at)| (name.to_string(), kind, detail.to_string(), at.to_string())).collect();
+        assert_eq!(list, expected);
+    }
+
+    #[test]
+    fn completion_offers_visible_names() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+        let completion = |server: &mut Server, line, character| -> Vec<(String, i64, String)> {
+            request(server, "textDocument/completion", line, character).as_array().unwrap().iter().map(|item| (
+                item.get("label").and_then(Json::as_str).unwrap().to_string(),
+                item.get("kind").and_then(Json::as_int).unwrap(),
+                item.get("detail").and_then(Json::as_str).unwrap().to_string(),
+            )).collect()
+        };
+        let find = |items: &[(String, i64, String)], name: &str| -> Vec<(i64, String)> {
+            items.iter().filter(|item| item.0 == name).map(|item| (item.1, item.2.clone())).collect()
+        };
+        // In the block the array hides both other `x`
+        let inner = completion(&mut server, 6, 4);
+        assert_eq!(find(&inner, "x"), vec![(6, "int x[2]".to_string())]);
+        assert_eq!(find(&inner, "a"), vec![(6, "int a[]".to_string())]);
+        assert_eq!(find(&inner, "N"), vec![(21, "const int N".to_string())]);
+        assert_eq!(find(&inner, "f"), vec![(3, "int f(int[])".to_string())]);
+        assert_eq!(find(&inner, "getint"), vec![(3, "int getint()".to_string())]);
+        // After it the function's local is back, `b` of main is not visible
+        let after = completion(&mut server, 8, 9);
+        assert_eq!(find(&after, "x"), vec![(6, "int x".to_string())]);
+        assert!(find(&after, "b").is_empty());
+        // Before its definition a local is not offered, the global is
+        let before = completion(&mut server, 3, 2);
+        assert_eq!(find(&before, "x"), vec![(6, "int x".to_string())]);
+        assert!(find(&before, "main").is_empty());
+    }
+
+    #[test]
+    fn broken_documents_keep_the_last_symbols_for_completion() {
+        let mut server = Server::new();
+        open(&mut server, SOURCE);
+        // The `;` after `int x[2]` is gone
+        let broken = SOURCE.replacen("int x[2] = {N};", "int x[2] = {N}", 1);
+        let change = Json::object(vec![
+            ("method", Json::str("textDocument/didChange")),
+            ("params", Json::object(vec![
+                ("textDocument", Json::object(vec![("uri", Json::str(URI))])),
+                ("contentChanges", Json::Array(vec![Json::object(vec![("text", Json::Str(broken))])])),
+            ])),
+        ]);
+        let published = server.handle(&change);
+        assert_eq!(published.len(), 1);
+        let errors = diagnostics(&published[0]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0.split('-').next(), Some("6:4"));
+
+        // Positions no longer match the symbols, only completion uses them
+        assert_eq!(request(&mut server, "textDocument/definition", 8, 9), Json::Null);
+        assert_eq!(request(&mut server, "textDocument/references", 8, 9), Json::Null);
+        assert_eq!(request(&mut server, "textDocument/hover", 8, 9), Json::Null);
+        let labels: Vec<Json> = request(&mut server, "textDocument/completion", 8, 9).as_array().unwrap().iter().map(|item| item.get("label").unwrap().clone()).collect();
+        assert!(labels.contains(&Json::str("x")) && labels.contains(&Json::str("f")));
+
+        // A document that never parsed has nothing to offer
+        let mut server = Server::new();
+        assert_eq!(diagnostics(&open(&mut server, "int main() { return 0 }\n")).len(), 1);
+        assert_eq!(request(&mut server, "textDocument/completion", 0, 13), Json::Array(vec![]));
+        assert_eq!(request(&mut server, "textDocument/hover", 0, 4), Json::Null);
+    }
+}
@@ -1,60 +1,145 @@
 /* Uses */
-use lalrpop_util::lalrpop_mod;
-use std::env::args;
-use std::fs::read_to_string;
-use std::fs::File;
+use std::env::{self, args};
+use std::fs;
+use std::io::{self, Read, Write};
 use std::path::Path;
-use std::io::prelude::*;
-
-/* Lalrpop Generate */
-lalrpop_mod!(sysy);
+use std::process::{self, exit};
+use compiler::json::ToJson;
+use compiler::{trace, Session, TargetOptions};
 
 /* Module (Extern) */
-pub mod ast;
-pub mod dump;
-pub mod generate;
+mod cli;
+
+use cli::{Args, Command, Emit};
 
 /* Main */
 fn main() {
     /* Args Process */
-    let mut args = args();
-    args.next();
-    // Mode: -koopa / -riscv32
-    let mode = args.next().unwrap();
-    // Input file path
-    let input = args.next().unwrap();
-    args.next();
-    // Output file path
-    let output = args.next().unwrap();
+    let command = match cli::parse_args(args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("error: {}", message);
+            eprintln!("Try `compiler --help` for more information.");
+            exit(2);
+        }
+    };
+    match command {
+        Command::Help => print!("{}", cli::usage()),
+        Command::Version => println!("compiler {}", env!("CARGO_PKG_VERSION")),
+        Command::Compile(args) => {
+            // The flag refines what the environment enabled
+            let traced = trace::configure_from_env().map_err(|message| message + " in $" + trace::ENV_VAR)
+                .and_then(|_| args.trace.as_deref().map_or(Ok(()), trace::configure));
+            if let Err(message) = traced {
+                eprintln!("error: {}", message);
+                exit(2);
+            }
+            exit(compile(*args))
+        }
+    }
+}
 
+/* Run one compilation, returning the exit code */
+fn compile(args: Args) -> i32 {
     /* Read */
-    let input = read_to_string(input).unwrap();
+    let source = match &args.input {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).map(|_| source)
+        }
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: cannot read {}: {}", args.input.as_deref().unwrap_or("stdin"), err);
+            return 1;
+        }
+    };
 
     /* Compile */
-    // Use lalrpop generated parser
-    let ast = sysy::ProgramParser::new().parse(&input).unwrap();
-    // Output log
-    // println!("{:#?}", ast);
-
-    // Select mode
-    let text = match mode.as_str() {
-        "-koopa" => {
-            // Koopa - output text
-            dump::gen_text_koopa(ast) // Gen from dump mod
-        },
-        "-riscv" => {
-            // Riscv32 - output assemble code
-            generate::gen_riscv32(ast)
-        },
-        "-perf" => {
-            // Performance mode - output assemble with optimization
-            generate::gen_riscv32(ast)
+    let target = args.options.target.clone();
+    let dot = args.options.dot.clone();
+    let mut session = Session::new(args.options);
+    let text = match args.emit {
+        Emit::Ast => session.parse(&source).map(|ast| format!("{:#?}\n", ast)),
+        Emit::Json => session.parse(&source).map(|ast| ast.to_json().pretty() + "\n"),
+        Emit::Koopa => session.koopa(&source).map(|program| compiler::emit_koopa(&program)),
+        Emit::Dot => session.koopa(&source).map(|program| compiler::emit_dot(&program, &dot)),
+        Emit::Asm | Emit::Obj => session.riscv(&source),
+    };
+    eprint!("{}", session.diagnostics.render(&source));
+
+    /* Snapshots */
+    // Written even when compilation failed, they help find out why
+    let stem = match &args.input {
+        Some(input) => Path::new(input).with_extension("").to_string_lossy().to_string(),
+        None => "stdin".to_string(),
+    };
+    for snapshot in &session.snapshots.list {
+        let path = snapshot.file_name(&stem);
+        if let Err(err) = fs::write(&path, &snapshot.text) {
+            eprintln!("error: cannot write {}: {}", path, err);
+            return 1;
         }
-        _ => unreachable!(),
+    }
+
+    let text = match text {
+        Some(text) if !session.diagnostics.has_errors() => text,
+        _ => return 1,
+    };
+    let bytes = match args.emit {
+        Emit::Obj => match assemble(&text, &target) {
+            Ok(bytes) => bytes,
+            Err(message) => {
+                eprintln!("error: {}", message);
+                return 1;
+            }
+        },
+        _ => text.into_bytes(),
     };
 
     /* Output */
-    let path = Path::new(&output);
-    let mut file = File::create(&path).unwrap(); 
-    file.write_all(text.as_bytes()).unwrap();
+    // Default output is next to the input, stdout when reading stdin
+    let output = match (args.output, &args.input) {
+        (Some(output), _) => output,
+        (None, Some(input)) => Path::new(input).with_extension(args.emit.extension()).to_string_lossy().to_string(),
+        (None, None) => "-".to_string(),
+    };
+    let written = if output == "-" {
+        io::stdout().write_all(&bytes)
+    }else {
+        fs::write(&output, &bytes)
+    };
+    if let Err(err) = written {
+        eprintln!("error: cannot write {}: {}", output, err);
+        return 1;
+    }
+    0
+}
+
+/* Assemble with an external assembler, `$SYSY_AS` or clang */
+fn assemble(asm: &str, target: &TargetOptions) -> Result<Vec<u8>, String> {
+    let dir = env::temp_dir();
+    let stem = "sysy-".to_string() + &process::id().to_string();
+    let asm_path = dir.join(stem.clone() + ".s");
+    let obj_path = dir.join(stem + ".o");
+    fs::write(&asm_path, asm).map_err(|err| "cannot write ".to_string() + &asm_path.to_string_lossy() + ": " + &err.to_string())?;
+
+    let assembler = env::var("SYSY_AS").unwrap_or_else(|_| "clang".to_string());
+    let mut command = process::Command::new(&assembler);
+    if assembler.contains("clang") {
+        command.arg("--target=".to_string() + &target.triple).arg("-c");
+    }
+    command.args(["-march=rv32im", "-mabi=ilp32"]).arg(&asm_path).arg("-o").arg(&obj_path);
+    let status = command.status();
+    let _ = fs::remove_file(&asm_path);
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(_) => return Err("assembler `".to_string() + &assembler + "` failed"),
+        Err(err) => return Err("cannot run assembler `".to_string() + &assembler + "`: " + &err.to_string() + ", set SYSY_AS to pick one"),
+    }
+    let bytes = fs::read(&obj_path).map_err(|err| "cannot read object file: ".to_string() + &err.to_string());
+    let _ = fs::remove_file(&obj_path);
+    bytes
 }
@@ -0,0 +1,44 @@
+/* Uses */
+use koopa::ir::*;
+
+/* Object a pointer points into: alloc, global alloc, or None if unknown */
+pub fn pointer_root(func_data: &FunctionData, mut ptr: Value) -> Option<Value> {
+    loop {
+        if ptr.is_global() {
+            return Some(ptr);
+        }
+        match func_data.dfg().value(ptr).kind() {
+            ValueKind::Alloc(_) => return Some(ptr),
+            ValueKind::GetElemPtr(gep) => ptr = gep.src(),
+            ValueKind::GetPtr(gp) => ptr = gp.src(),
+            _ => return None,
+        }
+    }
+}
+
+/* Scalar alloc: its address never escapes */
+pub fn is_scalar_alloc(func_data: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return false;
+    }
+    let data = func_data.dfg().value(value);
+    match (data.kind(), data.ty().kind()) {
+        (ValueKind::Alloc(_), TypeKind::Pointer(base)) => !matches!(base.kind(), TypeKind::Array(_, _)),
+        _ => false,
+    }
+}
+
+/* Two pointers may refer to the same memory
+ * Unknown pointers are loaded from pointer slots, which may hold the address
+ * of any array once callees are inlined. Only scalar slots stay out of reach.
+ */
+pub fn may_alias(func_data: &FunctionData, a: Value, b: Value) -> bool {
+    if a == b {
+        return true;
+    }
+    match (pointer_root(func_data, a), pointer_root(func_data, b)) {
+        (Some(ra), Some(rb)) => ra == rb,
+        (Some(root), None) | (None, Some(root)) => !is_scalar_alloc(func_data, root),
+        (None, None) => true,
+    }
+}
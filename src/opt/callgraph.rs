@@ -0,0 +1,120 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+
+/* Call graph over the functions of a program
+ * Every call site is an edge, so a callee may appear several times.
+ */
+pub struct CallGraph {
+    pub funcs: Vec<Function>,
+    pub callees: HashMap<Function, Vec<Function>>,
+    pub callers: HashMap<Function, Vec<Function>>,
+}
+
+/* Function is only declared (runtime library) */
+pub fn is_decl(program: &Program, func: Function) -> bool {
+    program.func(func).layout().entry_bb().is_none()
+}
+
+impl CallGraph {
+    pub fn new(program: &Program) -> CallGraph {
+        let funcs = program.func_layout().to_vec();
+        let mut callees: HashMap<Function, Vec<Function>> = HashMap::new();
+        let mut callers: HashMap<Function, Vec<Function>> = HashMap::new();
+        for &func in &funcs {
+            callees.entry(func).or_default();
+            callers.entry(func).or_default();
+        }
+        for &func in &funcs {
+            let func_data = program.func(func);
+            for (_, node) in func_data.layout().bbs() {
+                for &inst in node.insts().keys() {
+                    if let ValueKind::Call(call) = func_data.dfg().value(inst).kind() {
+                        callees.get_mut(&func).unwrap().push(call.callee());
+                        callers.get_mut(&call.callee()).unwrap().push(func);
+                    }
+                }
+            }
+        }
+        CallGraph { funcs, callees, callers }
+    }
+
+    /* Number of call sites of func */
+    pub fn call_count(&self, func: Function) -> usize {
+        self.callers[&func].len()
+    }
+
+    /* Strongly connected components, callees before callers (Tarjan) */
+    pub fn sccs(&self) -> Vec<Vec<Function>> {
+        let mut tarjan = Tarjan {
+            graph: self,
+            index: HashMap::new(),
+            low: HashMap::new(),
+            stack: vec![],
+            on_stack: HashSet::new(),
+            count: 0,
+            sccs: vec![],
+        };
+        for &func in &self.funcs {
+            if !tarjan.index.contains_key(&func) {
+                tarjan.visit(func);
+            }
+        }
+        tarjan.sccs
+    }
+
+    /* Functions that may call themselves, directly or through others */
+    pub fn recursive_funcs(&self) -> HashSet<Function> {
+        let mut set = HashSet::new();
+        for scc in self.sccs() {
+            if scc.len() > 1 || self.callees[&scc[0]].contains(&scc[0]) {
+                set.extend(scc);
+            }
+        }
+        set
+    }
+}
+
+struct Tarjan<'a> {
+    graph: &'a CallGraph,
+    index: HashMap<Function, usize>,
+    low: HashMap<Function, usize>,
+    stack: Vec<Function>,
+    on_stack: HashSet<Function>,
+    count: usize,
+    sccs: Vec<Vec<Function>>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, func: Function) {
+        self.index.insert(func, self.count);
+        self.low.insert(func, self.count);
+        self.count += 1;
+        self.stack.push(func);
+        self.on_stack.insert(func);
+
+        for &callee in &self.graph.callees[&func] {
+            if !self.index.contains_key(&callee) {
+                self.visit(callee);
+                let low = self.low[&func].min(self.low[&callee]);
+                self.low.insert(func, low);
+            }else if self.on_stack.contains(&callee) {
+                let low = self.low[&func].min(self.index[&callee]);
+                self.low.insert(func, low);
+            }
+        }
+
+        if self.low[&func] == self.index[&func] {
+            let mut scc = vec![];
+            loop {
+                let top = self.stack.pop().unwrap();
+                self.on_stack.remove(&top);
+                scc.push(top);
+                if top == func {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+
+/* Successors of a basic block, read from its terminator */
+pub fn successors(func_data: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let node = func_data.layout().bbs().node(&bb).unwrap();
+    match node.insts().back_key() {
+        Some(&inst) => match func_data.dfg().value(inst).kind() {
+            ValueKind::Branch(br) => vec![br.true_bb(), br.false_bb()],
+            ValueKind::Jump(jump) => vec![jump.target()],
+            _ => vec![],
+        },
+        None => vec![],
+    }
+}
+
+/* Predecessors of every reachable basic block */
+pub fn predecessors(func_data: &FunctionData) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+    let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+    for bb in reverse_post_order(func_data) {
+        preds.entry(bb).or_default();
+        for succ in successors(func_data, bb) {
+            preds.entry(succ).or_default().push(bb);
+        }
+    }
+    preds
+}
+
+/* Reachable basic blocks in reverse post order, starting from entry */
+pub fn reverse_post_order(func_data: &FunctionData) -> Vec<BasicBlock> {
+    let mut order = vec![];
+    let entry = match func_data.layout().entry_bb() {
+        Some(entry) => entry,
+        None => return order,
+    };
+    // Iterative dfs: (bb, index of the next successor to visit)
+    let mut visited = HashSet::new();
+    let mut stack = vec![(entry, successors(func_data, entry), 0)];
+    visited.insert(entry);
+    while let Some((bb, succs, i)) = stack.last_mut() {
+        if *i < succs.len() {
+            let succ = succs[*i];
+            *i += 1;
+            if visited.insert(succ) {
+                let next = successors(func_data, succ);
+                stack.push((succ, next, 0));
+            }
+        }else {
+            order.push(*bb);
+            stack.pop();
+        }
+    }
+    order.reverse();
+    order
+}
+
+/* Reachable blocks contain a cycle: some edge goes back in reverse post order */
+pub fn has_cycle(func_data: &FunctionData) -> bool {
+    let order = reverse_post_order(func_data);
+    let index: HashMap<BasicBlock, usize> = order.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+    order.iter().any(|&bb| successors(func_data, bb).iter().any(|succ| index[succ] <= index[&bb]))
+}
+
+/* Remove blocks that can't be reached from entry, with their instructions */
+pub fn remove_unreachable(func_data: &mut FunctionData) {
+    let reachable: HashSet<BasicBlock> = reverse_post_order(func_data).into_iter().collect();
+    let dead: Vec<BasicBlock> = func_data.layout().bbs().keys().copied().filter(|bb| !reachable.contains(bb)).collect();
+    remove_blocks(func_data, dead);
+}
+
+/* Remove blocks nothing else jumps to, with their instructions */
+pub fn remove_blocks(func_data: &mut FunctionData, dead: Vec<BasicBlock>) {
+    let mut pending = vec![];
+    for &bb in &dead {
+        let (_, node) = func_data.layout_mut().bbs_mut().remove(&bb).unwrap();
+        pending.extend(node.insts().keys().copied());
+    }
+    // Dead values only use each other, remove users first
+    while !pending.is_empty() {
+        let before = pending.len();
+        pending.retain(|&inst| {
+            if func_data.dfg().value(inst).used_by().is_empty() {
+                func_data.dfg_mut().remove_value(inst);
+                false
+            }else {
+                true
+            }
+        });
+        assert!(pending.len() < before, "dead instructions used by live ones");
+    }
+    for bb in dead {
+        func_data.dfg_mut().remove_bb(bb);
+    }
+}
@@ -0,0 +1,96 @@
+/* Uses */
+use koopa::ir::*;
+use crate::opt::{self, callgraph};
+use crate::opt::effects::Summary;
+
+/* Dead code elimination: unused pure instructions, and unused calls to
+ * functions without side effects that are known to return.
+ */
+pub fn run(program: &mut Program) {
+    let summary = Summary::new(program);
+    for func in program.func_layout().to_vec() {
+        if callgraph::is_decl(program, func) {
+            continue;
+        }
+        let func_data = program.func_mut(func);
+        loop {
+            opt::remove_dead_values(func_data);
+            let mut dead = vec![];
+            for (_, node) in func_data.layout().bbs() {
+                for &inst in node.insts().keys() {
+                    let data = func_data.dfg().value(inst);
+                    if let ValueKind::Call(call) = data.kind() {
+                        if data.used_by().is_empty() && summary.get(call.callee()).is_removable() {
+                            dead.push(inst);
+                        }
+                    }
+                }
+            }
+            if dead.is_empty() {
+                break;
+            }
+            for inst in dead {
+                opt::remove_inst(func_data, inst);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+
+    /* Callees still called from @main after dce */
+    fn called(text: &str) -> Vec<String> {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program);
+        let main = *program.func_layout().iter().find(|&&func| program.func(func).name() == "@main").unwrap();
+        let func_data = program.func(main);
+        let mut names = vec![];
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Call(call) = func_data.dfg().value(inst).kind() {
+                    names.push(program.func(call.callee()).name().to_string());
+                }
+            }
+        }
+        names
+    }
+
+    #[test]
+    fn keeps_calls_that_may_not_return() {
+        let names = called(r#"
+            fun @twice(@x: i32): i32 {
+            %entry:
+              %0 = mul @x, 2
+              ret %0
+            }
+
+            fun @spin(@x: i32): i32 {
+            %entry:
+              jump %loop
+            %loop:
+              %0 = ne @x, 0
+              br %0, %loop, %end
+            %end:
+              ret 0
+            }
+
+            fun @down(@x: i32): i32 {
+            %entry:
+              %0 = call @down(@x)
+              ret %0
+            }
+
+            fun @main(): i32 {
+            %entry:
+              %0 = call @twice(1)
+              %1 = call @spin(1)
+              %2 = call @down(1)
+              ret 0
+            }
+        "#);
+        assert_eq!(names, vec!["@spin", "@down"]);
+    }
+}
@@ -0,0 +1,142 @@
+/* Uses */
+use std::collections::HashMap;
+use koopa::ir::*;
+use crate::opt::cfg;
+
+/* Dominator tree of the reachable blocks (Cooper, Harvey & Kennedy) */
+pub struct DomTree {
+    // Reachable blocks in reverse post order
+    pub order: Vec<BasicBlock>,
+    idom: HashMap<BasicBlock, BasicBlock>,
+    children: HashMap<BasicBlock, Vec<BasicBlock>>,
+}
+
+impl DomTree {
+    pub fn new(func_data: &FunctionData) -> DomTree {
+        let order = cfg::reverse_post_order(func_data);
+        let preds = cfg::predecessors(func_data);
+        let index: HashMap<BasicBlock, usize> = order.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+
+        let mut idom: HashMap<BasicBlock, BasicBlock> = HashMap::new();
+        if let Some(&entry) = order.first() {
+            idom.insert(entry, entry);
+        }
+        let intersect = |idom: &HashMap<BasicBlock, BasicBlock>, mut a: BasicBlock, mut b: BasicBlock| {
+            while a != b {
+                while index[&a] > index[&b] {
+                    a = idom[&a];
+                }
+                while index[&b] > index[&a] {
+                    b = idom[&b];
+                }
+            }
+            a
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in order.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &preds[&bb] {
+                    if !idom.contains_key(&pred) {
+                        continue;
+                    }
+                    new_idom = match new_idom {
+                        None => Some(pred),
+                        Some(cur) => Some(intersect(&idom, pred, cur)),
+                    };
+                }
+                let new_idom = new_idom.unwrap();
+                if idom.get(&bb) != Some(&new_idom) {
+                    idom.insert(bb, new_idom);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for &bb in order.iter().skip(1) {
+            children.entry(idom[&bb]).or_default().push(bb);
+        }
+        DomTree { order, idom, children }
+    }
+
+    /* Immediate dominator, None for the entry */
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        match self.idom.get(&bb) {
+            Some(&d) if d != bb => Some(d),
+            _ => None,
+        }
+    }
+
+    pub fn children(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.children.get(&bb).map_or(&[], |c| c.as_slice())
+    }
+
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool {
+        self.idom.contains_key(&bb)
+    }
+
+    /* a dominates b (every block dominates itself) */
+    pub fn dominates(&self, a: BasicBlock, mut b: BasicBlock) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom(b) {
+                Some(d) => b = d,
+                None => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use koopa::ir::builder::*;
+
+    #[test]
+    fn diamond_and_unreachable_block() {
+        let mut program = Driver::from(r#"
+            fun @f(@x: i32): i32 {
+            %entry:
+              br @x, %then, %else
+            %then:
+              jump %join
+            %else:
+              jump %join
+            %join:
+              ret 0
+            }
+        "#).generate_program().unwrap();
+        // The parser drops blocks nothing jumps to, so add one by hand
+        let func = program.func_layout()[0];
+        let func_data = program.func_mut(func);
+        let join = *func_data.layout().bbs().keys().last().unwrap();
+        let dead = func_data.dfg_mut().new_bb().basic_block(Some("%dead".to_string()));
+        func_data.layout_mut().bbs_mut().push_key_back(dead).unwrap();
+        let jump = func_data.dfg_mut().new_value().jump(join);
+        func_data.layout_mut().bb_mut(dead).insts_mut().push_key_back(jump).unwrap();
+
+        let func_data = program.func(func);
+        let bb = |name: &str| *func_data.layout().bbs().keys().find(|&&bb| func_data.dfg().bb(bb).name().as_deref() == Some(name)).unwrap();
+        let dom = DomTree::new(func_data);
+
+        assert_eq!(dom.idom(bb("%entry")), None);
+        assert_eq!(dom.idom(bb("%then")), Some(bb("%entry")));
+        assert_eq!(dom.idom(bb("%join")), Some(bb("%entry")));
+        assert!(dom.dominates(bb("%join"), bb("%join")));
+        assert!(!dom.dominates(bb("%then"), bb("%join")));
+        assert_eq!(dom.children(bb("%entry")).len(), 3);
+
+        // %dead jumps to %join but is not reached, so it takes no part
+        assert!(!dom.is_reachable(bb("%dead")));
+        assert!(!dom.dominates(bb("%entry"), bb("%dead")));
+        assert_eq!(dom.order.len(), 4);
+    }
+}
@@ -0,0 +1,195 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use crate::opt::{alias, cfg};
+use crate::opt::callgraph::{self, CallGraph};
+
+/* Memory and I/O effects of a function, as seen by its callers */
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct Effects {
+    // Globals read or written
+    pub reads: HashSet<Value>,
+    pub writes: HashSet<Value>,
+    // Memory reached through pointer parameters
+    pub reads_params: bool,
+    pub writes_params: bool,
+    // Input/output through the runtime
+    pub io: bool,
+    // Loops or recursion, so a call may never return
+    pub may_diverge: bool,
+}
+
+impl Effects {
+    /* Result only depends on the arguments */
+    pub fn is_pure(&self) -> bool {
+        self.reads.is_empty() && !self.reads_params && self.is_readonly()
+    }
+
+    /* No writes and no I/O */
+    pub fn is_readonly(&self) -> bool {
+        self.writes.is_empty() && !self.writes_params && !self.io
+    }
+
+    /* Nothing observable happens and the call returns, so an unused call can be dropped */
+    pub fn is_removable(&self) -> bool {
+        self.is_readonly() && !self.may_diverge
+    }
+
+    fn merge(&mut self, other: &Effects) {
+        self.reads.extend(other.reads.iter().copied());
+        self.writes.extend(other.writes.iter().copied());
+        self.reads_params |= other.reads_params;
+        self.writes_params |= other.writes_params;
+        self.io |= other.io;
+        self.may_diverge |= other.may_diverge;
+    }
+}
+
+/* Effects of the runtime library */
+fn runtime_effects(program: &Program, func: Function) -> Effects {
+    let mut effects = Effects { io: true, ..Default::default() };
+    match program.func(func).name() {
+        "@getint" | "@getch" | "@putint" | "@putch" | "@starttime" | "@stoptime" => {}
+        "@getarray" => effects.writes_params = true,
+        "@putarray" => effects.reads_params = true,
+        _ => {
+            // Unknown declaration: anything may happen
+            effects.reads.extend(program.inst_layout().iter().copied());
+            effects.writes.extend(program.inst_layout().iter().copied());
+            effects.reads_params = true;
+            effects.writes_params = true;
+            effects.may_diverge = true;
+        }
+    }
+    effects
+}
+
+/* Bottom-up effect summaries of all functions */
+pub struct Summary {
+    effects: HashMap<Function, Effects>,
+}
+
+impl Summary {
+    pub fn new(program: &Program) -> Summary {
+        let graph = CallGraph::new(program);
+        let mut summary = Summary { effects: HashMap::new() };
+        for scc in graph.sccs() {
+            if let [func] = scc.as_slice() {
+                if callgraph::is_decl(program, *func) {
+                    summary.effects.insert(*func, runtime_effects(program, *func));
+                    continue;
+                }
+            }
+            let recursive = scc.len() > 1 || graph.callees[&scc[0]].contains(&scc[0]);
+            // Recursive calls see the summary of the previous round
+            for &func in &scc {
+                summary.effects.insert(func, Effects::default());
+            }
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for &func in &scc {
+                    let mut effects = summary.local_effects(program.func(func));
+                    effects.may_diverge |= recursive;
+                    if effects != summary.effects[&func] {
+                        summary.effects.insert(func, effects);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        summary
+    }
+
+    pub fn get(&self, func: Function) -> &Effects {
+        &self.effects[&func]
+    }
+
+    /* Effects of one function body, given the summaries of its callees */
+    fn local_effects(&self, func_data: &FunctionData) -> Effects {
+        // Globals whose address is stored may be reached through unknown pointers
+        let mut escaped = HashSet::new();
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Store(store) = func_data.dfg().value(inst).kind() {
+                    match alias::pointer_root(func_data, store.value()) {
+                        Some(root) if root.is_global() => {
+                            escaped.insert(root);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let mut effects = Effects { may_diverge: cfg::has_cycle(func_data), ..Default::default() };
+        let access = |ptr: Value, globals: &mut HashSet<Value>, params: &mut bool| {
+            match alias::pointer_root(func_data, ptr) {
+                Some(root) if root.is_global() => {
+                    globals.insert(root);
+                }
+                Some(_) => {}
+                None => {
+                    *params = true;
+                    globals.extend(escaped.iter().copied());
+                }
+            }
+        };
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Load(load) => access(load.src(), &mut effects.reads, &mut effects.reads_params),
+                    ValueKind::Store(store) => access(store.dest(), &mut effects.writes, &mut effects.writes_params),
+                    ValueKind::Call(call) => {
+                        let callee = &self.effects[&call.callee()];
+                        let (reads_params, writes_params) = (callee.reads_params, callee.writes_params);
+                        effects.merge(&Effects { reads_params: false, writes_params: false, ..callee.clone() });
+                        // Callee accesses through its parameters reach our arguments
+                        for &arg in call.args() {
+                            if !is_pointer(func_data, arg) {
+                                continue;
+                            }
+                            if reads_params {
+                                access(arg, &mut effects.reads, &mut effects.reads_params);
+                            }
+                            if writes_params {
+                                access(arg, &mut effects.writes, &mut effects.writes_params);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        effects
+    }
+
+    /* Call instruction may write the memory `ptr` points to */
+    pub fn call_may_write(&self, func_data: &FunctionData, call: Value, ptr: Value) -> bool {
+        let call = match func_data.dfg().value(call).kind() {
+            ValueKind::Call(call) => call,
+            _ => return false,
+        };
+        let effects = &self.effects[&call.callee()];
+        let through_args = |root: Option<Value>| {
+            effects.writes_params && call.args().iter().any(|&arg| {
+                is_pointer(func_data, arg) && match alias::pointer_root(func_data, arg) {
+                    Some(arg_root) => Some(arg_root) == root,
+                    None => true,
+                }
+            })
+        };
+        match alias::pointer_root(func_data, ptr) {
+            Some(root) if root.is_global() => effects.writes.contains(&root) || through_args(Some(root)),
+            Some(root) => !alias::is_scalar_alloc(func_data, root) && through_args(Some(root)),
+            None => effects.writes_params || !effects.writes.is_empty(),
+        }
+    }
+}
+
+fn is_pointer(func_data: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return true;
+    }
+    matches!(func_data.dfg().value(value).ty().kind(), TypeKind::Pointer(_))
+}
@@ -0,0 +1,285 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use koopa::ir::builder::*;
+use crate::opt::{self, alias, callgraph, cfg, sccp};
+use crate::opt::callgraph::CallGraph;
+use crate::opt::sccp::Init;
+
+/* Module-level dead code elimination
+ * Functions not reachable from main, runtime declarations nobody calls and
+ * globals nobody reads are removed. Loads of read-only globals become constants.
+ */
+pub fn run(program: &mut Program) {
+    remove_dead_funcs(program);
+    fold_readonly_loads(program);
+    remove_dead_globals(program);
+}
+
+fn live_funcs(program: &Program) -> Vec<Function> {
+    program.func_layout().iter().copied().filter(|&f| !callgraph::is_decl(program, f)).collect()
+}
+
+fn remove_dead_funcs(program: &mut Program) {
+    let main = match program.func_layout().iter().find(|&&f| program.func(f).name() == "@main") {
+        Some(&main) => main,
+        None => return,
+    };
+    let graph = CallGraph::new(program);
+    let mut reached = HashSet::new();
+    let mut stack = vec![main];
+    while let Some(func) = stack.pop() {
+        if reached.insert(func) {
+            stack.extend(graph.callees[&func].iter().copied());
+        }
+    }
+    for func in program.func_layout().to_vec() {
+        if reached.contains(&func) {
+            continue;
+        }
+        // Drop the body first, so globals forget its uses
+        let func_data = program.func_mut(func);
+        let bbs = func_data.layout().bbs().keys().copied().collect();
+        cfg::remove_blocks(func_data, bbs);
+        program.remove_func(func);
+    }
+}
+
+/* Constant indices from a load address back to its global */
+fn global_access(func_data: &FunctionData, mut ptr: Value) -> Option<(Value, Vec<i32>)> {
+    let mut indices = vec![];
+    while !ptr.is_global() {
+        match func_data.dfg().value(ptr).kind() {
+            ValueKind::GetElemPtr(gep) => {
+                match func_data.dfg().value(gep.index()).kind() {
+                    ValueKind::Integer(int) => indices.push(int.value()),
+                    _ => return None,
+                }
+                ptr = gep.src();
+            }
+            _ => return None,
+        }
+    }
+    indices.reverse();
+    Some((ptr, indices))
+}
+
+fn fold_readonly_loads(program: &mut Program) {
+    let globals: HashMap<Value, Init> = sccp::readonly_globals(program);
+    for func in live_funcs(program) {
+        let func_data = program.func_mut(func);
+        let mut folded = vec![];
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Load(load) = func_data.dfg().value(inst).kind() {
+                    let value = global_access(func_data, load.src()).and_then(|(g, indices)| globals.get(&g)?.get(&indices));
+                    if let Some(value) = value {
+                        folded.push((inst, value));
+                    }
+                }
+            }
+        }
+        for (inst, value) in folded {
+            let int = func_data.dfg_mut().new_value().integer(value);
+            opt::replace_uses(func_data, inst, int);
+            opt::remove_inst(func_data, inst);
+        }
+        opt::remove_dead_values(func_data);
+    }
+}
+
+/* Globals that are never read and whose address never escapes are only
+ * written, so their stores can go as well.
+ */
+fn remove_dead_globals(program: &mut Program) {
+    let mut needed = HashSet::new();
+    for func in live_funcs(program) {
+        let func_data = program.func(func);
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let mut used = vec![];
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Load(load) => used.push(load.src()),
+                    ValueKind::Store(store) => used.push(store.value()),
+                    ValueKind::Call(call) => used.extend(call.args().iter().copied()),
+                    ValueKind::GetPtr(gp) => used.push(gp.src()),
+                    _ => {}
+                }
+                needed.extend(used.into_iter().filter_map(|ptr| alias::pointer_root(func_data, ptr)));
+            }
+        }
+    }
+    let dead: HashSet<Value> = program.inst_layout().iter().copied().filter(|g| !needed.contains(g)).collect();
+    if dead.is_empty() {
+        return;
+    }
+
+    for func in live_funcs(program) {
+        let func_data = program.func_mut(func);
+        let mut stores = vec![];
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Store(store) = func_data.dfg().value(inst).kind() {
+                    if alias::pointer_root(func_data, store.dest()).is_some_and(|root| dead.contains(&root)) {
+                        stores.push(inst);
+                    }
+                }
+            }
+        }
+        for inst in stores {
+            opt::remove_inst(func_data, inst);
+        }
+        opt::remove_dead_values(func_data);
+    }
+
+    for global in program.inst_layout().to_vec() {
+        if dead.contains(&global) && program.borrow_value(global).used_by().is_empty() {
+            remove_global(program, global);
+        }
+    }
+}
+
+/* Remove a global value with its initializer */
+fn remove_global(program: &mut Program, value: Value) {
+    let data = program.remove_value(value);
+    for used in data.kind().value_uses() {
+        if program.borrow_value(used).used_by().is_empty() {
+            remove_global(program, used);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    fn gdce(text: &str) -> String {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program);
+        assert_eq!(verify::verify(&program), Ok(()));
+        crate::dump::gen_text_koopa(&program)
+    }
+
+    #[test]
+    fn removes_functions_main_never_reaches() {
+        assert_eq!(gdce(r#"
+            decl @getint(): i32
+            decl @putint(i32)
+
+            fun @unused(): i32 {
+            %entry:
+              %0 = call @getint()
+              ret %0
+            }
+
+            fun @used(@x: i32) {
+            %entry:
+              call @putint(@x)
+              ret
+            }
+
+            fun @main(): i32 {
+            %entry:
+              call @used(1)
+              ret 0
+            }
+        "#), r#"decl @putint(i32)
+
+fun @used(@x: i32) {
+%entry:
+  call @putint(@x)
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  call @used(1)
+  ret 0
+}
+"#);
+    }
+
+    #[test]
+    fn folds_loads_of_readonly_globals() {
+        assert_eq!(gdce(r#"
+            global @n = alloc i32, 5
+            global @table = alloc [i32, 3], {1, 2, 3}
+
+            fun @main(): i32 {
+            %entry:
+              %0 = load @n
+              %1 = getelemptr @table, 2
+              %2 = load %1
+              %3 = add %0, %2
+              ret %3
+            }
+        "#), r#"fun @main(): i32 {
+%entry:
+  %0 = add 5, 3
+  ret %0
+}
+"#);
+    }
+
+    #[test]
+    fn drops_globals_that_are_only_written() {
+        assert_eq!(gdce(r#"
+            global @sink = alloc i32, 0
+            global @read = alloc i32, 0
+
+            fun @main(): i32 {
+            %entry:
+              store 1, @sink
+              store 2, @read
+              %0 = load @read
+              ret %0
+            }
+        "#), r#"global @read = alloc i32, 0
+
+fun @main(): i32 {
+%entry:
+  store 2, @read
+  %0 = load @read
+  ret %0
+}
+"#);
+    }
+
+    #[test]
+    fn keeps_globals_written_through_a_pointer_argument() {
+        assert_eq!(gdce(r#"
+            global @buf = alloc [i32, 2], zeroinit
+
+            fun @set(@p: *i32) {
+            %entry:
+              %0 = getptr @p, 1
+              store 9, %0
+              ret
+            }
+
+            fun @main(): i32 {
+            %entry:
+              %0 = getelemptr @buf, 0
+              call @set(%0)
+              ret 0
+            }
+        "#), r#"global @buf = alloc [i32, 2], zeroinit
+
+fun @set(@p: *i32) {
+%entry:
+  %0 = getptr @p, 1
+  store 9, %0
+  ret
+}
+
+fun @main(): i32 {
+%entry:
+  %1 = getelemptr @buf, 0
+  call @set(%1)
+  ret 0
+}
+"#);
+    }
+}
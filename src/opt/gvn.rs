@@ -0,0 +1,269 @@
+/* Uses */
+use std::collections::HashMap;
+use koopa::ir::*;
+use crate::opt::{self, alias, callgraph, cfg};
+use crate::opt::dom::DomTree;
+use crate::opt::effects::Summary;
+
+/* Global value numbering over the dominator tree
+ * Pure binary and address computations are reused from dominating blocks.
+ * Loads reuse an earlier load or store of the same pointer when nothing in
+ * between may write it, checking calls against their effect summaries.
+ * Memory facts only follow single-predecessor edges.
+ */
+pub fn run(program: &mut Program) {
+    let summary = Summary::new(program);
+    for func in program.func_layout().to_vec() {
+        if callgraph::is_decl(program, func) {
+            continue;
+        }
+        gvn_func(program.func_mut(func), &summary);
+    }
+}
+
+/* Operand number: constants compare by value */
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Num {
+    Int(i32),
+    Val(Value),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Expr {
+    Binary(BinaryOp, Num, Num),
+    GetElemPtr(Num, Num),
+    GetPtr(Num, Num),
+}
+
+fn is_commutative(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor | BinaryOp::Eq | BinaryOp::NotEq)
+}
+
+fn num(func_data: &FunctionData, value: Value) -> Num {
+    if value.is_global() {
+        return Num::Val(value);
+    }
+    match func_data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Num::Int(int.value()),
+        _ => Num::Val(value),
+    }
+}
+
+fn expr(func_data: &FunctionData, inst: Value) -> Option<Expr> {
+    match func_data.dfg().value(inst).kind() {
+        ValueKind::Binary(bin) => Some(Expr::Binary(bin.op(), num(func_data, bin.lhs()), num(func_data, bin.rhs()))),
+        ValueKind::GetElemPtr(gep) => Some(Expr::GetElemPtr(num(func_data, gep.src()), num(func_data, gep.index()))),
+        ValueKind::GetPtr(gp) => Some(Expr::GetPtr(num(func_data, gp.src()), num(func_data, gp.index()))),
+        _ => None,
+    }
+}
+
+/* Available expressions, scoped by the dominator tree */
+struct Table {
+    map: HashMap<Expr, Value>,
+    // Keys added in each open scope
+    scopes: Vec<Vec<Expr>>,
+}
+
+impl Table {
+    fn lookup(&self, e: Expr) -> Option<Value> {
+        if let Some(&v) = self.map.get(&e) {
+            return Some(v);
+        }
+        match e {
+            Expr::Binary(op, lhs, rhs) if is_commutative(op) => self.map.get(&Expr::Binary(op, rhs, lhs)).copied(),
+            _ => None,
+        }
+    }
+
+    fn insert(&mut self, e: Expr, v: Value) {
+        self.map.insert(e, v);
+        self.scopes.last_mut().unwrap().push(e);
+    }
+
+    fn pop_scope(&mut self) {
+        for e in self.scopes.pop().unwrap() {
+            self.map.remove(&e);
+        }
+    }
+}
+
+/* Known memory contents: pointer -> value */
+type Memory = HashMap<Value, Value>;
+
+fn gvn_func(func_data: &mut FunctionData, summary: &Summary) {
+    let dom = DomTree::new(func_data);
+    let preds = cfg::predecessors(func_data);
+    let mut table = Table { map: HashMap::new(), scopes: vec![] };
+
+    // Dfs over the dominator tree; None marks the end of a scope
+    let entry = match dom.order.first() {
+        Some(&entry) => entry,
+        None => return,
+    };
+    let mut stack = vec![Some((entry, Memory::new()))];
+    while let Some(item) = stack.pop() {
+        let (bb, mut memory) = match item {
+            Some(item) => item,
+            None => {
+                table.pop_scope();
+                continue;
+            }
+        };
+        table.scopes.push(vec![]);
+        visit_block(func_data, bb, &mut table, &mut memory, summary);
+        stack.push(None);
+        for &child in dom.children(bb) {
+            // Memory facts only hold if bb is the only way into child
+            let inherited = if preds[&child].as_slice() == [bb] { memory.clone() } else { Memory::new() };
+            stack.push(Some((child, inherited)));
+        }
+    }
+}
+
+fn visit_block(func_data: &mut FunctionData, bb: BasicBlock, table: &mut Table, memory: &mut Memory, summary: &Summary) {
+    let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+    for inst in insts {
+        if let Some(e) = expr(func_data, inst) {
+            match table.lookup(e) {
+                Some(v) => {
+                    opt::replace_uses(func_data, inst, v);
+                    opt::remove_inst(func_data, inst);
+                }
+                None => table.insert(e, inst),
+            }
+            continue;
+        }
+        match func_data.dfg().value(inst).kind() {
+            ValueKind::Load(load) => {
+                let src = load.src();
+                match memory.get(&src) {
+                    Some(&v) => {
+                        opt::replace_uses(func_data, inst, v);
+                        opt::remove_inst(func_data, inst);
+                    }
+                    None => {
+                        memory.insert(src, inst);
+                    }
+                }
+            }
+            ValueKind::Store(store) => {
+                let (value, dest) = (store.value(), store.dest());
+                memory.retain(|&ptr, _| !alias::may_alias(func_data, ptr, dest));
+                memory.insert(dest, value);
+            }
+            ValueKind::Call(_) => {
+                memory.retain(|&ptr, _| !summary.call_may_write(func_data, inst, ptr));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    /* Koopa text of a program after gvn */
+    fn gvn(text: &str) -> String {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program);
+        assert_eq!(verify::verify(&program), Ok(()));
+        crate::dump::gen_text_koopa(&program)
+    }
+
+    #[test]
+    fn redundant_load_is_removed() {
+        assert_eq!(gvn(r#"
+            fun @f(@p: *i32): i32 {
+            %entry:
+              %a = load @p
+              %b = load @p
+              %s = add %a, %b
+              store %s, @p
+              %c = load @p
+              %t = add %s, %c
+              ret %t
+            }
+        "#), r#"fun @f(@p: *i32): i32 {
+%entry:
+  %a = load @p
+  %s = add %a, %a
+  store %s, @p
+  %t = add %s, %s
+  ret %t
+}
+"#);
+    }
+
+    #[test]
+    fn load_is_kept_after_a_call_that_writes() {
+        assert_eq!(gvn(r#"
+            global @g = alloc i32, 0
+
+            fun @bump() {
+            %entry:
+              store 1, @g
+              ret
+            }
+
+            fun @pure(@x: i32): i32 {
+            %entry:
+              ret @x
+            }
+
+            fun @f(): i32 {
+            %entry:
+              %a = load @g
+              %x = call @pure(%a)
+              %b = load @g
+              call @bump()
+              %c = load @g
+              %s = add %b, %c
+              ret %s
+            }
+        "#).split("fun @f").nth(1).unwrap(), r#"(): i32 {
+%entry:
+  %a = load @g
+  %x = call @pure(%a)
+  call @bump()
+  %c = load @g
+  %s = add %a, %c
+  ret %s
+}
+"#);
+    }
+
+    #[test]
+    fn facts_do_not_cross_a_join() {
+        assert_eq!(gvn(r#"
+            fun @f(@p: *i32, @c: i32): i32 {
+            %entry:
+              store 0, @p
+              br @c, %then, %join
+            %then:
+              %a = load @p
+              store 1, @p
+              jump %join
+            %join:
+              %b = load @p
+              ret %b
+            }
+        "#), r#"fun @f(@p: *i32, @c: i32): i32 {
+%entry:
+  store 0, @p
+  br @c, %then, %join
+
+%then:
+  store 1, @p
+  jump %join
+
+%join:
+  %b = load @p
+  ret %b
+}
+"#);
+    }
+}
@@ -0,0 +1,335 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use koopa::ir::builder::*;
+use koopa::ir::entities::ValueData;
+use crate::opt::callgraph::{self, CallGraph};
+use crate::opt::cfg;
+
+/* Cost model (sizes count instructions in the layout) */
+// Callees this small are always inlined
+const INLINE_SIZE: usize = 40;
+// Callees with a single call site may be larger
+const INLINE_ONCE_SIZE: usize = 400;
+// Stop inlining into a caller once it grows beyond this
+const CALLER_SIZE_LIMIT: usize = 4000;
+
+fn func_size(func_data: &FunctionData) -> usize {
+    func_data.layout().bbs().nodes().map(|node| node.insts().len()).sum()
+}
+
+/* Inline small, non-recursive functions into their callers */
+pub fn run(program: &mut Program) {
+    let graph = CallGraph::new(program);
+    let recursive = graph.recursive_funcs();
+    // Call sites per function, kept up to date while inlining
+    let mut counts: HashMap<Function, usize> = graph.funcs.iter().map(|&func| (func, graph.call_count(func))).collect();
+
+    // Bottom-up, so callees are already inlined into when they are cloned
+    for scc in graph.sccs() {
+        for caller in scc {
+            if callgraph::is_decl(program, caller) {
+                continue;
+            }
+            inline_calls(program, caller, &mut counts, &recursive);
+        }
+    }
+}
+
+fn should_inline(program: &Program, caller: Function, callee: Function, counts: &HashMap<Function, usize>, recursive: &HashSet<Function>) -> bool {
+    if callee == caller || recursive.contains(&callee) || callgraph::is_decl(program, callee) {
+        return false;
+    }
+    if func_size(program.func(caller)) > CALLER_SIZE_LIMIT {
+        return false;
+    }
+    let size = func_size(program.func(callee));
+    size <= INLINE_SIZE || (size <= INLINE_ONCE_SIZE && counts[&callee] == 1)
+}
+
+fn inline_calls(program: &mut Program, caller: Function, counts: &mut HashMap<Function, usize>, recursive: &HashSet<Function>) {
+    let mut rejected = HashSet::new();
+    loop {
+        // Find next call site to inline
+        let mut site = None;
+        let func_data = program.func(caller);
+        'find: for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Call(call) = func_data.dfg().value(inst).kind() {
+                    if rejected.contains(&inst) {
+                        continue;
+                    }
+                    if should_inline(program, caller, call.callee(), counts, recursive) {
+                        site = Some(inst);
+                        break 'find;
+                    }
+                    rejected.insert(inst);
+                }
+            }
+        }
+        match site {
+            Some(call) => inline_call(program, caller, call, counts),
+            None => break,
+        }
+    }
+}
+
+/* Copy of the callee body, taken before the caller is borrowed mutably */
+struct Body {
+    // Blocks with their params and instructions
+    bbs: Vec<(BasicBlock, Vec<Value>, Vec<Value>)>,
+    values: HashMap<Value, ValueData>,
+    ret_ty: Type,
+}
+
+impl Body {
+    fn new(func_data: &FunctionData) -> Body {
+        // Reverse post order: definitions are cloned before their uses
+        let mut bbs = vec![];
+        for bb in cfg::reverse_post_order(func_data) {
+            let params = func_data.dfg().bb(bb).params().to_vec();
+            let insts = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            bbs.push((bb, params, insts));
+        }
+        let ret_ty = match func_data.ty().kind() {
+            TypeKind::Function(_, ret_ty) => ret_ty.clone(),
+            _ => unreachable!(),
+        };
+        Body { bbs, values: func_data.dfg().values().clone(), ret_ty }
+    }
+}
+
+/* Maps callee values into the caller */
+struct Cloner<'a> {
+    body: &'a Body,
+    args: Vec<Value>,
+    value_map: HashMap<Value, Value>,
+    bb_map: HashMap<BasicBlock, BasicBlock>,
+}
+
+impl Cloner<'_> {
+    fn value(&mut self, value: Value, func_data: &mut FunctionData) -> Value {
+        if value.is_global() {
+            return value;
+        }
+        if let Some(&v) = self.value_map.get(&value) {
+            return v;
+        }
+        let data = &self.body.values[&value];
+        let v = match data.kind() {
+            ValueKind::Integer(int) => func_data.dfg_mut().new_value().integer(int.value()),
+            ValueKind::ZeroInit(_) => func_data.dfg_mut().new_value().zero_init(data.ty().clone()),
+            ValueKind::Undef(_) => func_data.dfg_mut().new_value().undef(data.ty().clone()),
+            ValueKind::FuncArgRef(arg) => self.args[arg.index()],
+            _ => panic!("callee value used before its definition"),
+        };
+        self.value_map.insert(value, v);
+        v
+    }
+
+    fn values(&mut self, values: &[Value], func_data: &mut FunctionData) -> Vec<Value> {
+        values.iter().map(|&v| self.value(v, func_data)).collect()
+    }
+}
+
+/* Replace one call with a copy of the callee body
+ *   bb: ... call ... => bb: ... jump %callee_entry
+ *   callee blocks, `ret v` => store v, %ret; jump %cont
+ *   cont: %call = load %ret ...
+ */
+fn inline_call(program: &mut Program, caller: Function, call: Value, counts: &mut HashMap<Function, usize>) {
+    let (callee, args) = match program.func(caller).dfg().value(call).kind() {
+        ValueKind::Call(c) => (c.callee(), c.args().to_vec()),
+        _ => unreachable!(),
+    };
+    let body = Body::new(program.func(callee));
+    let func_data = program.func_mut(caller);
+    let entry = func_data.layout().entry_bb().unwrap();
+
+    // Split the block at the call
+    let bb = func_data.layout().parent_bb(call).unwrap();
+    let cont_bb = func_data.dfg_mut().new_bb().basic_block(None);
+    func_data.layout_mut().bbs_mut().cursor_mut(bb).insert_key_after(cont_bb).unwrap();
+    let mut moving = vec![];
+    let mut cursor = func_data.layout().bbs().node(&bb).unwrap().insts().cursor(call);
+    while let Some(&inst) = cursor.key() {
+        moving.push(inst);
+        cursor.move_next();
+    }
+    for inst in moving {
+        func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        func_data.layout_mut().bb_mut(cont_bb).insts_mut().push_key_back(inst).unwrap();
+    }
+
+    // Slot for the return value
+    let ret_slot = if body.ret_ty.is_unit() {
+        None
+    }else {
+        let slot = func_data.dfg_mut().new_value().alloc(body.ret_ty.clone());
+        func_data.layout_mut().bb_mut(entry).insts_mut().push_key_front(slot).unwrap();
+        Some(slot)
+    };
+
+    // New blocks for the callee body
+    let mut cloner = Cloner { body: &body, args, value_map: HashMap::new(), bb_map: HashMap::new() };
+    for (old_bb, params, _) in &body.bbs {
+        let params_ty = params.iter().map(|param| body.values[param].ty().clone()).collect();
+        let new_bb = func_data.dfg_mut().new_bb().basic_block_with_params(None, params_ty);
+        func_data.layout_mut().bbs_mut().cursor_mut(cont_bb).insert_key_before(new_bb).unwrap();
+        for (&param, &new_param) in params.iter().zip(func_data.dfg().bb(new_bb).params()) {
+            cloner.value_map.insert(param, new_param);
+        }
+        cloner.bb_map.insert(*old_bb, new_bb);
+    }
+
+    // Clone instructions
+    for (old_bb, _, insts) in &body.bbs {
+        let new_bb = cloner.bb_map[old_bb];
+        for &inst in insts {
+            let data = &body.values[&inst];
+            let new_insts = match data.kind() {
+                ValueKind::Alloc(_) => {
+                    let ty = match data.ty().kind() {
+                        TypeKind::Pointer(base) => base.clone(),
+                        _ => unreachable!(),
+                    };
+                    // Hoisted, so the slot is allocated once per caller frame
+                    let alloc = func_data.dfg_mut().new_value().alloc(ty);
+                    func_data.layout_mut().bb_mut(entry).insts_mut().push_key_front(alloc).unwrap();
+                    cloner.value_map.insert(inst, alloc);
+                    continue;
+                }
+                ValueKind::Load(load) => {
+                    let src = cloner.value(load.src(), func_data);
+                    vec![func_data.dfg_mut().new_value().load(src)]
+                }
+                ValueKind::Store(store) => {
+                    let value = cloner.value(store.value(), func_data);
+                    let dest = cloner.value(store.dest(), func_data);
+                    vec![func_data.dfg_mut().new_value().store(value, dest)]
+                }
+                ValueKind::GetPtr(gp) => {
+                    let src = cloner.value(gp.src(), func_data);
+                    let index = cloner.value(gp.index(), func_data);
+                    vec![func_data.dfg_mut().new_value().get_ptr(src, index)]
+                }
+                ValueKind::GetElemPtr(gep) => {
+                    let src = cloner.value(gep.src(), func_data);
+                    let index = cloner.value(gep.index(), func_data);
+                    vec![func_data.dfg_mut().new_value().get_elem_ptr(src, index)]
+                }
+                ValueKind::Binary(bin) => {
+                    let lhs = cloner.value(bin.lhs(), func_data);
+                    let rhs = cloner.value(bin.rhs(), func_data);
+                    vec![func_data.dfg_mut().new_value().binary(bin.op(), lhs, rhs)]
+                }
+                ValueKind::Branch(br) => {
+                    let cond = cloner.value(br.cond(), func_data);
+                    let true_args = cloner.values(br.true_args(), func_data);
+                    let false_args = cloner.values(br.false_args(), func_data);
+                    let (true_bb, false_bb) = (cloner.bb_map[&br.true_bb()], cloner.bb_map[&br.false_bb()]);
+                    vec![func_data.dfg_mut().new_value().branch_with_args(cond, true_bb, false_bb, true_args, false_args)]
+                }
+                ValueKind::Jump(jump) => {
+                    let args = cloner.values(jump.args(), func_data);
+                    let target = cloner.bb_map[&jump.target()];
+                    vec![func_data.dfg_mut().new_value().jump_with_args(target, args)]
+                }
+                ValueKind::Call(c) => {
+                    let args = cloner.values(c.args(), func_data);
+                    *counts.get_mut(&c.callee()).unwrap() += 1;
+                    vec![func_data.dfg_mut().new_value().call(c.callee(), args)]
+                }
+                ValueKind::Return(ret) => {
+                    let mut list = vec![];
+                    if let (Some(value), Some(slot)) = (ret.value(), ret_slot) {
+                        let value = cloner.value(value, func_data);
+                        list.push(func_data.dfg_mut().new_value().store(value, slot));
+                    }
+                    list.push(func_data.dfg_mut().new_value().jump(cont_bb));
+                    list
+                }
+                _ => unreachable!(),
+            };
+            cloner.value_map.insert(inst, new_insts[0]);
+            for new_inst in new_insts {
+                func_data.layout_mut().bb_mut(new_bb).insts_mut().push_key_back(new_inst).unwrap();
+            }
+        }
+    }
+
+    // bb -> callee entry
+    let callee_entry = cloner.bb_map[&body.bbs[0].0];
+    let jump = func_data.dfg_mut().new_value().jump(callee_entry);
+    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(jump).unwrap();
+
+    *counts.get_mut(&callee).unwrap() -= 1;
+
+    // Call result becomes a load of the return slot
+    match ret_slot {
+        Some(slot) => {
+            func_data.dfg_mut().replace_value_with(call).load(slot);
+        }
+        None => {
+            func_data.layout_mut().bb_mut(cont_bb).insts_mut().remove(&call);
+            func_data.dfg_mut().remove_value(call);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    fn parse(text: &str) -> Program {
+        Driver::from(text).generate_program().unwrap()
+    }
+
+    fn func_named(program: &Program, name: &str) -> Function {
+        *program.func_layout().iter().find(|&&func| program.func(func).name() == name).unwrap()
+    }
+
+    #[test]
+    fn clones_block_params_and_hoists_allocs() {
+        let mut program = parse(r#"
+            fun @count(@n: i32): i32 {
+            %entry:
+              jump %loop(0)
+            %loop(%i: i32):
+              %p = alloc i32
+              store %i, %p
+              %c = lt %i, @n
+              br %c, %body, %end
+            %body:
+              %v = load %p
+              %next = add %v, 1
+              jump %loop(%next)
+            %end:
+              ret %i
+            }
+
+            fun @main(): i32 {
+            %entry:
+              %r = call @count(3)
+              ret %r
+            }
+        "#);
+        run(&mut program);
+        assert_eq!(verify::verify(&program), Ok(()));
+
+        let main = program.func(func_named(&program, "@main"));
+        let entry = main.layout().entry_bb().unwrap();
+        for (&bb, node) in main.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let kind = main.dfg().value(inst).kind();
+                assert!(!matches!(kind, ValueKind::Call(_)), "call left after inlining");
+                if matches!(kind, ValueKind::Alloc(_)) {
+                    assert_eq!(bb, entry, "alloc outside the entry block");
+                }
+            }
+        }
+        assert!(main.layout().bbs().keys().any(|&bb| main.dfg().bb(bb).params().len() == 1));
+    }
+}
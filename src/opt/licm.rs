@@ -0,0 +1,252 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use koopa::ir::builder::*;
+use crate::opt::{self, alias, callgraph, cfg};
+use crate::opt::dom::DomTree;
+use crate::opt::effects::Summary;
+use crate::opt::loops::{Loop, LoopInfo};
+
+/* Loop-invariant code motion: hoist invariant arithmetic, address
+ * computations and loads of memory the loop never writes into the preheader.
+ */
+pub fn run(program: &mut Program) {
+    let summary = Summary::new(program);
+    for func in program.func_layout().to_vec() {
+        if callgraph::is_decl(program, func) {
+            continue;
+        }
+        licm_func(program.func_mut(func), &summary);
+    }
+}
+
+/* Memory written in a loop */
+struct Writes {
+    roots: HashSet<Value>,
+    // Store through an unknown pointer
+    unknown: bool,
+    calls: Vec<Value>,
+}
+
+impl Writes {
+    fn new(func_data: &FunctionData, lp: &Loop) -> Writes {
+        let mut writes = Writes { roots: HashSet::new(), unknown: false, calls: vec![] };
+        for &bb in &lp.blocks {
+            for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys() {
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Store(store) => match alias::pointer_root(func_data, store.dest()) {
+                        Some(root) => {
+                            writes.roots.insert(root);
+                        }
+                        None => writes.unknown = true,
+                    },
+                    ValueKind::Call(_) => writes.calls.push(inst),
+                    _ => {}
+                }
+            }
+        }
+        writes
+    }
+
+    fn may_write(&self, func_data: &FunctionData, summary: &Summary, ptr: Value) -> bool {
+        if self.calls.iter().any(|&call| summary.call_may_write(func_data, call, ptr)) {
+            return true;
+        }
+        match alias::pointer_root(func_data, ptr) {
+            // Scalars never escape, so unknown stores can't reach them
+            Some(root) => self.roots.contains(&root) || (self.unknown && !alias::is_scalar_alloc(func_data, root)),
+            None => true,
+        }
+    }
+}
+
+fn licm_func(func_data: &mut FunctionData, summary: &Summary) {
+    // Give every loop a preheader first, the new blocks join the enclosing loops
+    let (dom, loop_info) = loop {
+        let dom = DomTree::new(func_data);
+        let loop_info = LoopInfo::new(func_data, &dom);
+        let preds = cfg::predecessors(func_data);
+        match loop_info.loops.iter().find(|lp| lp.preheader(func_data, &preds).is_none()) {
+            Some(lp) => {
+                insert_preheader(func_data, lp, &preds);
+            }
+            None => break (dom, loop_info),
+        }
+    };
+    // Inner loops first, so hoisted code can move further out
+    let preds = cfg::predecessors(func_data);
+    for lp in &loop_info.loops {
+        let preheader = lp.preheader(func_data, &preds).unwrap();
+        hoist_loop(func_data, lp, preheader, &dom, summary);
+    }
+}
+
+/* New block between the outside predecessors and the header */
+pub fn insert_preheader(func_data: &mut FunctionData, lp: &Loop, preds: &HashMap<BasicBlock, Vec<BasicBlock>>) -> BasicBlock {
+    let pre = func_data.dfg_mut().new_bb().basic_block(None);
+    func_data.layout_mut().bbs_mut().cursor_mut(lp.header).insert_key_before(pre).unwrap();
+    for &pred in &preds[&lp.header] {
+        if lp.contains(pred) {
+            continue;
+        }
+        let term = *func_data.layout().bbs().node(&pred).unwrap().insts().back_key().unwrap();
+        opt::retarget(func_data, term, lp.header, pre);
+    }
+    let jump = func_data.dfg_mut().new_value().jump(lp.header);
+    func_data.layout_mut().bb_mut(pre).insts_mut().push_key_back(jump).unwrap();
+    pre
+}
+
+fn hoist_loop(func_data: &mut FunctionData, lp: &Loop, preheader: BasicBlock, dom: &DomTree, summary: &Summary) {
+    let writes = Writes::new(func_data, lp);
+    // Block params of the loop change every iteration, like its instructions
+    let mut in_loop: HashSet<Value> = HashSet::new();
+    for &bb in &lp.blocks {
+        in_loop.extend(func_data.dfg().bb(bb).params().iter().copied());
+        in_loop.extend(func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied());
+    }
+    let is_invariant = |v: Value, in_loop: &HashSet<Value>| !in_loop.contains(&v);
+
+    // A block that dominates every block leaving the loop runs in every iteration
+    // that ends the loop, so what it computes may trap in the preheader as well
+    let exiting: Vec<BasicBlock> = lp.blocks.iter().copied().filter(|&bb| cfg::successors(func_data, bb).iter().any(|succ| !lp.contains(*succ))).collect();
+    let always_runs = |bb: BasicBlock| !exiting.is_empty() && exiting.iter().all(|&e| dom.dominates(bb, e));
+
+    let order: Vec<BasicBlock> = dom.order.iter().copied().filter(|bb| lp.contains(*bb)).collect();
+    let mut hoisted = vec![];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bb in &order {
+            let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+            for inst in insts {
+                if !in_loop.contains(&inst) {
+                    continue;
+                }
+                let ok = match func_data.dfg().value(inst).kind() {
+                    ValueKind::Binary(bin) => {
+                        let traps = matches!(bin.op(), BinaryOp::Div | BinaryOp::Mod);
+                        is_invariant(bin.lhs(), &in_loop) && is_invariant(bin.rhs(), &in_loop) && (!traps || always_runs(bb))
+                    }
+                    ValueKind::GetElemPtr(gep) => is_invariant(gep.src(), &in_loop) && is_invariant(gep.index(), &in_loop),
+                    ValueKind::GetPtr(gp) => is_invariant(gp.src(), &in_loop) && is_invariant(gp.index(), &in_loop),
+                    ValueKind::Load(load) => {
+                        let src = load.src();
+                        // Scalar slots and globals can always be read, array elements
+                        // only from the header, which runs whenever the loop is entered
+                        let direct = src.is_global() || matches!(func_data.dfg().value(src).kind(), ValueKind::Alloc(_));
+                        is_invariant(src, &in_loop) && (direct || bb == lp.header) && !writes.may_write(func_data, summary, src)
+                    }
+                    _ => false,
+                };
+                if ok {
+                    in_loop.remove(&inst);
+                    hoisted.push(inst);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    // Move to the end of the preheader, keeping their order
+    let term = *func_data.layout().bbs().node(&preheader).unwrap().insts().back_key().unwrap();
+    for inst in hoisted {
+        let bb = func_data.layout().parent_bb(inst).unwrap();
+        func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+        func_data.layout_mut().bb_mut(preheader).insts_mut().cursor_mut(term).insert_key_before(inst).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    /* Run licm on the only function, then name the block each named instruction ended up in */
+    fn placement(text: &str) -> HashMap<String, String> {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program);
+        assert_eq!(verify::verify(&program), Ok(()));
+        let func = *program.func_layout().iter().find(|&&func| !callgraph::is_decl(&program, func)).unwrap();
+        let func_data = program.func(func);
+        let mut found = HashMap::new();
+        for (&bb, node) in func_data.layout().bbs() {
+            let block = func_data.dfg().bb(bb).name().clone().unwrap_or("preheader".to_string());
+            for &inst in node.insts().keys() {
+                if let Some(name) = func_data.dfg().value(inst).name() {
+                    found.insert(name.clone(), block.clone());
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn hoists_invariant_values_only() {
+        let found = placement(r#"
+            global @g = alloc i32, 0
+            global @h = alloc i32, 0
+
+            fun @f(@n: i32, @k: i32): i32 {
+            %entry:
+              jump %loop(0)
+            %loop(%i: i32):
+              %inv = mul @k, 3
+              %var = add %i, %inv
+              %quot = div @n, @k
+              %seen = load @h
+              %old = load @g
+              %c = lt %var, %quot
+              br %c, %body, %end
+            %body:
+              %rem = mod @n, @k
+              %i1 = add %i, %rem
+              store %i1, @g
+              jump %loop(%i1)
+            %end:
+              ret %i
+            }
+        "#);
+        // Invariant arithmetic, and a division in the block every exit goes through
+        assert_eq!(found["%inv"], "%entry");
+        assert_eq!(found["%quot"], "%entry");
+        assert_eq!(found["%seen"], "%entry");
+        // Uses a block param of the loop
+        assert_eq!(found["%var"], "%loop");
+        assert_eq!(found["%c"], "%loop");
+        // May trap, and only runs when the loop goes on
+        assert_eq!(found["%rem"], "%body");
+        // @g is written in the loop
+        assert_eq!(found["%old"], "%loop");
+    }
+
+    #[test]
+    fn nested_loop_invariants_move_out_of_both() {
+        let found = placement(r#"
+            fun @f(@n: i32, @k: i32): i32 {
+            %entry:
+              %c0 = lt 0, @n
+              br %c0, %outer, %end
+            %outer:
+              %i = add @n, 0
+              jump %inner(0)
+            %inner(%j: i32):
+              %inv = mul @k, @k
+              %j1 = add %j, %inv
+              %c1 = lt %j1, @n
+              br %c1, %inner(%j1), %outer_latch
+            %outer_latch:
+              %c2 = lt %i, @k
+              br %c2, %outer, %end
+            %end:
+              ret 0
+            }
+        "#);
+        // The outer loop has no preheader of its own: one is inserted
+        assert_eq!(found["%inv"], "preheader");
+        assert_eq!(found["%i"], "preheader");
+        assert_eq!(found["%j1"], "%inner");
+        assert_eq!(found["%c1"], "%inner");
+    }
+}
@@ -0,0 +1,175 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use crate::opt::cfg;
+use crate::opt::dom::DomTree;
+
+/* Natural loop, identified by its header */
+pub struct Loop {
+    pub header: BasicBlock,
+    pub blocks: HashSet<BasicBlock>,
+    // Blocks in the loop that jump back to the header
+    pub latches: Vec<BasicBlock>,
+    // Blocks outside the loop that are targets of loop blocks
+    pub exits: Vec<BasicBlock>,
+    // Index of the enclosing loop
+    pub parent: Option<usize>,
+    pub depth: usize,
+}
+
+impl Loop {
+    pub fn contains(&self, bb: BasicBlock) -> bool {
+        self.blocks.contains(&bb)
+    }
+
+    /* Single predecessor outside the loop that only jumps to the header */
+    pub fn preheader(&self, func_data: &FunctionData, preds: &HashMap<BasicBlock, Vec<BasicBlock>>) -> Option<BasicBlock> {
+        let outside: Vec<BasicBlock> = preds[&self.header].iter().copied().filter(|p| !self.contains(*p)).collect();
+        match outside.as_slice() {
+            [pre] if cfg::successors(func_data, *pre).len() == 1 => Some(*pre),
+            _ => None,
+        }
+    }
+}
+
+/* All natural loops of a function, with nesting */
+pub struct LoopInfo {
+    // Inner loops come before the loops containing them
+    pub loops: Vec<Loop>,
+    depth: HashMap<BasicBlock, usize>,
+}
+
+impl LoopInfo {
+    pub fn new(func_data: &FunctionData, dom: &DomTree) -> LoopInfo {
+        let preds = cfg::predecessors(func_data);
+
+        // Back edges latch -> header, where header dominates latch
+        let mut latches: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        let mut headers = vec![];
+        for &bb in &dom.order {
+            for succ in cfg::successors(func_data, bb) {
+                if dom.dominates(succ, bb) {
+                    if !latches.contains_key(&succ) {
+                        headers.push(succ);
+                    }
+                    latches.entry(succ).or_default().push(bb);
+                }
+            }
+        }
+
+        let mut loops = vec![];
+        for header in headers {
+            // Walk backwards from the latches until the header
+            let mut blocks = HashSet::new();
+            blocks.insert(header);
+            let mut stack = latches[&header].clone();
+            while let Some(bb) = stack.pop() {
+                if blocks.insert(bb) {
+                    stack.extend(preds[&bb].iter().copied());
+                }
+            }
+            let mut exits = vec![];
+            for &bb in &dom.order {
+                if !blocks.contains(&bb) {
+                    continue;
+                }
+                for succ in cfg::successors(func_data, bb) {
+                    if !blocks.contains(&succ) && !exits.contains(&succ) {
+                        exits.push(succ);
+                    }
+                }
+            }
+            loops.push(Loop { header, blocks, latches: latches[&header].clone(), exits, parent: None, depth: 1 });
+        }
+
+        // Inner loops first: smaller loops can't contain larger ones
+        loops.sort_by_key(|l| l.blocks.len());
+        for i in 0..loops.len() {
+            let parent = (i + 1..loops.len()).find(|&j| loops[j].contains(loops[i].header));
+            loops[i].parent = parent;
+        }
+        for i in (0..loops.len()).rev() {
+            if let Some(p) = loops[i].parent {
+                loops[i].depth = loops[p].depth + 1;
+            }
+        }
+
+        let mut depth = HashMap::new();
+        for l in &loops {
+            for &bb in &l.blocks {
+                let d = depth.entry(bb).or_insert(0);
+                *d = (*d).max(l.depth);
+            }
+        }
+        LoopInfo { loops, depth }
+    }
+
+    /* Loop nesting depth of a block, 0 outside of loops */
+    pub fn depth(&self, bb: BasicBlock) -> usize {
+        self.depth.get(&bb).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+
+    #[test]
+    fn nested_loops_with_two_latches() {
+        let program = Driver::from(r#"
+            fun @f(@n: i32): i32 {
+            %entry:
+              jump %outer(0)
+            %outer(%i: i32):
+              %c = lt %i, @n
+              br %c, %inner_pre, %end
+            %inner_pre:
+              jump %inner(0)
+            %inner(%j: i32):
+              %d = lt %j, %i
+              br %d, %inner_body, %outer_latch
+            %inner_body:
+              %e = eq %j, 3
+              br %e, %skip, %step
+            %skip:
+              %j2 = add %j, 2
+              jump %inner(%j2)
+            %step:
+              %j1 = add %j, 1
+              jump %inner(%j1)
+            %outer_latch:
+              %i1 = add %i, 1
+              jump %outer(%i1)
+            %end:
+              ret %i
+            }
+        "#).generate_program().unwrap();
+        let func_data = program.func(program.func_layout()[0]);
+        let bb = |name: &str| *func_data.layout().bbs().keys().find(|&&bb| func_data.dfg().bb(bb).name().as_deref() == Some(name)).unwrap();
+        let set = |names: &[&str]| names.iter().map(|&name| bb(name)).collect::<HashSet<BasicBlock>>();
+        let info = LoopInfo::new(func_data, &DomTree::new(func_data));
+
+        assert_eq!(info.loops.len(), 2);
+        let inner = &info.loops[0];
+        assert_eq!(inner.header, bb("%inner"));
+        assert_eq!(inner.blocks, set(&["%inner", "%inner_body", "%skip", "%step"]));
+        assert_eq!(inner.latches.iter().copied().collect::<HashSet<_>>(), set(&["%skip", "%step"]));
+        assert_eq!(inner.exits, vec![bb("%outer_latch")]);
+        assert_eq!((inner.parent, inner.depth), (Some(1), 2));
+
+        let outer = &info.loops[1];
+        assert_eq!(outer.header, bb("%outer"));
+        assert_eq!(outer.blocks, set(&["%outer", "%inner_pre", "%inner", "%inner_body", "%skip", "%step", "%outer_latch"]));
+        assert_eq!(outer.latches, vec![bb("%outer_latch")]);
+        assert_eq!(outer.exits, vec![bb("%end")]);
+        assert_eq!((outer.parent, outer.depth), (None, 1));
+
+        assert_eq!(info.depth(bb("%entry")), 0);
+        assert_eq!(info.depth(bb("%inner_pre")), 1);
+        assert_eq!(info.depth(bb("%skip")), 2);
+        let preds = cfg::predecessors(func_data);
+        assert_eq!(inner.preheader(func_data, &preds), Some(bb("%inner_pre")));
+        assert_eq!(outer.preheader(func_data, &preds), Some(bb("%entry")));
+    }
+}
@@ -0,0 +1,193 @@
+/* Uses */
+use koopa::ir::*;
+use koopa::ir::builder::*;
+
+/* Module (Extern) */
+pub mod alias;
+pub mod callgraph;
+pub mod cfg;
+pub mod dce;
+pub mod dom;
+pub mod effects;
+pub mod gdce;
+pub mod gvn;
+pub mod inline;
+pub mod licm;
+pub mod loops;
+pub mod sccp;
+pub mod tailrec;
+pub mod unroll;
+pub mod verify;
+
+/* Optimization level */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+}
+
+/* Optimization settings */
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub level: OptLevel,
+    // Copies of the body per iteration of a partially unrolled loop
+    pub unroll_factor: usize,
+    // Instructions an unrolled loop may grow to
+    pub unroll_budget: usize,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { level: OptLevel::O2, unroll_factor: 4, unroll_budget: 400 }
+    }
+}
+
+/* Passes in the order `optimize` may run them, each name once */
+pub const PASSES: [&str; 8] = ["tailrec", "inline", "sccp", "gvn", "dce", "licm", "unroll", "gdce"];
+
+/* Run optimization passes on koopa ir */
+pub fn optimize(program: &mut Program, options: &Options) {
+    optimize_with(program, options, &mut |_, _| {});
+}
+
+/* Run optimization passes, calling `after` with the name of each pass once it finished */
+pub fn optimize_with(program: &mut Program, options: &Options, after: &mut dyn FnMut(&str, &Program)) {
+    crate::trace!(Opt, Info, "optimizing at {:?}", options.level);
+    let mut pass = |name: &str, program: &mut Program, run: &dyn Fn(&mut Program)| {
+        let before = if crate::trace::enabled(crate::trace::Category::Opt, crate::trace::Level::Debug) { inst_count(program) } else { 0 };
+        run(program);
+        crate::trace!(Opt, Debug, "{}: {} -> {} instructions", name, before, inst_count(program));
+        if cfg!(debug_assertions) {
+            verify::assert_valid(program, name);
+        }
+        after(name, program);
+    };
+    if options.level >= OptLevel::O2 {
+        pass("tailrec", program, &tailrec::run);
+        pass("inline", program, &inline::run);
+    }
+    if options.level >= OptLevel::O1 {
+        pass("sccp", program, &sccp::run);
+        pass("gvn", program, &gvn::run);
+        pass("dce", program, &dce::run);
+    }
+    if options.level >= OptLevel::O2 {
+        pass("licm", program, &licm::run);
+        pass("gvn", program, &gvn::run);
+    }
+    if options.level >= OptLevel::O3 {
+        pass("unroll", program, &|program| unroll::run(program, options));
+        pass("gvn", program, &gvn::run);
+        pass("sccp", program, &sccp::run);
+    }
+    if options.level >= OptLevel::O1 {
+        pass("dce", program, &dce::run);
+        pass("gdce", program, &gdce::run);
+    }
+}
+
+/* Instructions in every function body */
+fn inst_count(program: &Program) -> usize {
+    program.funcs().values().map(|func_data| func_data.layout().bbs().iter().map(|(_, node)| node.insts().len()).sum::<usize>()).sum()
+}
+
+/* Replace operand `old` with `new` in a value kind */
+fn replace_operand(kind: &mut ValueKind, old: Value, new: Value) {
+    let swap = |v: &mut Value| {
+        if *v == old {
+            *v = new;
+        }
+    };
+    match kind {
+        ValueKind::Load(load) => swap(load.src_mut()),
+        ValueKind::Store(store) => {
+            swap(store.value_mut());
+            swap(store.dest_mut());
+        }
+        ValueKind::GetPtr(gp) => {
+            swap(gp.src_mut());
+            swap(gp.index_mut());
+        }
+        ValueKind::GetElemPtr(gep) => {
+            swap(gep.src_mut());
+            swap(gep.index_mut());
+        }
+        ValueKind::Binary(bin) => {
+            swap(bin.lhs_mut());
+            swap(bin.rhs_mut());
+        }
+        ValueKind::Branch(br) => {
+            swap(br.cond_mut());
+            br.true_args_mut().iter_mut().for_each(swap);
+            br.false_args_mut().iter_mut().for_each(swap);
+        }
+        ValueKind::Jump(jump) => jump.args_mut().iter_mut().for_each(swap),
+        ValueKind::Call(call) => call.args_mut().iter_mut().for_each(swap),
+        ValueKind::Return(ret) => {
+            if let Some(v) = ret.value_mut() {
+                swap(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+/* Replace every use of a local value `old` with `new` */
+pub fn replace_uses(func_data: &mut FunctionData, old: Value, new: Value) {
+    let users: Vec<Value> = func_data.dfg().value(old).used_by().iter().copied().collect();
+    for user in users {
+        let mut data = func_data.dfg().value(user).clone();
+        replace_operand(data.kind_mut(), old, new);
+        func_data.dfg_mut().replace_value_with(user).raw(data);
+    }
+}
+
+/* Remove an unused instruction from its block and the dfg */
+pub fn remove_inst(func_data: &mut FunctionData, inst: Value) {
+    if let Some(bb) = func_data.layout().parent_bb(inst) {
+        func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    }
+    func_data.dfg_mut().remove_value(inst);
+}
+
+/* Point the edges of a terminator that go to `old` at `new` */
+pub fn retarget(func_data: &mut FunctionData, term: Value, old: BasicBlock, new: BasicBlock) {
+    let mut data = func_data.dfg().value(term).clone();
+    match data.kind_mut() {
+        ValueKind::Branch(br) => {
+            if br.true_bb() == old {
+                *br.true_bb_mut() = new;
+            }
+            if br.false_bb() == old {
+                *br.false_bb_mut() = new;
+            }
+        }
+        ValueKind::Jump(jump) if jump.target() == old => *jump.target_mut() = new,
+        _ => {}
+    }
+    func_data.dfg_mut().replace_value_with(term).raw(data);
+}
+
+/* Remove pure instructions whose results are never used */
+pub fn remove_dead_values(func_data: &mut FunctionData) {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut dead = vec![];
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let data = func_data.dfg().value(inst);
+                let pure = matches!(data.kind(), ValueKind::Binary(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_) | ValueKind::Load(_));
+                if pure && data.used_by().is_empty() {
+                    dead.push(inst);
+                }
+            }
+        }
+        for inst in dead {
+            remove_inst(func_data, inst);
+            changed = true;
+        }
+    }
+}
@@ -0,0 +1,466 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use koopa::ir::builder::*;
+use crate::opt::{self, alias, callgraph, cfg};
+
+/* Sparse conditional constant propagation (Wegman & Zadeck)
+ * Values and CFG edges are only considered once they are proven executable.
+ * Globals that are never written are read as constants, branches on
+ * constants become jumps and blocks that can't execute are removed.
+ */
+pub fn run(program: &mut Program) {
+    let globals = readonly_globals(program);
+    for func in program.func_layout().to_vec() {
+        if callgraph::is_decl(program, func) {
+            continue;
+        }
+        sccp_func(program.func_mut(func), &globals);
+    }
+}
+
+/* Initializer of a global */
+pub enum Init {
+    Int(i32),
+    Zero,
+    Agg(Vec<Init>),
+}
+
+impl Init {
+    pub fn new(program: &Program, value: Value) -> Init {
+        match program.borrow_value(value).kind() {
+            ValueKind::GlobalAlloc(alloc) => Init::new(program, alloc.init()),
+            ValueKind::Integer(int) => Init::Int(int.value()),
+            ValueKind::Aggregate(agg) => Init::Agg(agg.elems().iter().map(|&v| Init::new(program, v)).collect()),
+            _ => Init::Zero,
+        }
+    }
+
+    /* Scalar at the given indices */
+    pub fn get(&self, indices: &[i32]) -> Option<i32> {
+        match (self, indices.split_first()) {
+            (Init::Int(v), None) => Some(*v),
+            (Init::Zero, _) => Some(0),
+            (Init::Agg(elems), Some((&i, rest))) => elems.get(usize::try_from(i).ok()?)?.get(rest),
+            _ => None,
+        }
+    }
+}
+
+/* Globals that are never stored to and whose address never escapes */
+pub fn readonly_globals(program: &Program) -> HashMap<Value, Init> {
+    let mut written = HashSet::new();
+    for &func in program.func_layout() {
+        let func_data = program.func(func);
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let mut escaped = vec![];
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Store(store) => {
+                        escaped.push(store.dest());
+                        escaped.push(store.value());
+                    }
+                    ValueKind::Call(call) => escaped.extend(call.args().iter().copied()),
+                    ValueKind::GetPtr(gp) => escaped.push(gp.src()),
+                    _ => {}
+                }
+                for ptr in escaped {
+                    if let Some(root) = alias::pointer_root(func_data, ptr) {
+                        written.insert(root);
+                    }
+                }
+            }
+        }
+    }
+    let mut globals = HashMap::new();
+    for &global in program.inst_layout() {
+        if !written.contains(&global) {
+            globals.insert(global, Init::new(program, global));
+        }
+    }
+    globals
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Lat {
+    Top,
+    Const(i32),
+    Bottom,
+}
+
+fn meet(a: Lat, b: Lat) -> Lat {
+    match (a, b) {
+        (Lat::Top, x) | (x, Lat::Top) => x,
+        (Lat::Const(x), Lat::Const(y)) if x == y => a,
+        _ => Lat::Bottom,
+    }
+}
+
+/* Fold a binary operation, None if it has no defined result */
+pub fn fold_binary(op: BinaryOp, l: i32, r: i32) -> Option<i32> {
+    let v = match op {
+        BinaryOp::NotEq => (l != r) as i32,
+        BinaryOp::Eq => (l == r) as i32,
+        BinaryOp::Gt => (l > r) as i32,
+        BinaryOp::Lt => (l < r) as i32,
+        BinaryOp::Ge => (l >= r) as i32,
+        BinaryOp::Le => (l <= r) as i32,
+        BinaryOp::Add => l.wrapping_add(r),
+        BinaryOp::Sub => l.wrapping_sub(r),
+        BinaryOp::Mul => l.wrapping_mul(r),
+        BinaryOp::Div => l.checked_div(r).or(if r == -1 { Some(l.wrapping_neg()) } else { None })?,
+        BinaryOp::Mod => l.checked_rem(r).or(if r == -1 { Some(0) } else { None })?,
+        BinaryOp::And => l & r,
+        BinaryOp::Or => l | r,
+        BinaryOp::Xor => l ^ r,
+        BinaryOp::Shl => l.wrapping_shl(r as u32),
+        BinaryOp::Shr => ((l as u32).wrapping_shr(r as u32)) as i32,
+        BinaryOp::Sar => l.wrapping_shr(r as u32),
+    };
+    Some(v)
+}
+
+struct Sccp<'a> {
+    func_data: &'a FunctionData,
+    globals: &'a HashMap<Value, Init>,
+    preds: HashMap<BasicBlock, Vec<BasicBlock>>,
+    lat: HashMap<Value, Lat>,
+    exec_bbs: HashSet<BasicBlock>,
+    exec_edges: HashSet<(BasicBlock, BasicBlock)>,
+    flow: Vec<(BasicBlock, BasicBlock)>,
+    ssa: Vec<Value>,
+}
+
+impl Sccp<'_> {
+    fn get(&self, value: Value) -> Lat {
+        if value.is_global() {
+            return Lat::Bottom;
+        }
+        if let Some(&lat) = self.lat.get(&value) {
+            return lat;
+        }
+        match self.func_data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Lat::Const(int.value()),
+            ValueKind::BlockArgRef(_) => Lat::Top,
+            _ if self.func_data.layout().parent_bb(value).is_some() => Lat::Top,
+            _ => Lat::Bottom,
+        }
+    }
+
+    fn set(&mut self, value: Value, lat: Lat) {
+        if self.get(value) != lat {
+            self.lat.insert(value, lat);
+            self.ssa.push(value);
+        }
+    }
+
+    fn solve(&mut self) {
+        let entry = self.func_data.layout().entry_bb().unwrap();
+        self.exec_bbs.insert(entry);
+        self.visit_block(entry);
+        while !self.flow.is_empty() || !self.ssa.is_empty() {
+            while let Some((from, to)) = self.flow.pop() {
+                if !self.exec_edges.insert((from, to)) {
+                    continue;
+                }
+                if self.exec_bbs.insert(to) {
+                    self.visit_block(to);
+                }else {
+                    self.eval_params(to);
+                }
+            }
+            while let Some(value) = self.ssa.pop() {
+                let users: Vec<Value> = self.func_data.dfg().value(value).used_by().iter().copied().collect();
+                for user in users {
+                    let executable = self.func_data.layout().parent_bb(user).is_some_and(|bb| self.exec_bbs.contains(&bb));
+                    if executable {
+                        self.eval_inst(user);
+                    }
+                }
+            }
+        }
+    }
+
+    fn visit_block(&mut self, bb: BasicBlock) {
+        self.eval_params(bb);
+        let insts: Vec<Value> = self.func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            self.eval_inst(inst);
+        }
+    }
+
+    /* Block parameters meet the arguments of all executable incoming edges */
+    fn eval_params(&mut self, bb: BasicBlock) {
+        let params = self.func_data.dfg().bb(bb).params().to_vec();
+        if params.is_empty() {
+            return;
+        }
+        let mut lats = vec![Lat::Top; params.len()];
+        for &pred in self.preds.get(&bb).map_or(&[][..], |p| p.as_slice()) {
+            if !self.exec_edges.contains(&(pred, bb)) {
+                continue;
+            }
+            let term = *self.func_data.layout().bbs().node(&pred).unwrap().insts().back_key().unwrap();
+            let mut arg_lists = vec![];
+            match self.func_data.dfg().value(term).kind() {
+                ValueKind::Branch(br) => {
+                    if br.true_bb() == bb {
+                        arg_lists.push(br.true_args().to_vec());
+                    }
+                    if br.false_bb() == bb {
+                        arg_lists.push(br.false_args().to_vec());
+                    }
+                }
+                ValueKind::Jump(jump) => arg_lists.push(jump.args().to_vec()),
+                _ => {}
+            }
+            for args in arg_lists {
+                for (i, &arg) in args.iter().enumerate() {
+                    lats[i] = meet(lats[i], self.get(arg));
+                }
+            }
+        }
+        for (param, lat) in params.into_iter().zip(lats) {
+            self.set(param, lat);
+        }
+    }
+
+    /* Constant loaded through a pointer into a read-only global */
+    fn eval_load(&self, mut ptr: Value) -> Lat {
+        let mut indices = vec![];
+        while !ptr.is_global() {
+            match self.func_data.dfg().value(ptr).kind() {
+                ValueKind::GetElemPtr(gep) => {
+                    match self.get(gep.index()) {
+                        Lat::Const(i) => indices.push(i),
+                        lat => return lat,
+                    }
+                    ptr = gep.src();
+                }
+                _ => return Lat::Bottom,
+            }
+        }
+        indices.reverse();
+        match self.globals.get(&ptr).and_then(|init| init.get(&indices)) {
+            Some(v) => Lat::Const(v),
+            None => Lat::Bottom,
+        }
+    }
+
+    fn eval_inst(&mut self, inst: Value) {
+        let bb = self.func_data.layout().parent_bb(inst).unwrap();
+        let lat = match self.func_data.dfg().value(inst).kind() {
+            ValueKind::Binary(bin) => match (self.get(bin.lhs()), self.get(bin.rhs())) {
+                (Lat::Bottom, _) | (_, Lat::Bottom) => Lat::Bottom,
+                (Lat::Const(l), Lat::Const(r)) => fold_binary(bin.op(), l, r).map_or(Lat::Bottom, Lat::Const),
+                _ => Lat::Top,
+            },
+            ValueKind::Load(load) => self.eval_load(load.src()),
+            ValueKind::Branch(br) => {
+                match self.get(br.cond()) {
+                    Lat::Top => {}
+                    Lat::Const(c) => self.flow.push((bb, if c != 0 { br.true_bb() } else { br.false_bb() })),
+                    Lat::Bottom => {
+                        self.flow.push((bb, br.true_bb()));
+                        self.flow.push((bb, br.false_bb()));
+                    }
+                }
+                self.refresh_targets(bb);
+                return;
+            }
+            ValueKind::Jump(jump) => {
+                self.flow.push((bb, jump.target()));
+                self.refresh_targets(bb);
+                return;
+            }
+            _ => Lat::Bottom,
+        };
+        if !self.func_data.dfg().value(inst).ty().is_unit() {
+            self.set(inst, lat);
+        }
+    }
+
+    /* Arguments of an executable edge may have changed */
+    fn refresh_targets(&mut self, bb: BasicBlock) {
+        for succ in cfg::successors(self.func_data, bb) {
+            if self.exec_edges.contains(&(bb, succ)) {
+                self.eval_params(succ);
+            }
+        }
+    }
+}
+
+fn sccp_func(func_data: &mut FunctionData, globals: &HashMap<Value, Init>) {
+    let mut sccp = Sccp {
+        func_data,
+        globals,
+        preds: cfg::predecessors(func_data),
+        lat: HashMap::new(),
+        exec_bbs: HashSet::new(),
+        exec_edges: HashSet::new(),
+        flow: vec![],
+        ssa: vec![],
+    };
+    sccp.solve();
+
+    // Constant values and branches in executable blocks
+    let mut consts = vec![];
+    let mut branches = vec![];
+    for &bb in &sccp.exec_bbs {
+        for &param in func_data.dfg().bb(bb).params() {
+            if let Lat::Const(c) = sccp.get(param) {
+                consts.push((param, c, false));
+            }
+        }
+        for &inst in func_data.layout().bbs().node(&bb).unwrap().insts().keys() {
+            match func_data.dfg().value(inst).kind() {
+                ValueKind::Binary(_) | ValueKind::Load(_) => {
+                    if let Lat::Const(c) = sccp.get(inst) {
+                        consts.push((inst, c, true));
+                    }
+                }
+                ValueKind::Branch(br) => {
+                    if let Lat::Const(c) = sccp.get(br.cond()) {
+                        branches.push((inst, c));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for (value, c, is_inst) in consts {
+        let int = func_data.dfg_mut().new_value().integer(c);
+        opt::replace_uses(func_data, value, int);
+        if is_inst {
+            opt::remove_inst(func_data, value);
+        }
+    }
+    // Constant branches become jumps
+    for (inst, c) in branches {
+        let (target, args) = match func_data.dfg().value(inst).kind() {
+            ValueKind::Branch(br) if c != 0 => (br.true_bb(), br.true_args().to_vec()),
+            ValueKind::Branch(br) => (br.false_bb(), br.false_args().to_vec()),
+            _ => unreachable!(),
+        };
+        func_data.dfg_mut().replace_value_with(inst).jump_with_args(target, args);
+    }
+    cfg::remove_unreachable(func_data);
+    opt::remove_dead_values(func_data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    /* Koopa text of the function named `name` after sccp */
+    fn sccp(text: &str, name: &str) -> String {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program);
+        assert_eq!(verify::verify(&program), Ok(()));
+        let text = crate::dump::gen_text_koopa(&program);
+        let start = text.find(&("fun ".to_string() + name + "(")).unwrap();
+        text[start..].split("\n}\n").next().unwrap().to_string() + "\n}\n"
+    }
+
+    #[test]
+    fn constant_branch_becomes_a_jump() {
+        assert_eq!(sccp(r#"
+            fun @f(): i32 {
+            %entry:
+              %c = lt 1, 2
+              br %c, %then, %else
+            %then:
+              jump %end(10)
+            %else:
+              jump %end(20)
+            %end(%r: i32):
+              %s = add %r, 1
+              ret %s
+            }
+        "#, "@f"), r#"fun @f(): i32 {
+%entry:
+  jump %then
+
+%then:
+  jump %end(10)
+
+%end(%r: i32):
+  ret 11
+}
+"#);
+    }
+
+    #[test]
+    fn merged_values_stay_overdefined() {
+        assert_eq!(sccp(r#"
+            fun @f(@x: i32): i32 {
+            %entry:
+              br @x, %then, %else
+            %then:
+              jump %end(1)
+            %else:
+              jump %end(2)
+            %end(%r: i32):
+              %s = mul %r, 2
+              ret %s
+            }
+        "#, "@f"), r#"fun @f(@x: i32): i32 {
+%entry:
+  br @x, %then, %else
+
+%then:
+  jump %end(1)
+
+%else:
+  jump %end(2)
+
+%end(%r: i32):
+  %s = mul %r, 2
+  ret %s
+}
+"#);
+    }
+
+    #[test]
+    fn globals_written_through_a_call_are_not_constant() {
+        let text = r#"
+            global @kept = alloc [i32, 1], zeroinit
+            global @passed = alloc [i32, 1], zeroinit
+
+            fun @set(@p: *i32) {
+            %entry:
+              store 7, @p
+              ret
+            }
+
+            fun @f(): i32 {
+            %entry:
+              %p = getelemptr @passed, 0
+              call @set(%p)
+              %a = getelemptr @kept, 0
+              %b = load %a
+              %c = getelemptr @passed, 0
+              %d = load %c
+              %s = add %b, %d
+              ret %s
+            }
+        "#;
+        let program = Driver::from(text).generate_program().unwrap();
+        let globals = readonly_globals(&program);
+        let names: Vec<String> = globals.keys().map(|&g| program.borrow_value(g).name().clone().unwrap()).collect();
+        assert_eq!(names, vec!["@kept"]);
+        assert_eq!(sccp(text, "@f"), r#"fun @f(): i32 {
+%entry:
+  %p = getelemptr @passed, 0
+  call @set(%p)
+  %c = getelemptr @passed, 0
+  %d = load %c
+  %s = add 0, %d
+  ret %s
+}
+"#);
+    }
+}
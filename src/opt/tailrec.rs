@@ -0,0 +1,391 @@
+/* Uses */
+use std::collections::HashSet;
+use koopa::ir::*;
+use koopa::ir::builder::*;
+use crate::opt::{self, callgraph, cfg};
+
+/* Turn self tail calls into jumps back to the function entry
+ *   return f(args)      => params = args; jump %header
+ *   return x + f(args)  => acc = acc + x; params = args; jump %header
+ * With an accumulator, the remaining `ret v` become `ret acc + v`.
+ */
+pub fn run(program: &mut Program) {
+    for func in program.func_layout().to_vec() {
+        if callgraph::is_decl(program, func) {
+            continue;
+        }
+        eliminate(program.func_mut(func), func);
+    }
+}
+
+struct TailCall {
+    call: Value,
+    // Accumulator operation and its other operand
+    accum: Option<(Value, BinaryOp, Value)>,
+    term: Value,
+}
+
+fn is_only_user(func_data: &FunctionData, value: Value, user: Value) -> bool {
+    let used_by = func_data.dfg().value(value).used_by();
+    used_by.len() == 1 && used_by.contains(&user)
+}
+
+/* Match `call; ret`, `call; jump %bb_with_only_ret` and `call; binary; ret` */
+fn match_tail_call(func_data: &FunctionData, insts: &[Value], i: usize) -> Option<TailCall> {
+    let call = insts[i];
+    let next = *insts.get(i + 1)?;
+    match func_data.dfg().value(next).kind() {
+        ValueKind::Return(ret) => {
+            let ok = match ret.value() {
+                Some(v) => v == call && is_only_user(func_data, call, next),
+                None => true,
+            };
+            if ok {
+                return Some(TailCall { call, accum: None, term: next });
+            }
+        }
+        ValueKind::Jump(jump) => {
+            let target = func_data.layout().bbs().node(&jump.target()).unwrap().insts();
+            let only_ret = target.len() == 1 && matches!(func_data.dfg().value(*target.front_key().unwrap()).kind(), ValueKind::Return(ret) if ret.value().is_none());
+            if only_ret && jump.args().is_empty() {
+                return Some(TailCall { call, accum: None, term: next });
+            }
+        }
+        ValueKind::Binary(bin) if matches!(bin.op(), BinaryOp::Add | BinaryOp::Mul) => {
+            let other = if bin.lhs() == call {
+                bin.rhs()
+            }else if bin.rhs() == call {
+                bin.lhs()
+            }else {
+                return None;
+            };
+            let term = *insts.get(i + 2)?;
+            let ret_ok = matches!(func_data.dfg().value(term).kind(), ValueKind::Return(ret) if ret.value() == Some(next));
+            if other != call && ret_ok && is_only_user(func_data, call, next) && is_only_user(func_data, next, term) {
+                return Some(TailCall { call, accum: Some((next, bin.op(), other)), term });
+            }
+        }
+        _ => {}
+    }
+    None
+}
+
+/* Alloc a pointer was derived from, if it points into a local of this function */
+fn local_root(func_data: &FunctionData, mut ptr: Value) -> Option<Value> {
+    loop {
+        // Globals are not in the function's dfg
+        match func_data.dfg().values().get(&ptr)?.kind() {
+            ValueKind::Alloc(_) => return Some(ptr),
+            ValueKind::GetElemPtr(gep) => ptr = gep.src(),
+            ValueKind::GetPtr(gp) => ptr = gp.src(),
+            _ => return None,
+        }
+    }
+}
+
+/* Whether the address of a local reaches a call or is stored somewhere.
+ * The loop reuses one set of allocs for every iteration, so a callee that
+ * was handed a local of an earlier iteration would see it overwritten.
+ */
+fn local_address_escapes(func_data: &FunctionData) -> bool {
+    for (_, node) in func_data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            let escaping = match func_data.dfg().value(inst).kind() {
+                ValueKind::Call(call) => call.args().to_vec(),
+                ValueKind::Store(store) => vec![store.value()],
+                _ => continue,
+            };
+            if escaping.into_iter().any(|value| local_root(func_data, value).is_some()) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn find_tail_calls(func_data: &FunctionData, func: Function) -> Vec<TailCall> {
+    let mut list = vec![];
+    if local_address_escapes(func_data) {
+        return list;
+    }
+    for bb in cfg::reverse_post_order(func_data) {
+        let insts: Vec<Value> = func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for i in 0..insts.len() {
+            if let ValueKind::Call(call) = func_data.dfg().value(insts[i]).kind() {
+                if call.callee() != func {
+                    continue;
+                }
+                if let Some(tail) = match_tail_call(func_data, &insts, i) {
+                    list.push(tail);
+                }
+            }
+        }
+    }
+    // Only one accumulator operation per function
+    let op = list.iter().find_map(|t| t.accum.map(|(_, op, _)| op));
+    list.retain(|t| t.accum.is_none_or(|(_, o, _)| Some(o) == op));
+    list
+}
+
+fn push_inst(func_data: &mut FunctionData, bb: BasicBlock, inst: Value) {
+    func_data.layout_mut().bb_mut(bb).insts_mut().push_key_back(inst).unwrap();
+}
+
+fn eliminate(func_data: &mut FunctionData, func: Function) {
+    let tails = find_tail_calls(func_data, func);
+    if tails.is_empty() {
+        return;
+    }
+    let op = tails.iter().find_map(|t| t.accum.map(|(_, op, _)| op));
+
+    // New entry in front of the old one, which becomes the loop header
+    let header = func_data.layout().entry_bb().unwrap();
+    let entry = func_data.dfg_mut().new_bb().basic_block(Some("%tail_entry".to_string()));
+    func_data.layout_mut().bbs_mut().push_key_front(entry).unwrap();
+
+    // Allocs stay outside the loop
+    let header_insts: Vec<Value> = func_data.layout().bbs().node(&header).unwrap().insts().keys().copied().collect();
+    let mut moved = HashSet::new();
+    for &inst in &header_insts {
+        if let ValueKind::Alloc(_) = func_data.dfg().value(inst).kind() {
+            func_data.layout_mut().bb_mut(header).insts_mut().remove(&inst);
+            push_inst(func_data, entry, inst);
+            moved.insert(inst);
+        }
+    }
+
+    // One slot per parameter; reuse `store %arg, %alloc` from dump if possible
+    let mut slots = vec![];
+    let mut header_loads = vec![];
+    for param in func_data.params().to_vec() {
+        let users: Vec<Value> = func_data.dfg().value(param).used_by().iter().copied().collect();
+        let reuse = match users.as_slice() {
+            [user] => match func_data.dfg().value(*user).kind() {
+                ValueKind::Store(store) if store.value() == param && moved.contains(&store.dest()) => Some((*user, store.dest())),
+                _ => None,
+            },
+            _ => None,
+        };
+        match reuse {
+            Some((store, slot)) => {
+                func_data.layout_mut().bb_mut(header).insts_mut().remove(&store);
+                push_inst(func_data, entry, store);
+                slots.push(slot);
+            }
+            None => {
+                let ty = func_data.dfg().value(param).ty().clone();
+                let slot = func_data.dfg_mut().new_value().alloc(ty);
+                push_inst(func_data, entry, slot);
+                let store = func_data.dfg_mut().new_value().store(param, slot);
+                push_inst(func_data, entry, store);
+                let load = func_data.dfg_mut().new_value().load(slot);
+                header_loads.push(load);
+                for user in users {
+                    let mut data = func_data.dfg().value(user).clone();
+                    opt::replace_operand(data.kind_mut(), param, load);
+                    func_data.dfg_mut().replace_value_with(user).raw(data);
+                }
+                slots.push(slot);
+            }
+        }
+    }
+    for load in header_loads.into_iter().rev() {
+        func_data.layout_mut().bb_mut(header).insts_mut().push_key_front(load).unwrap();
+    }
+
+    // Accumulator starts at the identity of the operation
+    let acc = op.map(|op| {
+        let acc = func_data.dfg_mut().new_value().alloc(Type::get_i32());
+        push_inst(func_data, entry, acc);
+        let identity = func_data.dfg_mut().new_value().integer(if op == BinaryOp::Mul { 1 } else { 0 });
+        let store = func_data.dfg_mut().new_value().store(identity, acc);
+        push_inst(func_data, entry, store);
+        acc
+    });
+    let jump = func_data.dfg_mut().new_value().jump(header);
+    push_inst(func_data, entry, jump);
+
+    // Other returns fold the accumulator into their value
+    if let (Some(op), Some(acc)) = (op, acc) {
+        let tail_terms: HashSet<Value> = tails.iter().map(|t| t.term).collect();
+        let mut rets = vec![];
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Return(ret) = func_data.dfg().value(inst).kind() {
+                    if let Some(value) = ret.value() {
+                        if !tail_terms.contains(&inst) {
+                            rets.push((inst, value));
+                        }
+                    }
+                }
+            }
+        }
+        for (ret, value) in rets {
+            let bb = func_data.layout().parent_bb(ret).unwrap();
+            let load = func_data.dfg_mut().new_value().load(acc);
+            let result = func_data.dfg_mut().new_value().binary(op, load, value);
+            let mut cursor = func_data.layout_mut().bb_mut(bb).insts_mut().cursor_mut(ret);
+            cursor.insert_key_before(load).unwrap();
+            cursor.insert_key_before(result).unwrap();
+            func_data.dfg_mut().replace_value_with(ret).ret(Some(result));
+        }
+    }
+
+    // Tail calls become parameter updates and a jump back
+    for tail in tails {
+        let bb = func_data.layout().parent_bb(tail.call).unwrap();
+        let args = match func_data.dfg().value(tail.call).kind() {
+            ValueKind::Call(call) => call.args().to_vec(),
+            _ => unreachable!(),
+        };
+        opt::remove_inst(func_data, tail.term);
+        if let Some((binary, op, other)) = tail.accum {
+            opt::remove_inst(func_data, binary);
+            let acc = acc.unwrap();
+            let load = func_data.dfg_mut().new_value().load(acc);
+            push_inst(func_data, bb, load);
+            let value = func_data.dfg_mut().new_value().binary(op, load, other);
+            push_inst(func_data, bb, value);
+            let store = func_data.dfg_mut().new_value().store(value, acc);
+            push_inst(func_data, bb, store);
+        }
+        opt::remove_inst(func_data, tail.call);
+        for (arg, &slot) in args.into_iter().zip(slots.iter()) {
+            let store = func_data.dfg_mut().new_value().store(arg, slot);
+            push_inst(func_data, bb, store);
+        }
+        let jump = func_data.dfg_mut().new_value().jump(header);
+        push_inst(func_data, bb, jump);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    /* Run the pass and return, per function, the calls it still makes to itself */
+    fn self_calls(text: &str) -> (Program, Vec<(String, usize)>) {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program);
+        assert_eq!(verify::verify(&program), Ok(()));
+        let mut counts = vec![];
+        for &func in program.func_layout() {
+            let func_data = program.func(func);
+            let mut count = 0;
+            for (_, node) in func_data.layout().bbs() {
+                for &inst in node.insts().keys() {
+                    if matches!(func_data.dfg().value(inst).kind(), ValueKind::Call(call) if call.callee() == func) {
+                        count += 1;
+                    }
+                }
+            }
+            counts.push((func_data.name().to_string(), count));
+        }
+        (program, counts)
+    }
+
+    #[test]
+    fn accumulates_into_other_returns() {
+        let (program, counts) = self_calls(r#"
+            fun @sum(@n: i32): i32 {
+            %entry:
+              %c = eq @n, 0
+              br %c, %base, %rec
+            %base:
+              ret 0
+            %rec:
+              %m = sub @n, 1
+              %r = call @sum(%m)
+              %s = add @n, %r
+              ret %s
+            }
+        "#);
+        assert_eq!(counts, vec![("@sum".to_string(), 0)]);
+        let func_data = program.func(program.func_layout()[0]);
+        let entry = func_data.layout().entry_bb().unwrap();
+        assert_eq!(func_data.dfg().bb(entry).name().as_deref(), Some("%tail_entry"));
+        // The base case returns acc + 0
+        let mut rets = vec![];
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Return(ret) = func_data.dfg().value(inst).kind() {
+                    rets.push(ret.value().unwrap());
+                }
+            }
+        }
+        assert_eq!(rets.len(), 1);
+        match func_data.dfg().value(rets[0]).kind() {
+            ValueKind::Binary(bin) => {
+                assert_eq!(bin.op(), BinaryOp::Add);
+                assert!(matches!(func_data.dfg().value(bin.lhs()).kind(), ValueKind::Load(_)));
+                assert!(matches!(func_data.dfg().value(bin.rhs()).kind(), ValueKind::Integer(i) if i.value() == 0));
+            }
+            kind => panic!("base case returns {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn void_recursion_through_a_return_block() {
+        let (_, counts) = self_calls(r#"
+            fun @count(@p: *i32, @n: i32) {
+            %entry:
+              %c = eq @n, 0
+              br %c, %end, %rec
+            %rec:
+              %v = load @p
+              %w = add %v, 1
+              store %w, @p
+              %m = sub @n, 1
+              call @count(@p, %m)
+              jump %end
+            %end:
+              ret
+            }
+        "#);
+        assert_eq!(counts, vec![("@count".to_string(), 0)]);
+    }
+
+    #[test]
+    fn keeps_calls_that_see_a_local_array() {
+        // f(a, n - 1) reads the a of the previous call, which a loop would overwrite
+        let (_, counts) = self_calls(r#"
+            decl @putarray(i32, *i32)
+
+            fun @f(@p: *i32, @n: i32): i32 {
+            %entry:
+              %a = alloc [i32, 1]
+              %0 = getelemptr %a, 0
+              store @n, %0
+              %c = eq @n, 0
+              br %c, %base, %rec
+            %base:
+              %1 = getptr @p, 0
+              %2 = load %1
+              ret %2
+            %rec:
+              %3 = getelemptr %a, 0
+              %4 = sub @n, 1
+              %5 = call @f(%3, %4)
+              ret %5
+            }
+
+            fun @g(@n: i32): i32 {
+            %entry:
+              %a = alloc [i32, 1]
+              %0 = getelemptr %a, 0
+              call @putarray(1, %0)
+              %c = eq @n, 0
+              br %c, %base, %rec
+            %base:
+              ret 0
+            %rec:
+              %1 = sub @n, 1
+              %2 = call @g(%1)
+              ret %2
+            }
+        "#);
+        assert_eq!(counts, vec![("@putarray".to_string(), 0), ("@f".to_string(), 1), ("@g".to_string(), 1)]);
+    }
+}
@@ -0,0 +1,552 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use koopa::ir::*;
+use koopa::ir::builder::*;
+use crate::opt::{self, alias, callgraph, cfg, sccp, Options};
+use crate::opt::dom::DomTree;
+use crate::opt::licm;
+use crate::opt::loops::{Loop, LoopInfo};
+
+/* Unroll counted innermost loops
+ *   constant trip count within budget => fully unrolled
+ *   otherwise => main loop running `factor` iterations per check,
+ *                followed by the original loop for the remainder
+ */
+pub fn run(program: &mut Program, options: &Options) {
+    for func in program.func_layout().to_vec() {
+        if callgraph::is_decl(program, func) {
+            continue;
+        }
+        unroll_func(program.func_mut(func), options);
+    }
+}
+
+/* Loop `while (iv op bound) { ...; iv = iv + step; }` */
+struct CountedLoop {
+    header: BasicBlock,
+    latch: BasicBlock,
+    preheader: BasicBlock,
+    // Header successors inside and outside the loop
+    body: BasicBlock,
+    exit: BasicBlock,
+    // Loop blocks, header first
+    blocks: Vec<BasicBlock>,
+    // Scalar alloc of the induction variable
+    iv: Value,
+    // Comparison with the induction variable on the left
+    op: BinaryOp,
+    bound: Value,
+    step: i32,
+    size: usize,
+}
+
+fn block_insts(func_data: &FunctionData, bb: BasicBlock) -> Vec<Value> {
+    func_data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect()
+}
+
+fn terminator(func_data: &FunctionData, bb: BasicBlock) -> Value {
+    *func_data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap()
+}
+
+fn swap_cmp(op: BinaryOp) -> Option<BinaryOp> {
+    match op {
+        BinaryOp::Lt => Some(BinaryOp::Gt),
+        BinaryOp::Gt => Some(BinaryOp::Lt),
+        BinaryOp::Le => Some(BinaryOp::Ge),
+        BinaryOp::Ge => Some(BinaryOp::Le),
+        BinaryOp::Eq | BinaryOp::NotEq => Some(op),
+        _ => None,
+    }
+}
+
+/* Induction variable slot read by `value`, if it is a load of a scalar alloc */
+fn loaded_slot(func_data: &FunctionData, value: Value) -> Option<Value> {
+    if value.is_global() {
+        return None;
+    }
+    match func_data.dfg().value(value).kind() {
+        ValueKind::Load(load) if alias::is_scalar_alloc(func_data, load.src()) => Some(load.src()),
+        _ => None,
+    }
+}
+
+fn get_integer(func_data: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match func_data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
+fn counted_loop(func_data: &FunctionData, lp: &Loop, dom: &DomTree, preds: &HashMap<BasicBlock, Vec<BasicBlock>>) -> Option<CountedLoop> {
+    // Single latch jumping back, single exit from the header
+    let latch = match lp.latches.as_slice() {
+        [latch] => *latch,
+        _ => return None,
+    };
+    match func_data.dfg().value(terminator(func_data, latch)).kind() {
+        ValueKind::Jump(jump) if jump.args().is_empty() => {}
+        _ => return None,
+    }
+    let (cond, body, exit) = match func_data.dfg().value(terminator(func_data, lp.header)).kind() {
+        ValueKind::Branch(br) if lp.contains(br.true_bb()) && !lp.contains(br.false_bb()) && br.true_args().is_empty() && br.false_args().is_empty() => (br.cond(), br.true_bb(), br.false_bb()),
+        _ => return None,
+    };
+    if lp.exits.len() != 1 {
+        return None;
+    }
+    for &bb in &lp.blocks {
+        if !func_data.dfg().bb(bb).params().is_empty() {
+            return None;
+        }
+        if bb != lp.header && cfg::successors(func_data, bb).iter().any(|s| !lp.contains(*s)) {
+            return None;
+        }
+    }
+    // Header only computes the condition
+    let header_insts = block_insts(func_data, lp.header);
+    for &inst in &header_insts[..header_insts.len() - 1] {
+        let kind = func_data.dfg().value(inst).kind();
+        if !matches!(kind, ValueKind::Load(_) | ValueKind::Binary(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_)) {
+            return None;
+        }
+    }
+    let preheader = lp.preheader(func_data, preds)?;
+
+    // Condition `load %iv op bound`
+    let in_loop: HashSet<Value> = lp.blocks.iter().flat_map(|&bb| block_insts(func_data, bb)).collect();
+    let (op, lhs, rhs) = match func_data.dfg().value(cond).kind() {
+        ValueKind::Binary(bin) if in_loop.contains(&cond) => (bin.op(), bin.lhs(), bin.rhs()),
+        _ => return None,
+    };
+    let (iv, op, bound) = match (loaded_slot(func_data, lhs), loaded_slot(func_data, rhs)) {
+        (Some(iv), _) if in_loop.contains(&lhs) && !in_loop.contains(&rhs) => (iv, op, rhs),
+        (_, Some(iv)) if in_loop.contains(&rhs) && !in_loop.contains(&lhs) => (iv, swap_cmp(op)?, lhs),
+        _ => return None,
+    };
+    swap_cmp(op)?;
+
+    // Exactly one store to iv, in the latch: `store add(load %iv, step), %iv`
+    let mut stores = vec![];
+    for &inst in &in_loop {
+        if let ValueKind::Store(store) = func_data.dfg().value(inst).kind() {
+            if store.dest() == iv {
+                stores.push(inst);
+            }
+        }
+    }
+    let store = match stores.as_slice() {
+        [store] if func_data.layout().parent_bb(*store) == Some(latch) => *store,
+        _ => return None,
+    };
+    let value = match func_data.dfg().value(store).kind() {
+        ValueKind::Store(s) => s.value(),
+        _ => unreachable!(),
+    };
+    let (base, step) = match func_data.dfg().value(value).kind() {
+        ValueKind::Binary(bin) if in_loop.contains(&value) => match (bin.op(), get_integer(func_data, bin.lhs()), get_integer(func_data, bin.rhs())) {
+            (BinaryOp::Add, _, Some(c)) => (bin.lhs(), c),
+            (BinaryOp::Add, Some(c), _) => (bin.rhs(), c),
+            (BinaryOp::Sub, _, Some(c)) if c != i32::MIN => (bin.lhs(), -c),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    if step == 0 || loaded_slot(func_data, base) != Some(iv) || !in_loop.contains(&base) {
+        return None;
+    }
+    // The load must see the value of this iteration
+    if func_data.layout().parent_bb(base) == Some(latch) {
+        let insts = block_insts(func_data, latch);
+        let pos = |v: Value| insts.iter().position(|&i| i == v);
+        if pos(base) > pos(store) {
+            return None;
+        }
+    }
+
+    let blocks: Vec<BasicBlock> = dom.order.iter().copied().filter(|bb| lp.contains(*bb)).collect();
+    let size = blocks.iter().map(|&bb| block_insts(func_data, bb).len()).sum();
+    Some(CountedLoop { header: lp.header, latch, preheader, body, exit, blocks, iv, op, bound, step, size })
+}
+
+/* Constant stored to iv before the loop, following single predecessors */
+fn initial_value(func_data: &FunctionData, cl: &CountedLoop, preds: &HashMap<BasicBlock, Vec<BasicBlock>>) -> Option<i32> {
+    let mut bb = cl.preheader;
+    let mut visited = HashSet::new();
+    while visited.insert(bb) {
+        for inst in block_insts(func_data, bb).into_iter().rev() {
+            if let ValueKind::Store(store) = func_data.dfg().value(inst).kind() {
+                if store.dest() == cl.iv {
+                    return get_integer(func_data, store.value());
+                }
+            }
+        }
+        bb = match preds.get(&bb).map(|p| p.as_slice()) {
+            Some([pred]) => *pred,
+            _ => return None,
+        };
+    }
+    None
+}
+
+/* Number of iterations, None if unknown or above limit */
+fn trip_count(func_data: &FunctionData, cl: &CountedLoop, preds: &HashMap<BasicBlock, Vec<BasicBlock>>, limit: usize) -> Option<usize> {
+    let bound = get_integer(func_data, cl.bound)?;
+    let mut iv = initial_value(func_data, cl, preds)?;
+    let mut count = 0;
+    while sccp::fold_binary(cl.op, iv, bound)? != 0 {
+        count += 1;
+        if count > limit {
+            return None;
+        }
+        iv = iv.wrapping_add(cl.step);
+    }
+    Some(count)
+}
+
+/* Copy the loop blocks in front of `before`
+ * Edges leaving the loop keep their targets. Returns the map to the copied blocks.
+ */
+fn clone_loop(func_data: &mut FunctionData, cl: &CountedLoop, before: BasicBlock) -> HashMap<BasicBlock, BasicBlock> {
+    let mut bb_map = HashMap::new();
+    for &bb in &cl.blocks {
+        let new_bb = func_data.dfg_mut().new_bb().basic_block(None);
+        func_data.layout_mut().bbs_mut().cursor_mut(before).insert_key_before(new_bb).unwrap();
+        bb_map.insert(bb, new_bb);
+    }
+    let mut value_map: HashMap<Value, Value> = HashMap::new();
+    for &bb in &cl.blocks {
+        for inst in block_insts(func_data, bb) {
+            let mut data = func_data.dfg().value(inst).clone();
+            let uses: Vec<Value> = data.kind().value_uses().collect();
+            for used in uses {
+                if let Some(&new) = value_map.get(&used) {
+                    opt::replace_operand(data.kind_mut(), used, new);
+                }
+            }
+            match data.kind_mut() {
+                ValueKind::Branch(br) => {
+                    if let Some(&t) = bb_map.get(&br.true_bb()) {
+                        *br.true_bb_mut() = t;
+                    }
+                    if let Some(&f) = bb_map.get(&br.false_bb()) {
+                        *br.false_bb_mut() = f;
+                    }
+                }
+                ValueKind::Jump(jump) => {
+                    if let Some(&t) = bb_map.get(&jump.target()) {
+                        *jump.target_mut() = t;
+                    }
+                }
+                _ => {}
+            }
+            let new = func_data.dfg_mut().new_value().raw(data);
+            func_data.layout_mut().bb_mut(bb_map[&bb]).insts_mut().push_key_back(new).unwrap();
+            value_map.insert(inst, new);
+        }
+    }
+    bb_map
+}
+
+fn set_jump(func_data: &mut FunctionData, bb: BasicBlock, target: BasicBlock) {
+    let term = terminator(func_data, bb);
+    func_data.dfg_mut().replace_value_with(term).jump(target);
+}
+
+/* Run the body `trips` times straight, the original header then exits */
+fn full_unroll(func_data: &mut FunctionData, cl: &CountedLoop, trips: usize) {
+    let mut entry = cl.header;
+    let mut copies = vec![];
+    for _ in 0..trips {
+        copies.push(clone_loop(func_data, cl, cl.header));
+    }
+    // Chain the copies: header -> body, latch -> next header
+    for (i, copy) in copies.iter().enumerate() {
+        set_jump(func_data, copy[&cl.header], copy[&cl.body]);
+        let next = copies.get(i + 1).map_or(cl.header, |c| c[&cl.header]);
+        set_jump(func_data, copy[&cl.latch], next);
+        if i == 0 {
+            entry = copy[&cl.header];
+        }
+    }
+    let term = terminator(func_data, cl.preheader);
+    opt::retarget(func_data, term, cl.header, entry);
+    set_jump(func_data, cl.header, cl.exit);
+}
+
+/* Main loop doing `factor` iterations per check, the original loop runs the rest
+ *   preheader: lim = bound - (factor - 1) * step; ok = lim did not wrap
+ *   main:      br ok & (iv op lim), copy_0, %header
+ */
+fn partial_unroll(func_data: &mut FunctionData, cl: &CountedLoop, factor: usize) -> bool {
+    let ascending = match cl.op {
+        BinaryOp::Lt | BinaryOp::Le => true,
+        BinaryOp::Gt | BinaryOp::Ge => false,
+        _ => return false,
+    };
+    if ascending != (cl.step > 0) {
+        return false;
+    }
+    let span = match i32::try_from((factor as i64 - 1) * cl.step as i64) {
+        Ok(span) => span,
+        Err(_) => return false,
+    };
+
+    // Limit computed once before the loop
+    let pre_term = terminator(func_data, cl.preheader);
+    let span = func_data.dfg_mut().new_value().integer(span);
+    let lim = func_data.dfg_mut().new_value().binary(BinaryOp::Sub, cl.bound, span);
+    let no_wrap = if ascending { BinaryOp::Lt } else { BinaryOp::Gt };
+    let ok = func_data.dfg_mut().new_value().binary(no_wrap, lim, cl.bound);
+    let mut cursor = func_data.layout_mut().bb_mut(cl.preheader).insts_mut().cursor_mut(pre_term);
+    cursor.insert_key_before(lim).unwrap();
+    cursor.insert_key_before(ok).unwrap();
+
+    // Main loop header
+    let main = func_data.dfg_mut().new_bb().basic_block(None);
+    func_data.layout_mut().bbs_mut().cursor_mut(cl.header).insert_key_before(main).unwrap();
+    let copies: Vec<HashMap<BasicBlock, BasicBlock>> = (0..factor).map(|_| clone_loop(func_data, cl, cl.header)).collect();
+    let load = func_data.dfg_mut().new_value().load(cl.iv);
+    let cmp = func_data.dfg_mut().new_value().binary(cl.op, load, lim);
+    let cond = func_data.dfg_mut().new_value().binary(BinaryOp::And, ok, cmp);
+    let br = func_data.dfg_mut().new_value().branch(cond, copies[0][&cl.header], cl.header);
+    for inst in [load, cmp, cond, br] {
+        func_data.layout_mut().bb_mut(main).insts_mut().push_key_back(inst).unwrap();
+    }
+    for (i, copy) in copies.iter().enumerate() {
+        set_jump(func_data, copy[&cl.header], copy[&cl.body]);
+        let next = copies.get(i + 1).map_or(main, |c| c[&cl.header]);
+        set_jump(func_data, copy[&cl.latch], next);
+    }
+    opt::retarget(func_data, pre_term, cl.header, main);
+    true
+}
+
+/* Allocs in the loop move to the entry, so copies share their slots */
+fn hoist_allocs(func_data: &mut FunctionData, cl: &CountedLoop) {
+    let entry = func_data.layout().entry_bb().unwrap();
+    for &bb in &cl.blocks {
+        for inst in block_insts(func_data, bb) {
+            if let ValueKind::Alloc(_) = func_data.dfg().value(inst).kind() {
+                func_data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                func_data.layout_mut().bb_mut(entry).insts_mut().push_key_front(inst).unwrap();
+            }
+        }
+    }
+}
+
+fn unroll_func(func_data: &mut FunctionData, options: &Options) {
+    // Innermost loops only, remembered by header since blocks change
+    let headers: Vec<BasicBlock> = {
+        let dom = DomTree::new(func_data);
+        let loop_info = LoopInfo::new(func_data, &dom);
+        let parents: HashSet<usize> = loop_info.loops.iter().filter_map(|l| l.parent).collect();
+        loop_info.loops.iter().enumerate().filter(|(i, _)| !parents.contains(i)).map(|(_, l)| l.header).collect()
+    };
+    let mut changed = false;
+    for header in headers {
+        let dom = DomTree::new(func_data);
+        let loop_info = LoopInfo::new(func_data, &dom);
+        let lp = match loop_info.loops.iter().find(|l| l.header == header) {
+            Some(lp) => lp,
+            None => continue,
+        };
+        let mut preds = cfg::predecessors(func_data);
+        if lp.preheader(func_data, &preds).is_none() {
+            licm::insert_preheader(func_data, lp, &preds);
+            preds = cfg::predecessors(func_data);
+        }
+        let cl = match counted_loop(func_data, lp, &dom, &preds) {
+            Some(cl) => cl,
+            None => continue,
+        };
+        let limit = options.unroll_budget / cl.size.max(1);
+        if let Some(trips) = trip_count(func_data, &cl, &preds, limit) {
+            hoist_allocs(func_data, &cl);
+            full_unroll(func_data, &cl, trips);
+            changed = true;
+        }else if options.unroll_factor > 1 && cl.size * options.unroll_factor <= options.unroll_budget {
+            hoist_allocs(func_data, &cl);
+            changed |= partial_unroll(func_data, &cl, options.unroll_factor);
+        }
+    }
+    if changed {
+        cfg::remove_unreachable(func_data);
+        opt::remove_dead_values(func_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use crate::opt::verify;
+
+    /* Sum of the induction variable over `while (i op bound) { s = s + 1; i = i + step; }` */
+    fn counting_loop(init: &str, op: &str, bound: &str, step: i32) -> String {
+        r#"
+            fun @f(@start: i32, @n: i32): i32 {
+            %entry:
+              %i = alloc i32
+              %s = alloc i32
+              store INIT, %i
+              store 0, %s
+              jump %cond
+            %cond:
+              %0 = load %i
+              %1 = OP %0, BOUND
+              br %1, %body, %end
+            %body:
+              %2 = load %s
+              %3 = add %2, 1
+              store %3, %s
+              %4 = load %i
+              %5 = add %4, STEP
+              store %5, %i
+              jump %cond
+            %end:
+              %6 = load %s
+              ret %6
+            }
+        "#.replace("INIT", init).replace("OP", op).replace("BOUND", bound).replace("STEP", &step.to_string())
+    }
+
+    fn unroll(text: &str, factor: usize) -> Program {
+        let mut program = Driver::from(text).generate_program().unwrap();
+        run(&mut program, &Options { unroll_factor: factor, ..Default::default() });
+        assert_eq!(verify::verify(&program), Ok(()));
+        program
+    }
+
+    fn loop_count(program: &Program) -> usize {
+        let func_data = program.func(program.func_layout()[0]);
+        LoopInfo::new(func_data, &DomTree::new(func_data)).loops.len()
+    }
+
+    /* Interpret a function of scalar allocs, returning its result and how many branches ran */
+    fn exec(program: &Program, args: &[i32]) -> (i32, usize) {
+        let func_data = program.func(program.func_layout()[0]);
+        let mut values: HashMap<Value, i32> = func_data.params().iter().copied().zip(args.iter().copied()).collect();
+        let mut memory: HashMap<Value, i32> = HashMap::new();
+        let get = |values: &HashMap<Value, i32>, v: Value| get_integer(func_data, v).unwrap_or_else(|| values[&v]);
+        let mut bb = func_data.layout().entry_bb().unwrap();
+        let mut branches = 0;
+        for _ in 0..100000 {
+            for inst in block_insts(func_data, bb) {
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Alloc(_) => {}
+                    ValueKind::Load(load) => {
+                        values.insert(inst, memory[&load.src()]);
+                    }
+                    ValueKind::Store(store) => {
+                        memory.insert(store.dest(), get(&values, store.value()));
+                    }
+                    ValueKind::Binary(bin) => {
+                        let result = sccp::fold_binary(bin.op(), get(&values, bin.lhs()), get(&values, bin.rhs())).unwrap();
+                        values.insert(inst, result);
+                    }
+                    ValueKind::Branch(br) => {
+                        branches += 1;
+                        bb = if get(&values, br.cond()) != 0 { br.true_bb() } else { br.false_bb() };
+                    }
+                    ValueKind::Jump(jump) => bb = jump.target(),
+                    ValueKind::Return(ret) => return (get(&values, ret.value().unwrap()), branches),
+                    kind => panic!("cannot run {:?}", kind),
+                }
+            }
+        }
+        panic!("did not return");
+    }
+
+    #[test]
+    fn constant_trip_count_unrolls_fully() {
+        let text = counting_loop("0", "lt", "10", 3);
+        let program = unroll(&text, 4);
+        assert_eq!(loop_count(&program), 0);
+        assert_eq!(exec(&program, &[0, 0]), (4, 0));
+    }
+
+    #[test]
+    fn partial_unroll_runs_the_remainder() {
+        let text = counting_loop("0", "lt", "@n", 1);
+        let before = Driver::from(text.as_str()).generate_program().unwrap();
+        let program = unroll(&text, 4);
+        // Main loop plus the original one for what is left
+        assert_eq!(loop_count(&program), 2);
+        for n in [-3, 0, 1, 3, 4, 5, 10, 11] {
+            assert_eq!(exec(&program, &[0, n]).0, exec(&before, &[0, n]).0, "n = {}", n);
+        }
+        // One check per four iterations, plus the remainder's
+        assert_eq!(exec(&before, &[0, 10]).1, 11);
+        assert_eq!(exec(&program, &[0, 10]).1, 3 + 3);
+    }
+
+    #[test]
+    fn bounds_near_the_ends_of_i32() {
+        // lim = bound - 3 stops the main loop before iv could pass i32::MAX,
+        // and near i32::MIN, lim wraps and `ok` skips the main loop
+        for (op, step, start, n) in [("lt", 1, i32::MAX - 5, i32::MAX), ("lt", 1, i32::MAX - 9, i32::MAX - 1), ("lt", 1, i32::MIN, i32::MIN + 2), ("gt", -1, i32::MIN + 5, i32::MIN), ("gt", -1, i32::MAX, i32::MAX - 2)] {
+            let text = counting_loop("@start", op, "@n", step);
+            let before = Driver::from(text.as_str()).generate_program().unwrap();
+            let program = unroll(&text, 4);
+            assert_eq!(loop_count(&program), 2);
+            let expected = exec(&before, &[start, n]).0;
+            assert_eq!(expected, (n as i64 - start as i64).abs() as i32);
+            assert_eq!(exec(&program, &[start, n]).0, expected, "{} {} {}", op, start, n);
+        }
+    }
+
+    #[test]
+    fn rejects_two_latches_and_two_stores_to_the_iv() {
+        let two_latches = r#"
+            fun @f(@start: i32, @n: i32): i32 {
+            %entry:
+              %i = alloc i32
+              store 0, %i
+              jump %cond
+            %cond:
+              %0 = load %i
+              %1 = lt %0, 8
+              br %1, %body, %end
+            %body:
+              %2 = load %i
+              %3 = add %2, 1
+              store %3, %i
+              %4 = eq %2, @n
+              br %4, %cond, %latch
+            %latch:
+              jump %cond
+            %end:
+              ret 0
+            }
+        "#;
+        let two_stores = r#"
+            fun @f(@start: i32, @n: i32): i32 {
+            %entry:
+              %i = alloc i32
+              store 0, %i
+              jump %cond
+            %cond:
+              %0 = load %i
+              %1 = lt %0, 8
+              br %1, %body, %end
+            %body:
+              %2 = load %i
+              %3 = add %2, @n
+              store %3, %i
+              %4 = load %i
+              %5 = add %4, 1
+              store %5, %i
+              jump %cond
+            %end:
+              ret 0
+            }
+        "#;
+        for text in [two_latches, two_stores] {
+            let before = crate::dump::gen_text_koopa(&Driver::from(text).generate_program().unwrap());
+            assert_eq!(crate::dump::gen_text_koopa(&unroll(text, 4)), before);
+        }
+    }
+}
@@ -0,0 +1,428 @@
+/* Uses */
+use std::collections::HashMap;
+use std::fmt;
+use koopa::ir::*;
+use crate::opt::dom::DomTree;
+
+/* Problem found in a function of the program */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    pub func: String,
+    // Block and position in it, when the problem is in an instruction
+    pub block: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.block {
+            Some(block) => write!(f, "{} {}: {}", self.func, block, self.message),
+            None => write!(f, "{}: {}", self.func, self.message),
+        }
+    }
+}
+
+/* Check that a Koopa program is well formed
+ * Every block ends in its only terminator, operand types fit their
+ * instruction, local operands dominate their uses, and jumps and calls
+ * pass arguments matching the parameters of their target.
+ */
+pub fn verify(program: &Program) -> Result<(), Vec<VerifyError>> {
+    let mut errors = vec![];
+    for &func in program.func_layout() {
+        let func_data = program.func(func);
+        if func_data.layout().entry_bb().is_some() {
+            verify_func(program, func_data, &mut errors);
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    }else {
+        Err(errors)
+    }
+}
+
+/* Panic with every problem found, for checks in debug builds */
+pub fn assert_valid(program: &Program, stage: &str) {
+    if let Err(errors) = verify(program) {
+        let report: String = errors.iter().map(|e| "\n  ".to_string() + &e.to_string()).collect();
+        panic!("invalid Koopa IR after {}:{}", stage, report);
+    }
+}
+
+struct Checker<'a> {
+    program: &'a Program,
+    func_data: &'a FunctionData,
+    errors: &'a mut Vec<VerifyError>,
+    // Block and instruction currently checked, for messages
+    place: Option<String>,
+}
+
+impl Checker<'_> {
+    fn error(&mut self, message: String) {
+        self.errors.push(VerifyError { func: self.func_data.name().to_string(), block: self.place.clone(), message });
+    }
+
+    /* Type of a local or global value, None for a value that no longer exists */
+    fn ty(&self, value: Value) -> Option<Type> {
+        if let Some(data) = self.func_data.dfg().values().get(&value) {
+            Some(data.ty().clone())
+        }else if self.program.borrow_values().contains_key(&value) {
+            Some(self.program.borrow_value(value).ty().clone())
+        }else {
+            None
+        }
+    }
+
+    /* Type of an operand, reporting missing values */
+    fn operand(&mut self, value: Value, what: &str) -> Option<Type> {
+        let ty = self.ty(value);
+        if ty.is_none() {
+            self.error(what.to_string() + " refers to a removed value");
+        }
+        ty
+    }
+
+    fn expect(&mut self, value: Value, what: &str, expected: &Type) {
+        if let Some(ty) = self.operand(value, what) {
+            if &ty != expected {
+                self.error(what.to_string() + " has type " + &ty.to_string() + ", expected " + &expected.to_string());
+            }
+        }
+    }
+
+    /* Type pointed to by an operand that must be a pointer */
+    fn pointee(&mut self, value: Value, what: &str) -> Option<Type> {
+        let ty = self.operand(value, what)?;
+        match ty.kind() {
+            TypeKind::Pointer(base) => Some(base.clone()),
+            _ => {
+                self.error(what.to_string() + " has type " + &ty.to_string() + ", expected a pointer");
+                None
+            }
+        }
+    }
+
+    /* Arguments passed to a block must match its parameters */
+    fn block_args(&mut self, target: BasicBlock, args: &[Value]) {
+        let params = match self.func_data.dfg().bbs().get(&target) {
+            Some(data) => data.params().to_vec(),
+            None => {
+                self.error("jump to a removed block".to_string());
+                return;
+            }
+        };
+        if self.func_data.layout().bbs().node(&target).is_none() {
+            self.error("jump to ".to_string() + &bb_name(self.func_data, target) + ", which is not in the layout");
+        }
+        // The entry has no predecessors, a loop at the start of a function needs its own header
+        if self.func_data.layout().entry_bb() == Some(target) {
+            self.error("jump to the entry block ".to_string() + &bb_name(self.func_data, target));
+        }
+        if params.len() != args.len() {
+            self.error(bb_name(self.func_data, target) + " takes " + &count(params.len(), "argument") + ", " + &args.len().to_string() + " given");
+            return;
+        }
+        for (i, (&param, &arg)) in params.iter().zip(args).enumerate() {
+            let expected = self.func_data.dfg().value(param).ty().clone();
+            self.expect(arg, &("argument ".to_string() + &i.to_string() + " of the jump"), &expected);
+        }
+    }
+
+    fn inst(&mut self, inst: Value) {
+        let data = self.func_data.dfg().value(inst);
+        let ty = data.ty().clone();
+        match data.kind() {
+            ValueKind::Store(store) => {
+                if let (Some(value), Some(base)) = (self.operand(store.value(), "stored value"), self.pointee(store.dest(), "store destination")) {
+                    if value != base {
+                        self.error("store of ".to_string() + &value.to_string() + " through a pointer to " + &base.to_string());
+                    }
+                }
+            }
+            ValueKind::Load(load) => {
+                if let Some(base) = self.pointee(load.src(), "load source") {
+                    if base != ty {
+                        self.error("load of ".to_string() + &base.to_string() + " produces " + &ty.to_string());
+                    }
+                }
+            }
+            ValueKind::GetElemPtr(gep) => {
+                self.expect(gep.index(), "getelemptr index", &Type::get_i32());
+                if let Some(base) = self.pointee(gep.src(), "getelemptr source") {
+                    match base.kind() {
+                        TypeKind::Array(elem, _) if ty == Type::get_pointer(elem.clone()) => {}
+                        TypeKind::Array(elem, _) => self.error("getelemptr into ".to_string() + &base.to_string() + " produces " + &ty.to_string() + ", expected *" + &elem.to_string()),
+                        _ => self.error("getelemptr source points to ".to_string() + &base.to_string() + ", expected an array"),
+                    }
+                }
+            }
+            ValueKind::GetPtr(gp) => {
+                self.expect(gp.index(), "getptr index", &Type::get_i32());
+                if let Some(src) = self.operand(gp.src(), "getptr source") {
+                    if !matches!(src.kind(), TypeKind::Pointer(_)) {
+                        self.error("getptr source has type ".to_string() + &src.to_string() + ", expected a pointer");
+                    }else if src != ty {
+                        self.error("getptr on ".to_string() + &src.to_string() + " produces " + &ty.to_string());
+                    }
+                }
+            }
+            ValueKind::Binary(bin) => {
+                self.expect(bin.lhs(), "left operand", &Type::get_i32());
+                self.expect(bin.rhs(), "right operand", &Type::get_i32());
+            }
+            ValueKind::Branch(br) => {
+                self.expect(br.cond(), "branch condition", &Type::get_i32());
+                self.block_args(br.true_bb(), br.true_args());
+                self.block_args(br.false_bb(), br.false_args());
+            }
+            ValueKind::Jump(jump) => self.block_args(jump.target(), jump.args()),
+            ValueKind::Call(call) => {
+                let callee = self.program.func(call.callee());
+                let (params, ret) = match callee.ty().kind() {
+                    TypeKind::Function(params, ret) => (params.clone(), ret.clone()),
+                    _ => unreachable!(),
+                };
+                if params.len() != call.args().len() {
+                    self.error(callee.name().to_string() + " takes " + &count(params.len(), "argument") + ", " + &call.args().len().to_string() + " given");
+                }else {
+                    for (i, (param, &arg)) in params.iter().zip(call.args()).enumerate() {
+                        self.expect(arg, &("argument ".to_string() + &i.to_string() + " of " + callee.name()), param);
+                    }
+                }
+                if ret != ty {
+                    self.error("call of ".to_string() + callee.name() + " returning " + &ret.to_string() + " produces " + &ty.to_string());
+                }
+            }
+            ValueKind::Return(ret) => {
+                let expected = match self.func_data.ty().kind() {
+                    TypeKind::Function(_, ret) => ret.clone(),
+                    _ => unreachable!(),
+                };
+                match ret.value() {
+                    Some(value) => self.expect(value, "returned value", &expected),
+                    None if !expected.is_unit() => self.error("ret without a value in a function returning ".to_string() + &expected.to_string()),
+                    None => {}
+                }
+            }
+            ValueKind::Alloc(_) => {
+                if !matches!(ty.kind(), TypeKind::Pointer(_)) {
+                    self.error("alloc produces ".to_string() + &ty.to_string() + ", expected a pointer");
+                }
+            }
+            kind => self.error("unexpected instruction ".to_string() + &format!("{:?}", kind)),
+        }
+    }
+}
+
+fn verify_func(program: &Program, func_data: &FunctionData, errors: &mut Vec<VerifyError>) {
+    let dom = DomTree::new(func_data);
+    // Block and position of every instruction in the layout
+    let mut place: HashMap<Value, (BasicBlock, usize)> = HashMap::new();
+    for (&bb, node) in func_data.layout().bbs() {
+        for (i, &inst) in node.insts().keys().enumerate() {
+            place.insert(inst, (bb, i));
+        }
+    }
+
+    let mut checker = Checker { program, func_data, errors, place: None };
+    for (&bb, node) in func_data.layout().bbs() {
+        let name = bb_name(func_data, bb);
+        let insts: Vec<Value> = node.insts().keys().copied().collect();
+        if insts.is_empty() {
+            checker.place = Some(name);
+            checker.error("empty block, expected a terminator".to_string());
+            continue;
+        }
+        for (i, &inst) in insts.iter().enumerate() {
+            checker.place = Some(name.clone() + " #" + &i.to_string() + " (" + kind_name(func_data.dfg().value(inst).kind()) + ")");
+            let last = i + 1 == insts.len();
+            let term = matches!(func_data.dfg().value(inst).kind(), ValueKind::Branch(_) | ValueKind::Jump(_) | ValueKind::Return(_));
+            if term && !last {
+                checker.error("terminator in the middle of the block".to_string());
+            }else if !term && last {
+                checker.error("block does not end in a terminator".to_string());
+            }
+            checker.inst(inst);
+
+            // Operands computed in this function must be available here
+            for operand in func_data.dfg().value(inst).kind().value_uses() {
+                let data = match func_data.dfg().values().get(&operand) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                if matches!(data.kind(), ValueKind::Integer(_) | ValueKind::ZeroInit(_) | ValueKind::Undef(_) | ValueKind::Aggregate(_) | ValueKind::FuncArgRef(_)) {
+                    continue;
+                }
+                let def = match data.kind() {
+                    ValueKind::BlockArgRef(_) => func_data.dfg().bbs().iter().find(|(_, d)| d.params().contains(&operand)).map(|(&b, _)| (b, 0)),
+                    _ => place.get(&operand).copied(),
+                };
+                match def {
+                    None => checker.error("operand ".to_string() + &value_name(func_data, operand) + " is not in any block"),
+                    Some((def_bb, pos)) if def_bb == bb => {
+                        if !matches!(data.kind(), ValueKind::BlockArgRef(_)) && pos >= i {
+                            checker.error("operand ".to_string() + &value_name(func_data, operand) + " is used before it is defined");
+                        }
+                    }
+                    Some((def_bb, _)) => {
+                        if dom.is_reachable(bb) && !dom.dominates(def_bb, bb) {
+                            checker.error("operand ".to_string() + &value_name(func_data, operand) + " from " + &bb_name(func_data, def_bb) + " does not dominate its use");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/* `n` of `noun`, pluralized */
+fn count(n: usize, noun: &str) -> String {
+    n.to_string() + " " + noun + if n == 1 { "" } else { "s" }
+}
+
+fn bb_name(func_data: &FunctionData, bb: BasicBlock) -> String {
+    match func_data.dfg().bbs().get(&bb).and_then(|data| data.name().clone()) {
+        Some(name) => name,
+        None => format!("{:?}", bb),
+    }
+}
+
+fn value_name(func_data: &FunctionData, value: Value) -> String {
+    match func_data.dfg().value(value).name() {
+        Some(name) => name.clone(),
+        None => kind_name(func_data.dfg().value(value).kind()).to_string() + " " + &format!("{:?}", value),
+    }
+}
+
+fn kind_name(kind: &ValueKind) -> &'static str {
+    match kind {
+        ValueKind::Alloc(_) => "alloc",
+        ValueKind::Load(_) => "load",
+        ValueKind::Store(_) => "store",
+        ValueKind::GetPtr(_) => "getptr",
+        ValueKind::GetElemPtr(_) => "getelemptr",
+        ValueKind::Binary(_) => "binary",
+        ValueKind::Branch(_) => "br",
+        ValueKind::Jump(_) => "jump",
+        ValueKind::Call(_) => "call",
+        ValueKind::Return(_) => "ret",
+        ValueKind::BlockArgRef(_) => "block argument",
+        ValueKind::FuncArgRef(_) => "function argument",
+        _ => "value",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use koopa::front::Driver;
+    use koopa::ir::builder::*;
+
+    fn parse(text: &str) -> Program {
+        Driver::from(text).generate_program().unwrap()
+    }
+
+    fn messages(program: &Program) -> Vec<String> {
+        match verify(program) {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|e| e.message).collect(),
+        }
+    }
+
+    /* Only function of the program, with its blocks in layout order */
+    fn only_func(program: &mut Program) -> (&mut FunctionData, Vec<BasicBlock>) {
+        let func = program.func_layout()[0];
+        let func_data = program.func_mut(func);
+        let bbs = func_data.layout().bbs().keys().copied().collect();
+        (func_data, bbs)
+    }
+
+    fn last_inst(func_data: &FunctionData, bb: BasicBlock) -> Value {
+        *func_data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap()
+    }
+
+    const TWO_BLOCKS: &str = "fun @f(): i32 {\n%entry:\n  jump %end\n%end:\n  ret 0\n}\n";
+
+    #[test]
+    fn accepts_valid_function() {
+        assert_eq!(messages(&parse(TWO_BLOCKS)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn terminator_in_the_middle() {
+        let mut program = parse(TWO_BLOCKS);
+        let (func_data, bbs) = only_func(&mut program);
+        let ret = func_data.dfg_mut().new_value().ret(None);
+        func_data.layout_mut().bb_mut(bbs[0]).insts_mut().push_key_front(ret).unwrap();
+        assert!(messages(&program).contains(&"terminator in the middle of the block".to_string()));
+    }
+
+    #[test]
+    fn block_without_terminator() {
+        let mut program = parse("fun @f(): i32 {\n%entry:\n  %0 = add 1, 2\n  ret %0\n}\n");
+        let (func_data, bbs) = only_func(&mut program);
+        let ret = last_inst(func_data, bbs[0]);
+        func_data.layout_mut().bb_mut(bbs[0]).insts_mut().remove(&ret);
+        assert_eq!(messages(&program), vec!["block does not end in a terminator"]);
+    }
+
+    #[test]
+    fn store_type_mismatch() {
+        let mut program = parse("fun @f(): i32 {\n%entry:\n  %p = alloc i32\n  %q = alloc *i32\n  store 1, %p\n  ret 0\n}\n");
+        let (func_data, bbs) = only_func(&mut program);
+        let insts: Vec<Value> = func_data.layout().bbs().node(&bbs[0]).unwrap().insts().keys().copied().collect();
+        let (p, q, store) = (insts[0], insts[1], insts[2]);
+        let mut data = func_data.dfg().value(store).clone();
+        crate::opt::replace_operand(data.kind_mut(), p, q);
+        func_data.dfg_mut().replace_value_with(store).raw(data);
+        assert_eq!(messages(&program), vec!["store of i32 through a pointer to *i32"]);
+    }
+
+    #[test]
+    fn use_not_dominated_by_definition() {
+        let program = parse("fun @f(@c: i32): i32 {\n%entry:\n  br @c, %a, %b\n%a:\n  %x = add 1, 2\n  jump %b\n%b:\n  ret %x\n}\n");
+        assert_eq!(messages(&program), vec!["operand %x from %a does not dominate its use"]);
+    }
+
+    /* Pass `extra` as one more argument to the terminator or call `inst` */
+    fn add_arg(func_data: &mut FunctionData, inst: Value, extra: i32) {
+        let extra = func_data.dfg_mut().new_value().integer(extra);
+        let mut data = func_data.dfg().value(inst).clone();
+        match data.kind_mut() {
+            ValueKind::Jump(jump) => jump.args_mut().push(extra),
+            ValueKind::Call(call) => call.args_mut().push(extra),
+            _ => unreachable!(),
+        }
+        func_data.dfg_mut().replace_value_with(inst).raw(data);
+    }
+
+    #[test]
+    fn block_argument_count() {
+        let mut program = parse("fun @f(): i32 {\n%entry:\n  jump %b(1)\n%b(%x: i32):\n  ret %x\n}\n");
+        let (func_data, bbs) = only_func(&mut program);
+        let jump = last_inst(func_data, bbs[0]);
+        add_arg(func_data, jump, 2);
+        assert_eq!(messages(&program), vec!["%b takes 1 argument, 2 given"]);
+    }
+
+    #[test]
+    fn call_arity() {
+        let mut program = parse("decl @g(i32): i32\nfun @f(): i32 {\n%entry:\n  %0 = call @g(1)\n  ret %0\n}\n");
+        let func = program.func_layout()[1];
+        let func_data = program.func_mut(func);
+        let entry = func_data.layout().entry_bb().unwrap();
+        let call = *func_data.layout().bbs().node(&entry).unwrap().insts().front_key().unwrap();
+        add_arg(func_data, call, 2);
+        assert_eq!(messages(&program), vec!["@g takes 1 argument, 2 given"]);
+    }
+
+    #[test]
+    fn jump_to_entry() {
+        let mut program = parse(TWO_BLOCKS);
+        let (func_data, bbs) = only_func(&mut program);
+        let jump = last_inst(func_data, bbs[0]);
+        func_data.dfg_mut().replace_value_with(jump).jump(bbs[0]);
+        assert_eq!(messages(&program), vec!["jump to the entry block %entry"]);
+    }
+}
@@ -0,0 +1,288 @@
+/* Uses */
+use koopa::ir::BinaryOp;
+
+/* Strength reduction for `*`, `/` and `%` by a constant
+ * Operand is expected in t0, result is written to t2 and t1 may be clobbered.
+ * None means the plain `mul`/`div`/`rem` should be used.
+ */
+pub fn reduce_binary(op: BinaryOp, c: i32) -> Option<String> {
+    match op {
+        BinaryOp::Mul => reduce_mul(c),
+        BinaryOp::Div => reduce_div(c),
+        BinaryOp::Mod => reduce_mod(c),
+        _ => None,
+    }
+}
+
+fn inst_rr(op: &str, rd: &str, rs: &str) -> String {
+    op.to_string() + " " + rd + ", " + rs + "\n"
+}
+
+fn inst_rrr(op: &str, rd: &str, rs1: &str, rs2: &str) -> String {
+    op.to_string() + " " + rd + ", " + rs1 + ", " + rs2 + "\n"
+}
+
+fn inst_rri(op: &str, rd: &str, rs: &str, imm: i64) -> String {
+    op.to_string() + " " + rd + ", " + rs + ", " + &imm.to_string() + "\n"
+}
+
+/* Shift t0 left by k into reg (t0 itself for k = 0) */
+fn shifted(reg: &'static str, k: u32, text: &mut String) -> &'static str {
+    if k == 0 {
+        return "t0";
+    }
+    *text += &inst_rri("slli", reg, "t0", k as i64);
+    reg
+}
+
+/* Multiplication: shift/add chain for 2^a, 2^a + 2^b and 2^a - 2^b */
+fn reduce_mul(c: i32) -> Option<String> {
+    let mut text = String::new();
+    match c {
+        0 => return Some("li t2, 0\n".to_string()),
+        1 => return Some(inst_rr("mv", "t2", "t0")),
+        -1 => return Some(inst_rr("neg", "t2", "t0")),
+        _ => {}
+    }
+    let ac = c.unsigned_abs();
+    let lo = ac.trailing_zeros();
+    let rest = ac - (1 << lo);
+    if rest == 0 {
+        // 2^a
+        text += &inst_rri("slli", "t2", "t0", lo as i64);
+    }else if rest.is_power_of_two() {
+        // 2^a + 2^b
+        let hi = rest.trailing_zeros();
+        let r0 = shifted("t1", hi, &mut text);
+        let r1 = shifted("t2", lo, &mut text);
+        text += &inst_rrr("add", "t2", r0, r1);
+    }else if (ac + (1 << lo)).is_power_of_two() {
+        // 2^a - 2^b
+        let hi = (ac + (1 << lo)).trailing_zeros();
+        let r0 = shifted("t1", hi, &mut text);
+        let r1 = shifted("t2", lo, &mut text);
+        text += &inst_rrr("sub", "t2", r0, r1);
+    }else {
+        return None;
+    }
+    if c < 0 {
+        text += &inst_rr("neg", "t2", "t2");
+    }
+    Some(text)
+}
+
+/* Magic number (multiplier, shift) for signed division, Hacker's Delight 10-1
+ * Only valid for 2 <= |d| < 2^31.
+ */
+fn magic(d: i32) -> (i32, u32) {
+    let two31: u32 = 0x8000_0000;
+    let ad = d.unsigned_abs();
+    let t = two31 + ((d as u32) >> 31);
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let mut q1 = two31 / anc;
+    let mut r1 = two31 - q1 * anc;
+    let mut q2 = two31 / ad;
+    let mut r2 = two31 - q2 * ad;
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
+        }
+    }
+    let m = q2.wrapping_add(1) as i32;
+    if d < 0 {
+        (m.wrapping_neg(), p - 32)
+    }else {
+        (m, p - 32)
+    }
+}
+
+/* t2 = t0 / 2^k, rounding toward zero */
+fn div_pow2(k: u32, text: &mut String) {
+    // Bias negative dividends by 2^k - 1
+    if k == 1 {
+        *text += &inst_rri("srli", "t1", "t0", 31);
+    }else {
+        *text += &inst_rri("srai", "t1", "t0", (k - 1) as i64);
+        *text += &inst_rri("srli", "t1", "t1", (32 - k) as i64);
+    }
+    *text += &inst_rrr("add", "t1", "t0", "t1");
+    *text += &inst_rri("srai", "t2", "t1", k as i64);
+}
+
+/* t2 = t0 / d for |d| >= 2, d != i32::MIN */
+fn div_magic(d: i32, text: &mut String) {
+    let (m, s) = magic(d);
+    *text += &("li t1, ".to_string() + &m.to_string() + "\n");
+    *text += &inst_rrr("mulh", "t2", "t0", "t1");
+    if d > 0 && m < 0 {
+        *text += &inst_rrr("add", "t2", "t2", "t0");
+    }else if d < 0 && m > 0 {
+        *text += &inst_rrr("sub", "t2", "t2", "t0");
+    }
+    if s > 0 {
+        *text += &inst_rri("srai", "t2", "t2", s as i64);
+    }
+    // Round toward zero: add one if the quotient is negative
+    *text += &inst_rri("srli", "t1", "t2", 31);
+    *text += &inst_rrr("add", "t2", "t2", "t1");
+}
+
+/* t2 = (t0 == i32::MIN), the quotient for d = i32::MIN */
+fn div_min(text: &mut String) {
+    *text += &("li t1, ".to_string() + &i32::MIN.to_string() + "\n");
+    *text += &inst_rrr("sub", "t2", "t0", "t1");
+    *text += &inst_rr("seqz", "t2", "t2");
+}
+
+fn reduce_div(d: i32) -> Option<String> {
+    let mut text = String::new();
+    match d {
+        0 => return None,
+        1 => text += &inst_rr("mv", "t2", "t0"),
+        -1 => text += &inst_rr("neg", "t2", "t0"),
+        i32::MIN => div_min(&mut text),
+        d if d.unsigned_abs().is_power_of_two() => {
+            div_pow2(d.unsigned_abs().trailing_zeros(), &mut text);
+            if d < 0 {
+                text += &inst_rr("neg", "t2", "t2");
+            }
+        }
+        d => div_magic(d, &mut text),
+    }
+    Some(text)
+}
+
+/* Remainder: t0 - (t0 / d) * d, sign follows the dividend */
+fn reduce_mod(d: i32) -> Option<String> {
+    let mut text = String::new();
+    match d {
+        0 => return None,
+        1 | -1 => text += "li t2, 0\n",
+        i32::MIN => {
+            div_min(&mut text);
+            text += &inst_rri("slli", "t2", "t2", 31);
+            text += &inst_rrr("sub", "t2", "t0", "t2");
+        }
+        d if d.unsigned_abs().is_power_of_two() => {
+            // Remainder only depends on |d|
+            let k = d.unsigned_abs().trailing_zeros();
+            div_pow2(k, &mut text);
+            text += &inst_rri("slli", "t2", "t2", k as i64);
+            text += &inst_rrr("sub", "t2", "t0", "t2");
+        }
+        d => {
+            div_magic(d, &mut text);
+            text += &("li t1, ".to_string() + &d.to_string() + "\n");
+            text += &inst_rrr("mul", "t2", "t2", "t1");
+            text += &inst_rrr("sub", "t2", "t0", "t2");
+        }
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Run a reduced sequence with `x` in t0 and return t2 */
+    fn run(text: &str, x: i32) -> i32 {
+        let mut regs = [x, 0x5555_5555, 0x2aaa_aaaa];
+        let reg = |name: &str| ["t0", "t1", "t2"].iter().position(|&r| r == name).expect(name);
+        for line in text.lines() {
+            let (op, args) = line.split_once(' ').unwrap();
+            let args: Vec<&str> = args.split(", ").collect();
+            let rd = reg(args[0]);
+            let src = |i: usize| regs[reg(args[i])];
+            let imm = |i: usize| args[i].parse::<i64>().unwrap();
+            regs[rd] = match op {
+                "li" => imm(1) as i32,
+                "mv" => src(1),
+                "neg" => src(1).wrapping_neg(),
+                "seqz" => (src(1) == 0) as i32,
+                "slli" => src(1).wrapping_shl(imm(2) as u32),
+                "srli" => ((src(1) as u32) >> imm(2)) as i32,
+                "srai" => src(1) >> imm(2),
+                "add" => src(1).wrapping_add(src(2)),
+                "sub" => src(1).wrapping_sub(src(2)),
+                "mul" => src(1).wrapping_mul(src(2)),
+                "mulh" => ((src(1) as i64 * src(2) as i64) >> 32) as i32,
+                _ => panic!("unexpected instruction `{}`", line),
+            };
+        }
+        regs[2]
+    }
+
+    fn constants() -> Vec<i32> {
+        let mut list = vec![0, 1, -1, 3, -3, 5, 6, 7, -7, 10, 12, 15, 641, -641, 1000, i32::MIN, i32::MAX, i32::MIN + 1];
+        for k in 1..31 {
+            list.push(1 << k);
+            list.push(-(1 << k));
+            list.push((1 << k) + 1);
+            list.push((1 << k) - 1);
+        }
+        list
+    }
+
+    fn dividends(c: i32) -> Vec<i32> {
+        let mut list = vec![i32::MIN, i32::MIN + 1, -2, -1, 0, 1, 2, i32::MAX - 1, i32::MAX];
+        // Around multiples of the constant, where rounding changes
+        for m in [1, 2, 3, 1000, i32::MAX / c.saturating_abs().max(1)] {
+            let base = c.wrapping_mul(m);
+            for delta in -1..=1 {
+                list.push(base.wrapping_add(delta));
+                list.push(base.wrapping_neg().wrapping_add(delta));
+            }
+        }
+        let mut seed = 0x1234_5678_u32 ^ c as u32;
+        for _ in 0..200 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            list.push(seed as i32);
+        }
+        list
+    }
+
+    fn check(op: BinaryOp, expect: fn(i32, i32) -> i32) {
+        for c in constants() {
+            let Some(text) = reduce_binary(op, c) else { continue };
+            for x in dividends(c) {
+                assert_eq!(run(&text, x), expect(x, c), "{:?} {} by {}:\n{}", op, x, c, text);
+            }
+        }
+    }
+
+    #[test]
+    fn mul_matches_wrapping_mul() {
+        check(BinaryOp::Mul, i32::wrapping_mul);
+    }
+
+    #[test]
+    fn div_matches_wrapping_div() {
+        check(BinaryOp::Div, i32::wrapping_div);
+    }
+
+    #[test]
+    fn mod_matches_wrapping_rem() {
+        check(BinaryOp::Mod, i32::wrapping_rem);
+    }
+
+    #[test]
+    fn zero_divisor_is_not_reduced() {
+        assert!(reduce_binary(BinaryOp::Div, 0).is_none());
+        assert!(reduce_binary(BinaryOp::Mod, 0).is_none());
+    }
+}
@@ -0,0 +1,398 @@
+/* Uses */
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use koopa::ir::{Function, Value};
+use crate::ast::Span;
+use crate::init::Init;
+use crate::ty::Ty;
+
+/* Index of a symbol in its table */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SymbolId(pub usize);
+
+/* Index of a scope in its table */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopeId(pub usize);
+
+/* What a name refers to */
+#[derive(Clone, Debug)]
+pub enum SymbolKind {
+    // `const`, with its value, a scalar has no dimensions
+    Const(Rc<Init<i32>>),
+    Var,
+    Param,
+    Func(Function),
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    // Type of the object, or the function type
+    pub ty: Ty,
+    // Array dimensions, the first is 0 for an array parameter
+    pub shape: Vec<usize>,
+    // Name in its definition
+    pub span: Span,
+    // Alloc holding the object, None for scalar consts and functions
+    pub value: Option<Value>,
+    pub scope: ScopeId,
+}
+
+impl Symbol {
+    pub fn new(name: &str, kind: SymbolKind, ty: Ty, span: Span) -> Symbol {
+        let shape = ty.shape();
+        Symbol { name: name.to_string(), kind, ty, shape, span, value: None, scope: ScopeId(0) }
+    }
+
+    pub fn with_value(mut self, value: Value) -> Symbol {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn is_const(&self) -> bool {
+        matches!(self.kind, SymbolKind::Const(_))
+    }
+
+    pub fn is_func(&self) -> bool {
+        matches!(self.kind, SymbolKind::Func(_))
+    }
+
+    /* Value of a scalar const */
+    pub fn const_value(&self) -> Option<i32> {
+        match &self.kind {
+            SymbolKind::Const(init) if init.dims.is_empty() => Some(init.elems.get(&0).copied().unwrap_or(0)),
+            _ => None,
+        }
+    }
+
+    /* Whether `value` holds a pointer to the array instead of the array itself */
+    pub fn is_pointer(&self) -> bool {
+        matches!(self.ty, Ty::Pointer(_))
+    }
+}
+
+struct Scope {
+    parent: Option<ScopeId>,
+    names: HashMap<String, SymbolId>,
+    // Nesting level, 0 for globals
+    depth: usize,
+    // Source the scope covers, (0, 0) for the global scope
+    span: Span,
+}
+
+/* Every symbol and scope of a program
+ * Scopes form a tree kept in an arena, entering and leaving one only moves
+ * the current scope, so symbols stay available for queries afterwards.
+ */
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    scopes: Vec<Scope>,
+    current: ScopeId,
+    // Span of each resolved use to its symbol
+    uses: HashMap<Span, SymbolId>,
+    // Koopa names taken in the function being lowered, and the next suffix per source name
+    local_names: HashSet<String>,
+    local_counts: HashMap<String, usize>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> SymbolTable {
+        SymbolTable::new()
+    }
+}
+
+impl SymbolTable {
+    /* Table with only the global scope */
+    pub fn new() -> SymbolTable {
+        let global = Scope { parent: None, names: HashMap::new(), depth: 0, span: (0, 0) };
+        SymbolTable {
+            symbols: vec![],
+            scopes: vec![global],
+            current: ScopeId(0),
+            uses: HashMap::new(),
+            local_names: HashSet::new(),
+            local_counts: HashMap::new(),
+        }
+    }
+
+    /* Enter a new scope nested in the current one */
+    pub fn push(&mut self, span: Span) -> ScopeId {
+        let depth = self.scopes[self.current.0].depth + 1;
+        self.scopes.push(Scope { parent: Some(self.current), names: HashMap::new(), depth, span });
+        self.current = ScopeId(self.scopes.len() - 1);
+        self.current
+    }
+
+    /* Leave the current scope */
+    pub fn pop(&mut self) {
+        self.current = self.scopes[self.current.0].parent.expect("pop of the global scope");
+    }
+
+    pub fn current(&self) -> ScopeId {
+        self.current
+    }
+
+    pub fn depth(&self) -> usize {
+        self.scopes[self.current.0].depth
+    }
+
+    /* Add a symbol to the current scope, or the one it clashes with there */
+    pub fn define(&mut self, mut symbol: Symbol) -> Result<SymbolId, SymbolId> {
+        if let Some(&old) = self.scopes[self.current.0].names.get(&symbol.name) {
+            return Err(old);
+        }
+        let id = SymbolId(self.symbols.len());
+        symbol.scope = self.current;
+        self.scopes[self.current.0].names.insert(symbol.name.clone(), id);
+        self.symbols.push(symbol);
+        Ok(id)
+    }
+
+    /* Start naming the locals of a function, its parameters and every global are taken */
+    pub fn begin_function(&mut self, params: &[String]) {
+        self.local_names = self.scopes[0].names.keys().chain(params).cloned().collect();
+        self.local_counts.clear();
+    }
+
+    /* Koopa name for a local of the current function, `@x_1`, `@x_2`, ... for `x` */
+    pub fn local_name(&mut self, name: &str) -> String {
+        let count = self.local_counts.entry(name.to_string()).or_insert(0);
+        loop {
+            *count += 1;
+            let local = name.to_string() + "_" + &count.to_string();
+            if self.local_names.insert(local.clone()) {
+                return "@".to_string() + &local;
+            }
+        }
+    }
+
+    /* Innermost symbol named `name` visible in the current scope */
+    pub fn lookup(&self, name: &str) -> Option<SymbolId> {
+        self.lookup_in(self.current, name)
+    }
+
+    pub fn lookup_in(&self, scope: ScopeId, name: &str) -> Option<SymbolId> {
+        let mut scope = Some(scope);
+        while let Some(id) = scope {
+            if let Some(&symbol) = self.scopes[id.0].names.get(name) {
+                return Some(symbol);
+            }
+            scope = self.scopes[id.0].parent;
+        }
+        None
+    }
+
+    /* Look up a use of `name` at `span`, remembering what it resolved to */
+    pub fn resolve(&mut self, name: &str, span: Span) -> Option<SymbolId> {
+        let id = self.lookup(name)?;
+        self.uses.insert(span, id);
+        Some(id)
+    }
+
+    pub fn get(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.0]
+    }
+
+    pub fn get_mut(&mut self, id: SymbolId) -> &mut Symbol {
+        &mut self.symbols[id.0]
+    }
+
+    /* Symbols visible in `scope`, innermost first, without the shadowed ones */
+    pub fn visible(&self, scope: ScopeId) -> Vec<SymbolId> {
+        self.visible_where(scope, |_| true)
+    }
+
+    /* Symbols that can be named at a source offset, those of the source only
+     * after their definition: until then an outer symbol of the same name is
+     * not shadowed
+     */
+    pub fn visible_at(&self, offset: usize) -> Vec<SymbolId> {
+        self.visible_where(self.scope_at(offset), |symbol| symbol.span == (0, 0) || symbol.span.0 < offset)
+    }
+
+    fn visible_where(&self, scope: ScopeId, declared: impl Fn(&Symbol) -> bool) -> Vec<SymbolId> {
+        let mut seen = HashMap::new();
+        let mut list = vec![];
+        let mut scope = Some(scope);
+        while let Some(id) = scope {
+            let mut names: Vec<_> = self.scopes[id.0].names.iter().collect();
+            names.sort_by_key(|(_, &symbol)| symbol);
+            for (name, &symbol) in names {
+                if !declared(&self.symbols[symbol.0]) {
+                    continue;
+                }
+                if seen.insert(name.clone(), symbol).is_none() {
+                    list.push(symbol);
+                }
+            }
+            scope = self.scopes[id.0].parent;
+        }
+        list
+    }
+
+    /* Innermost scope covering the source offset */
+    pub fn scope_at(&self, offset: usize) -> ScopeId {
+        let mut best = ScopeId(0);
+        for (i, scope) in self.scopes.iter().enumerate().skip(1) {
+            let (start, end) = scope.span;
+            if start <= offset && offset < end && scope.depth > self.scopes[best.0].depth {
+                best = ScopeId(i);
+            }
+        }
+        best
+    }
+
+    /* Every symbol, in definition order */
+    pub fn symbols(&self) -> impl Iterator<Item = (SymbolId, &Symbol)> {
+        self.symbols.iter().enumerate().map(|(i, symbol)| (SymbolId(i), symbol))
+    }
+
+    /* Every resolved use, as (span of the use, symbol) */
+    pub fn uses(&self) -> impl Iterator<Item = (Span, SymbolId)> + '_ {
+        self.uses.iter().map(|(&span, &id)| (span, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_names_are_unique_in_a_function() {
+        let mut symbols = SymbolTable::new();
+        symbols.define(Symbol::new("x_1", SymbolKind::Var, Ty::Int, (0, 3))).unwrap();
+        symbols.begin_function(&["x".to_string(), "x_2".to_string()]);
+        // Sibling blocks at the same depth
+        let names: Vec<String> = (0..3).map(|_| symbols.local_name("x")).collect();
+        assert_eq!(names, vec!["@x_3", "@x_4", "@x_5"]);
+        assert_eq!(symbols.local_name("x_2"), "@x_2_1");
+
+        // Counting starts over in the next function
+        symbols.begin_function(&[]);
+        assert_eq!(symbols.local_name("x"), "@x_2");
+    }
+
+    /* Symbol table of a source that lowers without errors */
+    fn table(source: &str) -> SymbolTable {
+        let analysis = crate::analyze(source, &Default::default());
+        assert!(!analysis.diagnostics.has_errors(), "{:?}", analysis.diagnostics.list);
+        analysis.symbols.unwrap()
+    }
+
+    /* Offset of the `n`th occurrence of `text` */
+    fn nth(source: &str, text: &str, n: usize) -> usize {
+        source.match_indices(text).nth(n).unwrap().0
+    }
+
+    #[test]
+    fn inner_definitions_shadow_outer_ones() {
+        let source = "int x;\nint main() {\n  int x = 1;\n  {\n    int x = 2;\n    x = x + 1;\n  }\n  return x;\n}\n";
+        let symbols = table(source);
+        let defs: Vec<(SymbolId, usize)> = symbols.symbols().filter(|(_, s)| s.name == "x").map(|(id, s)| (id, s.span.0)).collect();
+        assert_eq!(defs.iter().map(|d| d.1).collect::<Vec<_>>(), vec![nth(source, "x", 0), nth(source, "x", 1), nth(source, "x", 2)]);
+        let scopes: HashSet<ScopeId> = defs.iter().map(|d| symbols.get(d.0).scope).collect();
+        assert_eq!(scopes.len(), 3);
+
+        let resolved = |offset: usize| symbols.uses().find(|(span, _)| span.0 == offset).unwrap().1;
+        // Both uses in the inner block see the inner x, the return sees the middle one
+        assert_eq!(resolved(nth(source, "x", 3)), defs[2].0);
+        assert_eq!(resolved(nth(source, "x", 4)), defs[2].0);
+        assert_eq!(resolved(nth(source, "x", 5)), defs[1].0);
+    }
+
+    #[test]
+    fn visible_symbols_by_position() {
+        let source = "int g;\nint f(int a) {\n  int b;\n  {\n    int a;\n    int c;\n  }\n  return a;\n}\nint main() { return f(g); }\n";
+        let symbols = table(source);
+        let names = |offset: usize| {
+            let mut names: Vec<String> = symbols.visible(symbols.scope_at(offset)).into_iter().map(|id| symbols.get(id).name.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(symbols.scope_at(0), ScopeId(0));
+        let global = names(0);
+        assert!(["f", "g", "main", "putint"].iter().all(|name| global.contains(&name.to_string())));
+        assert!(!global.contains(&"a".to_string()) && !global.contains(&"b".to_string()));
+        let inner = nth(source, "int c", 0);
+        let visible = names(inner);
+        assert!(visible.contains(&"c".to_string()) && visible.contains(&"b".to_string()));
+        // The inner a hides the parameter
+        let a = symbols.visible(symbols.scope_at(inner)).into_iter().find(|&id| symbols.get(id).name == "a").unwrap();
+        assert!(matches!(symbols.get(a).kind, SymbolKind::Var));
+        let ret = nth(source, "return a", 0);
+        assert!(!names(ret).contains(&"c".to_string()));
+        let a = symbols.lookup_in(symbols.scope_at(ret), "a").unwrap();
+        assert!(matches!(symbols.get(a).kind, SymbolKind::Param));
+        assert!(symbols.get(symbols.lookup_in(symbols.scope_at(ret), "b").unwrap()).scope.0 > 0);
+    }
+
+    #[test]
+    fn names_are_declared_before_use() {
+        let error = |source: &str| {
+            let analysis = crate::analyze(source, &Default::default());
+            analysis.diagnostics.list.into_iter().find(|d| d.severity == crate::Severity::Error).map(|d| d.message)
+        };
+        assert_eq!(error("int f() { return g; }\nint g;\nint main() { return f(); }").as_deref(), Some("undefined variable `g`"));
+        assert_eq!(error("int f() { return h(); }\nint h() { return 1; }\nint main() { return f(); }").as_deref(), Some("undefined function `h`"));
+        assert_eq!(error("int main() { int y = z; int z = 1; return y; }").as_deref(), Some("undefined variable `z`"));
+        // A function can call itself, and later ones can use earlier globals
+        assert_eq!(error("int g;\nint f(int n) { if (n) return f(n - 1); return g; }\nint main() { return f(2); }"), None);
+    }
+
+    #[test]
+    fn kinds_of_each_definition() {
+        let source = "const int N = 2, M[2] = {N, 5};\nint g = N, ga[N][3];\nint f(int p, int q[][3]) {\n  int v = p;\n  int l[2];\n  return v + q[0][0] + l[0];\n}\nint main() { return f(g, ga); }\n";
+        let symbols = table(source);
+        let find = |name: &str| symbols.symbols().map(|(_, symbol)| symbol).find(|symbol| symbol.name == name).unwrap();
+
+        let n = find("N");
+        assert!(matches!(n.kind, SymbolKind::Const(_)) && n.is_const());
+        assert_eq!((n.const_value(), n.ty.to_string(), n.value.is_none()), (Some(2), "const int".to_string(), true));
+        let m = find("M");
+        assert!(matches!(&m.kind, SymbolKind::Const(init) if init.elems.values().copied().collect::<Vec<_>>() == vec![2, 5]));
+        assert_eq!((m.const_value(), m.shape.clone(), m.value.is_some()), (None, vec![2], true));
+
+        // Mutable globals are variables, never folded
+        let g = find("g");
+        assert!(matches!(g.kind, SymbolKind::Var) && !g.is_const());
+        assert_eq!((g.const_value(), g.ty.to_string(), g.value.is_some()), (None, "int".to_string(), true));
+        let ga = find("ga");
+        assert!(matches!(ga.kind, SymbolKind::Var));
+        assert_eq!((ga.shape.clone(), ga.is_pointer()), (vec![2, 3], false));
+
+        let p = find("p");
+        assert!(matches!(p.kind, SymbolKind::Param));
+        assert_eq!((p.ty.to_string(), p.shape.clone()), ("int".to_string(), vec![]));
+        let q = find("q");
+        assert!(matches!(q.kind, SymbolKind::Param) && q.is_pointer());
+        assert_eq!((q.ty.to_string(), q.shape.clone()), ("int[][3]".to_string(), vec![0, 3]));
+
+        for (name, shape) in [("v", vec![]), ("l", vec![2])] {
+            let local = find(name);
+            assert!(matches!(local.kind, SymbolKind::Var) && local.scope.0 > 0);
+            assert_eq!((local.shape.clone(), local.value.is_some()), (shape, true));
+        }
+
+        let f = find("f");
+        assert!(matches!(f.kind, SymbolKind::Func(_)) && f.is_func() && !f.is_const());
+        assert_eq!((f.ty.to_string(), f.value.is_none()), ("int(int, int[][3])".to_string(), true));
+        let getint = find("getint");
+        assert!(getint.is_func());
+        assert_eq!(getint.span, (0, 0));
+    }
+
+    #[test]
+    fn only_consts_are_folded() {
+        let errors = |source: &str| crate::analyze(source, &Default::default()).diagnostics.list.into_iter().map(|d| d.message).collect::<Vec<_>>();
+        assert_eq!(errors("int n = 3;\nint a[n];\n"), vec!["`n` is not a constant".to_string()]);
+        assert_eq!(errors("int f(int p) {\n  const int c = p;\n  return c;\n}\n"), vec!["`p` is not a constant".to_string()]);
+        assert!(errors("const int n = 3;\nint a[n];\nint main() { return a[0]; }\n").is_empty());
+
+        // The loop reads the global every time, the const is a literal
+        let program = crate::lower(&crate::parse("const int N = 3;\nint n = 3;\nint main() {\n  while (n) n = n - 1;\n  return n + N;\n}\n").unwrap()).ok().unwrap();
+        let text = crate::emit_koopa(&program);
+        assert_eq!(text.matches("load @n").count(), 3);
+        assert!(text.contains("add %3, 3"));
+    }
+}
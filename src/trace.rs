@@ -0,0 +1,165 @@
+/* Uses */
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/* Part of the compiler a trace message comes from */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    // Parser
+    Parse,
+    // Ast to koopa lowering
+    Lower,
+    // Initializer normalization
+    Init,
+    // Koopa optimization passes
+    Opt,
+    // Stack frame layout
+    Frame,
+    // Instruction selection
+    Isel,
+    // Language server messages
+    Lsp,
+}
+
+pub const CATEGORIES: [Category; 7] = [Category::Parse, Category::Lower, Category::Init, Category::Opt, Category::Frame, Category::Isel, Category::Lsp];
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Parse => "parse",
+            Category::Lower => "lower",
+            Category::Init => "init",
+            Category::Opt => "opt",
+            Category::Frame => "frame",
+            Category::Isel => "isel",
+            Category::Lsp => "lsp",
+        }
+    }
+}
+
+/* Amount of detail, each level includes the ones before it */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info = 1,
+    Debug = 2,
+    Trace = 3,
+}
+
+impl Level {
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+/* Most detailed level enabled per category, 0 when off */
+static LEVELS: [AtomicU8; 7] = [AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0)];
+
+/* Environment variable read by `configure_from_env` */
+pub const ENV_VAR: &str = "SYSY_TRACE";
+
+/* Enable categories from a spec like `lower,frame:debug` or `all:info`
+ * A category without a level gets every message. Nothing changes if the
+ * spec has an error.
+ */
+pub fn configure(spec: &str) -> Result<(), String> {
+    for (category, level) in parse_spec(spec)? {
+        LEVELS[category as usize].store(level, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/* Level to set per category, in the order they are given */
+fn parse_spec(spec: &str) -> Result<Vec<(Category, u8)>, String> {
+    let mut levels = vec![];
+    for item in spec.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (name, level) = match item.split_once(':') {
+            Some((name, level)) => (name, level),
+            None => (item, "trace"),
+        };
+        let level = match level {
+            "off" => 0,
+            "info" => Level::Info as u8,
+            "debug" => Level::Debug as u8,
+            "trace" => Level::Trace as u8,
+            _ => return Err("unknown trace level `".to_string() + level + "`"),
+        };
+        if name == "all" {
+            levels.extend(CATEGORIES.iter().map(|&category| (category, level)));
+        }else {
+            match CATEGORIES.iter().find(|c| c.name() == name) {
+                Some(&category) => levels.push((category, level)),
+                None => return Err("unknown trace category `".to_string() + name + "`"),
+            }
+        }
+    }
+    Ok(levels)
+}
+
+/* Enable categories from the environment, if it is set */
+pub fn configure_from_env() -> Result<(), String> {
+    match std::env::var(ENV_VAR) {
+        Ok(spec) => configure(&spec),
+        Err(_) => Ok(()),
+    }
+}
+
+pub fn enabled(category: Category, level: Level) -> bool {
+    LEVELS[category as usize].load(Ordering::Relaxed) >= level as u8
+}
+
+/* Write one message to stderr, use the `trace!` macro instead */
+pub fn write(category: Category, level: Level, message: &str) {
+    eprintln!("[{}:{}] {}", category.name(), level.name(), message);
+}
+
+/* trace!(Category, Level, "format", args...) */
+#[macro_export]
+macro_rules! trace {
+    ($category:ident, $level:ident, $($arg:tt)*) => {
+        if $crate::trace::enabled($crate::trace::Category::$category, $crate::trace::Level::$level) {
+            $crate::trace::write($crate::trace::Category::$category, $crate::trace::Level::$level, &format!($($arg)*));
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_items_and_levels() {
+        use Category::*;
+        let (t, d, i) = (Level::Trace as u8, Level::Debug as u8, Level::Info as u8);
+        assert_eq!(parse_spec("lower"), Ok(vec![(Lower, t)]));
+        assert_eq!(parse_spec(" frame:debug , isel:info,"), Ok(vec![(Frame, d), (Isel, i)]));
+        assert_eq!(parse_spec(""), Ok(vec![]));
+        // Later items win, so `all` can be narrowed
+        let levels = parse_spec("all:info,opt:off").unwrap();
+        assert_eq!(levels.len(), CATEGORIES.len() + 1);
+        assert!(levels[..CATEGORIES.len()].iter().all(|&(_, level)| level == i));
+        assert_eq!(levels.last(), Some(&(Opt, 0)));
+
+        assert_eq!(parse_spec("lower,frames"), Err("unknown trace category `frames`".to_string()));
+        assert_eq!(parse_spec("lower:loud"), Err("unknown trace level `loud`".to_string()));
+        assert_eq!(parse_spec("Lower"), Err("unknown trace category `Lower`".to_string()));
+    }
+
+    #[test]
+    fn one_category_leaves_the_others_off() {
+        // The only test that changes the levels, others may trace while it runs
+        configure("frame:debug").unwrap();
+        assert!(enabled(Category::Frame, Level::Info) && enabled(Category::Frame, Level::Debug));
+        assert!(!enabled(Category::Frame, Level::Trace));
+        for category in CATEGORIES.into_iter().filter(|&c| c != Category::Frame) {
+            assert!(!enabled(category, Level::Info), "{:?}", category);
+        }
+        // A bad spec changes nothing, not even the items before the error
+        assert!(configure("lower,bogus").is_err());
+        assert!(!enabled(Category::Lower, Level::Info));
+        configure("all:off").unwrap();
+        assert!(CATEGORIES.iter().all(|&c| !enabled(c, Level::Info)));
+    }
+}
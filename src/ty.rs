@@ -0,0 +1,183 @@
+/* Uses */
+use std::fmt;
+use koopa::ir::Type;
+
+/* Type of a SysY object, expression or function */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ty {
+    Int,
+    Void,
+    // Element type and length
+    Array(Box<Ty>, usize),
+    // Array parameter, decayed to a pointer to its first element
+    Pointer(Box<Ty>),
+    // Parameter types and return type
+    Func(Vec<Ty>, Box<Ty>),
+    // Object that can't be assigned, only ever outermost
+    Const(Box<Ty>),
+}
+
+impl Ty {
+    /* Array of `base` with the dimensions `dims`, outermost first */
+    pub fn array(base: Ty, dims: &[usize]) -> Ty {
+        dims.iter().rev().fold(base, |ty, &len| Ty::Array(Box::new(ty), len))
+    }
+
+    pub fn constant(self) -> Ty {
+        match self {
+            Ty::Const(_) => self,
+            ty => Ty::Const(Box::new(ty)),
+        }
+    }
+
+    /* The type without const */
+    pub fn unqualified(&self) -> &Ty {
+        match self {
+            Ty::Const(ty) => ty,
+            ty => ty,
+        }
+    }
+
+    pub fn is_const(&self) -> bool {
+        matches!(self, Ty::Const(_))
+    }
+
+    pub fn is_int(&self) -> bool {
+        *self.unqualified() == Ty::Int
+    }
+
+    /* Type after one subscript, None when it can't be indexed */
+    pub fn index(&self) -> Option<Ty> {
+        match self {
+            Ty::Const(ty) => ty.index().map(Ty::constant),
+            Ty::Array(elem, _) | Ty::Pointer(elem) => Some((**elem).clone()),
+            _ => None,
+        }
+    }
+
+    /* Array dimensions, the first is 0 for a decayed pointer */
+    pub fn shape(&self) -> Vec<usize> {
+        match self.unqualified() {
+            Ty::Array(elem, len) => [vec![*len], elem.shape()].concat(),
+            Ty::Pointer(elem) => [vec![0], elem.shape()].concat(),
+            _ => vec![],
+        }
+    }
+
+    /* Whether an argument of type `arg` can be passed for a parameter of this type
+     * Arrays decay to a pointer to their first element, const is dropped since
+     * SysY has no way to spell a pointer to const.
+     */
+    pub fn accepts(&self, arg: &Ty) -> bool {
+        match (self.unqualified(), arg.unqualified()) {
+            (Ty::Pointer(elem), Ty::Array(arg_elem, _) | Ty::Pointer(arg_elem)) => elem == arg_elem,
+            (param, arg) => param == arg,
+        }
+    }
+
+    /* Element type of an array, the type itself otherwise */
+    fn base(&self) -> &Ty {
+        match self {
+            Ty::Array(elem, _) | Ty::Pointer(elem) => elem.base(),
+            ty => ty,
+        }
+    }
+
+    /* Dimensions as written after a name, `[]` for a decayed pointer */
+    fn brackets(&self) -> String {
+        self.shape().iter().map(|&len| if len == 0 { "[]".to_string() } else { "[".to_string() + &len.to_string() + "]" }).collect()
+    }
+
+    /* Declaration of `name` with this type, like `const int a[2][3]` or `int f(int[], int)` */
+    pub fn declare(&self, name: &str) -> String {
+        match self {
+            Ty::Const(ty) => "const ".to_string() + &ty.declare(name),
+            Ty::Func(params, ret) => {
+                let params: Vec<_> = params.iter().map(Ty::to_string).collect();
+                ret.to_string() + " " + name + "(" + &params.join(", ") + ")"
+            }
+            ty => ty.base().to_string() + " " + name + &ty.brackets(),
+        }
+    }
+
+    pub fn to_koopa(&self) -> Type {
+        match self {
+            Ty::Int => Type::get_i32(),
+            Ty::Void => Type::get_unit(),
+            Ty::Array(elem, len) => Type::get_array(elem.to_koopa(), *len),
+            Ty::Pointer(elem) => Type::get_pointer(elem.to_koopa()),
+            Ty::Func(params, ret) => Type::get_function(params.iter().map(Ty::to_koopa).collect(), ret.to_koopa()),
+            Ty::Const(ty) => ty.to_koopa(),
+        }
+    }
+}
+
+/* Types as written in SysY, like `const int[2][3]`, `int[][3]` or `int(int, int[])` */
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ty::Int => write!(f, "int"),
+            Ty::Void => write!(f, "void"),
+            Ty::Const(ty) => write!(f, "const {}", ty),
+            Ty::Func(params, ret) => {
+                let params: Vec<_> = params.iter().map(Ty::to_string).collect();
+                write!(f, "{}({})", ret, params.join(", "))
+            }
+            Ty::Array(..) | Ty::Pointer(_) => write!(f, "{}{}", self.base(), self.brackets()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Message of the error lowering `source` stops at, with the text it points at */
+    fn error(source: &str) -> (String, &str) {
+        let found = match crate::lower(&crate::parse(source).unwrap()) {
+            Ok(_) => panic!("no error in {}", source),
+            Err(found) => found.list.into_iter().next().unwrap(),
+        };
+        let (start, end) = found.span.unwrap();
+        (found.message, &source[start..end])
+    }
+
+    #[test]
+    fn array_parameters_decay_one_dimension() {
+        let param = Ty::Pointer(Box::new(Ty::array(Ty::Int, &[4])));
+        assert_eq!(param.to_string(), "int[][4]");
+        assert!(param.accepts(&Ty::array(Ty::Int, &[3, 4])));
+        assert!(param.accepts(&Ty::array(Ty::Int, &[3, 4]).constant()));
+        assert!(!param.accepts(&Ty::array(Ty::Int, &[4, 3])));
+        assert!(!param.accepts(&Ty::array(Ty::Int, &[4])));
+        assert!(!param.accepts(&Ty::Int));
+        assert_eq!(Ty::array(Ty::Int, &[2, 3]).index(), Some(Ty::array(Ty::Int, &[3])));
+    }
+
+    #[test]
+    fn dimension_mismatch() {
+        assert_eq!(error("int f(int p[]) { return 0; }\nint main() { int a[3][4]; return f(a); }"), ("mismatched types: expected `int[]`, found `int[3][4]`".to_string(), "a"));
+        assert_eq!(error("int f(int p[][3]) { return 0; }\nint main() { int a[3][4]; return f(a); }"), ("mismatched types: expected `int[][3]`, found `int[3][4]`".to_string(), "a"));
+    }
+
+    #[test]
+    fn scalar_for_an_array() {
+        assert_eq!(error("int f(int p[]) { return p[0]; }\nint main() { int x = 1; return f(x); }"), ("mismatched types: expected `int[]`, found `int`".to_string(), "x"));
+        assert_eq!(error("int main() { int a[2]; putint(a); return 0; }"), ("mismatched types: expected `int`, found `int[2]`".to_string(), "a"));
+    }
+
+    #[test]
+    fn void_call_as_a_value() {
+        assert_eq!(error("void g() {}\nint main() { return g() + 1; }"), ("mismatched types: expected `int`, found `void`".to_string(), "g()"));
+        assert_eq!(error("void g() {}\nint main() { int x = g(); return x; }"), ("mismatched types: expected `int`, found `void`".to_string(), "g()"));
+    }
+
+    #[test]
+    fn array_sizes_must_fit() {
+        assert_eq!(error("int a[65536][65536][65536];\nint main() { return 0; }"), ("array is too large".to_string(), "65536"));
+        assert_eq!(error("int main() { int a[0]; return 0; }"), ("array size must be positive".to_string(), "0"));
+        // The editor gets the same error instead of a panic
+        let analysis = crate::analyze("int main() { int a[65536][65536]; return 0; }", &Default::default());
+        assert_eq!(analysis.diagnostics.list[0].message, "array is too large");
+    }
+}